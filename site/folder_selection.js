@@ -27,7 +27,7 @@ const rust = import('./pkg');
 //     // await invoke("load_spotify_data");
 //     window.location.assign("dashboard.html");
 //   } catch (err) {
-//     document.getElementById("folder-selection-general-status-msg").innerHTML = "There was an error while trying to load the folder. Make sure you selected an unzipped copy of the zip file provided by Spotify. Please try again.";
+//     document.getElementById("folder-selection-general-status-msg").innerHTML = "There was an error while trying to load the folder. Make sure you selected the zip file provided by Spotify, or an unzipped copy of it. Please try again.";
 //     document.getElementById("folder-selection-extended-status-msg").innerHTML = err
 //   }
 // }
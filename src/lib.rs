@@ -1,3 +1,82 @@
+// The plays are loaded by the same modules as the desktop app's. Only some of what they have is used
+// here, e.g. there are no files to read plays from.
+#[path = "old/cache.rs"]
+#[allow(dead_code)]
+mod cache;
+#[path = "old/country.rs"]
+#[allow(dead_code)]
+mod country;
+#[path = "old/device.rs"]
+#[allow(dead_code)]
+mod device;
+#[path = "old/group.rs"]
+#[allow(dead_code)]
+mod group;
+#[path = "old/play_reader.rs"]
+#[allow(dead_code)]
+mod play_reader;
+#[path = "old/play_store.rs"]
+#[allow(dead_code)]
+mod play_store;
+#[path = "old/plays.rs"]
+#[allow(dead_code)]
+mod plays;
+#[path = "old/privacy.rs"]
+#[allow(dead_code)]
+mod privacy;
+#[path = "old/sort.rs"]
+#[allow(dead_code)]
+mod sort;
+#[path = "old/util.rs"]
+#[allow(dead_code)]
+mod util;
+
+// The desktop app's other modules aren't used here, but are compiled for their tests
+#[cfg(test)]
+#[path = "old/apple_music.rs"]
+#[allow(dead_code)]
+mod apple_music;
+#[cfg(test)]
+#[path = "old/arrow_export.rs"]
+#[allow(dead_code)]
+mod arrow_export;
+#[cfg(test)]
+#[path = "old/db.rs"]
+#[allow(dead_code)]
+mod db;
+#[cfg(test)]
+#[path = "old/dedup.rs"]
+#[allow(dead_code)]
+mod dedup;
+#[cfg(test)]
+#[path = "old/filter.rs"]
+#[allow(dead_code)]
+mod filter;
+#[cfg(test)]
+#[path = "old/group_tree.rs"]
+#[allow(dead_code)]
+mod group_tree;
+#[cfg(test)]
+#[path = "old/lastfm.rs"]
+#[allow(dead_code)]
+mod lastfm;
+#[cfg(test)]
+#[path = "old/library.rs"]
+#[allow(dead_code)]
+mod library;
+#[cfg(test)]
+#[path = "old/listenbrainz.rs"]
+#[allow(dead_code)]
+mod listenbrainz;
+#[cfg(test)]
+#[path = "old/pivot.rs"]
+#[allow(dead_code)]
+mod pivot;
+
+use group::GroupOptions;
+use play_store::PlayStore;
+use plays::{IngestionReport, LoadOptions, LoadProgress};
+use sort::SortSpotifyDataBy;
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
@@ -23,3 +102,125 @@ pub fn rust_init() {
 pub fn greet(name: &str) {
     alert(&format!("Hello! {}!", name));
 }
+
+/// Plays loaded in the browser. They are kept on the Rust side in a PlayStore, so that they don't have to
+/// be copied into JS to be worked with and take up as little of the browser's memory as they can.
+#[wasm_bindgen]
+pub struct LoadedPlays {
+    play_store: PlayStore,
+    ingestion_report: IngestionReport,
+    /// The hash of the zip file that the plays were loaded from, which their cache is written with
+    source_hash: String,
+}
+
+#[wasm_bindgen]
+impl LoadedPlays {
+    #[wasm_bindgen(getter)]
+    pub fn play_count(&self) -> usize {
+        self.play_store.len()
+    }
+
+    /// What happened while loading the plays (e.g. files or records that couldn't be read), as JSON
+    pub fn get_ingestion_report_json(&self) -> Result<String, JsError> {
+        Ok(serde_json::to_string(&self.ingestion_report)?)
+    }
+
+    /// Writes the plays into the bytes of a cache, which can be stored (e.g. in IndexedDB) and passed to
+    /// `load_plays_from_cache` to load the plays again without parsing the zip file
+    pub fn get_cache_bytes(&self) -> Result<Vec<u8>, JsError> {
+        // The cache holds PlayItems, so the plays are only copied out of the store while it's written
+        let play_items: Vec<_> = self.play_store.iter_play_items().collect();
+
+        cache::get_cache_bytes(&play_items, &self.ingestion_report, &self.source_hash)
+            .map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    /// Groups all of the plays, the same as the desktop app does, and returns the groups from the most
+    /// played down as JSON. `group_by` is the name of a way of grouping, e.g. "artists" or "every_7_days".
+    pub fn get_grouped_data_json(
+        &self,
+        group_by: &str,
+        merge_same_name_releases: bool,
+        utc_offset_minutes: i32,
+    ) -> Result<String, JsError> {
+        let Some(group_by) = group::get_group_by(group_by) else {
+            return Err(JsError::new("Invalid group string passed."));
+        };
+        if utc_offset_minutes.abs() >= 24 * 60 {
+            return Err(JsError::new("Invalid UTC offset passed."));
+        }
+        let options = GroupOptions {
+            merge_same_name_releases,
+            utc_offset_minutes,
+        };
+
+        let play_indices: Vec<usize> = (0..self.play_store.len()).collect();
+        let mut grouped_data = group::get_grouped_data_from_store(
+            &group_by,
+            &options,
+            &self.play_store,
+            &play_indices,
+        );
+        sort::sort_grouped_data(&mut grouped_data, SortSpotifyDataBy::PlayCount, true);
+
+        Ok(serde_json::to_string(&grouped_data)?)
+    }
+}
+
+/// Calls a JS progress callback. Progress is only shown to the user, so a callback that fails doesn't stop
+/// the plays from loading.
+fn send_progress(on_progress: &js_sys::Function, progress: &LoadProgress) {
+    let Ok(progress_json) = serde_json::to_string(progress) else {
+        return;
+    };
+
+    if let Ok(progress) = js_sys::JSON::parse(&progress_json) {
+        let _ = on_progress.call1(&JsValue::NULL, &progress);
+    }
+}
+
+/// Loads plays from the bytes of the zip archive that Spotify sends, e.g. from a file the user picked.
+/// There is no filesystem in the browser, so the archive is read in memory.
+///
+/// `on_progress` is called with a `LoadProgress` object (files done, bytes read, records parsed and the
+/// totals) as the streaming history files are read, so that it can be shown to the user.
+#[wasm_bindgen]
+pub fn load_plays_from_zip(
+    zip_bytes: &[u8],
+    zip_name: &str,
+    on_progress: Option<js_sys::Function>,
+) -> Result<LoadedPlays, JsError> {
+    let load_options = LoadOptions::default();
+    let source_hash = cache::get_source_hash_from_zip_bytes(zip_bytes, &load_options);
+    let (play_items, ingestion_report) =
+        plays::extract_plays_from_zip_bytes(zip_bytes, zip_name, &load_options, |progress| {
+            if let Some(on_progress) = &on_progress {
+                send_progress(on_progress, progress);
+            }
+        })
+        .map_err(|e| JsError::new(&e.to_string()))?;
+
+    Ok(LoadedPlays {
+        play_store: PlayStore::from_play_items(&play_items),
+        ingestion_report,
+        source_hash,
+    })
+}
+
+/// Loads plays from the bytes of a cache made by `LoadedPlays.get_cache_bytes`. The zip file that the plays
+/// were loaded from is needed to check that the cache is still up to date with it; if it isn't (or the
+/// cache is from another version of dio), this fails and the plays have to be loaded from the zip file.
+#[wasm_bindgen]
+pub fn load_plays_from_cache(cache_bytes: &[u8], zip_bytes: &[u8]) -> Result<LoadedPlays, JsError> {
+    let load_options = LoadOptions::default();
+    let source_hash = cache::get_source_hash_from_zip_bytes(zip_bytes, &load_options);
+    let (play_items, ingestion_report) =
+        cache::get_plays_from_cache_bytes(cache_bytes, &source_hash, &load_options)
+            .map_err(|e| JsError::new(&e.to_string()))?;
+
+    Ok(LoadedPlays {
+        play_store: PlayStore::from_play_items(&play_items),
+        ingestion_report,
+        source_hash,
+    })
+}
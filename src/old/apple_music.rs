@@ -0,0 +1,304 @@
+use crate::plays::{
+    self, FileIngestionReport, IngestionReport, LoadOptions, ParseMode, PlayItem, PlayItemKind,
+    PlayItemSource, ReasonEnd,
+};
+use chrono::{prelude::*, Duration};
+use eyre::{eyre, Result};
+use std::{fs, io::Read, path::PathBuf, sync::Arc};
+
+/// Which column of `Apple Music Play Activity.csv` holds each field. Apple has added, removed and renamed
+/// columns between exports, so they are found by their header and most of them are optional.
+struct PlayActivityColumns {
+    song_name: usize,
+    album_name: Option<usize>,
+    artist_name: Option<usize>,
+    event_start_timestamp: usize,
+    event_end_timestamp: Option<usize>,
+    event_type: Option<usize>,
+    play_duration_ms: usize,
+    end_reason_type: Option<usize>,
+    media_type: Option<usize>,
+    offline: Option<usize>,
+    client_ip_address: Option<usize>,
+}
+
+impl PlayActivityColumns {
+    fn from_header(header: &csv::StringRecord) -> std::result::Result<Self, String> {
+        let find_column = |names: &[&str]| {
+            header
+                .iter()
+                .position(|column_name| names.contains(&column_name.trim()))
+        };
+        let find_required_column = |names: &[&str]| {
+            find_column(names).ok_or_else(|| {
+                format!(
+                    "Expected an Apple Music Play Activity file, but there is no \"{}\" column.",
+                    names[0]
+                )
+            })
+        };
+
+        Ok(PlayActivityColumns {
+            song_name: find_required_column(&["Song Name", "Content Name"])?,
+            album_name: find_column(&["Container Album Name", "Album Name"]),
+            artist_name: find_column(&["Artist Name", "Container Artist Name"]),
+            event_start_timestamp: find_required_column(&["Event Start Timestamp"])?,
+            event_end_timestamp: find_column(&["Event End Timestamp"]),
+            event_type: find_column(&["Event Type"]),
+            play_duration_ms: find_required_column(&["Play Duration Milliseconds"])?,
+            end_reason_type: find_column(&["End Reason Type"]),
+            media_type: find_column(&["Media Type"]),
+            offline: find_column(&["Offline"]),
+            client_ip_address: find_column(&["Client IP Address"]),
+        })
+    }
+}
+
+/// Maps Apple's `End Reason Type` onto why the play ended and whether it was skipped, in the same terms
+/// as the Spotify streaming history
+fn get_reason_end_and_skipped(end_reason_type: &str) -> (Option<ReasonEnd>, Option<bool>) {
+    match end_reason_type {
+        "NATURAL_END_OF_TRACK" => (Some(ReasonEnd::TrackDone), Some(false)),
+        "TRACK_SKIPPED_FORWARDS" => (Some(ReasonEnd::FwdBtn), Some(true)),
+        "TRACK_SKIPPED_BACKWARDS" => (Some(ReasonEnd::BackBtn), Some(true)),
+        "MANUALLY_SELECTED_PLAYBACK_OF_A_DIFF_ITEM" => (Some(ReasonEnd::ClickRow), Some(true)),
+        "PLAYBACK_MANUALLY_PAUSED" | "PLAYBACK_STOPPED_DUE_TO_SESSION_TIMEOUT" => {
+            (Some(ReasonEnd::EndPlay), Some(false))
+        }
+        "FAILED_TO_LOAD" => (Some(ReasonEnd::TrackError), Some(false)),
+        "EXITED_APPLICATION" => (Some(ReasonEnd::UnexpectedExit), Some(false)),
+        "" | "NOT_APPLICABLE" => (None, None),
+        _ => (Some(ReasonEnd::Other(end_reason_type.to_lowercase())), None),
+    }
+}
+
+/// Reads the plays in an Apple Music Play Activity file. Only rows for the end of a play are plays, and
+/// rows for other events (like the start of a play, or lyrics being shown) are passed over.
+fn get_plays_from_play_activity_csv(
+    reader: impl Read,
+    options: &LoadOptions,
+    file_report: &mut FileIngestionReport,
+) -> std::result::Result<Vec<PlayItem>, String> {
+    let source_file: Arc<str> = Arc::from(file_report.file_name.as_str());
+
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(reader);
+    let header = reader.headers().map_err(|e| e.to_string())?;
+    let columns = PlayActivityColumns::from_header(header)?;
+
+    let mut play_items = vec![];
+    for (record_index, record) in reader.records().enumerate() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                plays::handle_failed_record(record_index, e.to_string(), options, file_report)?;
+                continue;
+            }
+        };
+
+        let get_field = |column: Option<usize>| {
+            column
+                .and_then(|column| record.get(column))
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+        };
+
+        if get_field(columns.event_type).is_some_and(|event_type| event_type != "PLAY_END") {
+            continue;
+        }
+
+        let Some(song_name) = get_field(Some(columns.song_name)) else {
+            // Rows without a song are for things like radio stations, rather than plays of a song
+            continue;
+        };
+
+        let Some(Ok(started_at)) = get_field(Some(columns.event_start_timestamp))
+            .map(|timestamp| timestamp.parse::<DateTime<Utc>>())
+        else {
+            let error = "The play has no valid event start timestamp.".to_owned();
+            plays::handle_failed_record(record_index, error, options, file_report)?;
+            continue;
+        };
+
+        // Durations are sometimes negative when Apple couldn't work them out
+        let Some(Ok(ms_played)) =
+            get_field(Some(columns.play_duration_ms)).map(|duration| duration.parse::<u64>())
+        else {
+            let error = "The play has no valid play duration.".to_owned();
+            plays::handle_failed_record(record_index, error, options, file_report)?;
+            continue;
+        };
+
+        let ts = get_field(columns.event_end_timestamp)
+            .and_then(|timestamp| timestamp.parse::<DateTime<Utc>>().ok())
+            .unwrap_or(started_at + Duration::milliseconds(ms_played as i64));
+
+        let (reason_end, skipped) = get_field(columns.end_reason_type)
+            .map(get_reason_end_and_skipped)
+            .unwrap_or_default();
+
+        let kind = match get_field(columns.media_type) {
+            Some("VIDEO") => PlayItemKind::Video,
+            _ => PlayItemKind::Audio,
+        };
+
+        let mut play_item = PlayItem {
+            audiobook_chapter_title: None,
+            audiobook_chapter_uri: None,
+            audiobook_title: None,
+            audiobook_uri: None,
+            conn_country: None,
+            episode_name: None,
+            episode_show_name: None,
+            incognito_mode: None,
+            ip_addr_decrypted: get_field(columns.client_ip_address).map(str::to_owned),
+            master_metadata_album_album_name: get_field(columns.album_name).map(str::to_owned),
+            master_metadata_album_artist_name: get_field(columns.artist_name).map(str::to_owned),
+            master_metadata_track_name: Some(song_name.to_owned()),
+            ms_played: Some(ms_played),
+            offline: get_field(columns.offline).and_then(|offline| offline.parse().ok()),
+            offline_timestamp: None,
+            platform: None,
+            reason_end,
+            reason_start: None,
+            shuffle: None,
+            skipped,
+            spotify_episode_uri: None,
+            spotify_track_uri: None,
+            ts,
+            user_agent_decrypted: None,
+            username: None,
+            kind,
+            source: PlayItemSource::AppleMusic,
+            source_file: Some(Arc::clone(&source_file)),
+        };
+        options.privacy_policy.apply(&mut play_item);
+
+        play_items.push(play_item);
+    }
+
+    Ok(play_items)
+}
+
+/// Extracts plays from `Apple Music Play Activity.csv` (from Apple's data and privacy export), given as
+/// the name and a reader for each file. The plays are in the order that they ended in.
+///
+/// This does not touch the filesystem, so it can also be used where there isn't one (e.g. the wasm build).
+pub fn extract_plays_from_apple_music_files(
+    files: Vec<(String, impl Read)>,
+    options: &LoadOptions,
+) -> Result<(Vec<PlayItem>, IngestionReport)> {
+    let mut all_play_items = vec![];
+    let mut report = IngestionReport::new(options);
+
+    for (file_name, reader) in files {
+        let mut file_report = FileIngestionReport {
+            file_name,
+            ..FileIngestionReport::default()
+        };
+
+        match get_plays_from_play_activity_csv(reader, options, &mut file_report) {
+            Ok(mut play_items) => {
+                file_report.plays_loaded = play_items.len();
+                all_play_items.append(&mut play_items);
+            }
+            Err(error) => match options.parse_mode {
+                ParseMode::Strict => {
+                    return Err(eyre!("Unable to load {}. {}", file_report.file_name, error))
+                }
+                ParseMode::Lenient => file_report.error = Some(error),
+            },
+        }
+
+        report.files.push(file_report);
+    }
+
+    all_play_items.sort_by_key(|play_item| play_item.ts);
+
+    Ok((all_play_items, report))
+}
+
+pub fn extract_plays_from_apple_music_paths(
+    file_paths: &[PathBuf],
+    options: &LoadOptions,
+) -> Result<(Vec<PlayItem>, IngestionReport)> {
+    let mut files = vec![];
+    for path in file_paths {
+        let file_name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        files.push((file_name, fs::File::open(path)?));
+    }
+
+    extract_plays_from_apple_music_files(files, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAY_ACTIVITY: &str = "\
+Artist Name,Container Album Name,Song Name,Event Type,Event Start Timestamp,Event End Timestamp,Play Duration Milliseconds,End Reason Type,Media Type
+Artist,Album,Skipped,PLAY_START,2023-01-01T10:00:00.000Z,,0,,AUDIO
+Artist,Album,Skipped,PLAY_END,2023-01-01T10:00:00.000Z,2023-01-01T10:00:30.000Z,30000,TRACK_SKIPPED_FORWARDS,AUDIO
+Artist,Album,Finished,PLAY_END,2023-01-01T10:01:00.000Z,,200000,NATURAL_END_OF_TRACK,AUDIO
+Artist,,Video,PLAY_END,2023-01-01T11:00:00.000Z,2023-01-01T11:03:00.000Z,180000,PLAYBACK_MANUALLY_PAUSED,VIDEO
+Artist,Album,Broken,PLAY_END,2023-01-01T12:00:00.000Z,,-1,NATURAL_END_OF_TRACK,AUDIO
+";
+
+    fn extract_plays() -> (Vec<PlayItem>, IngestionReport) {
+        let files = vec![(
+            "Apple Music Play Activity.csv".to_owned(),
+            PLAY_ACTIVITY.as_bytes(),
+        )];
+        extract_plays_from_apple_music_files(files, &LoadOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn only_reads_play_end_rows() {
+        let (play_items, report) = extract_plays();
+
+        assert_eq!(play_items.len(), 3);
+        assert_eq!(report.files[0].plays_loaded, 3);
+        // The play with a negative duration is skipped
+        assert_eq!(report.files[0].skipped_records.len(), 1);
+        assert_eq!(report.files[0].skipped_records[0].record_index, 4);
+
+        assert_eq!(
+            play_items[0].master_metadata_track_name.as_deref(),
+            Some("Skipped")
+        );
+        assert_eq!(
+            play_items[0].ts,
+            Utc.with_ymd_and_hms(2023, 1, 1, 10, 0, 30).unwrap()
+        );
+        // Without an end timestamp, the play ends its duration after it started
+        assert_eq!(
+            play_items[1].ts,
+            Utc.with_ymd_and_hms(2023, 1, 1, 10, 4, 20).unwrap()
+        );
+        assert_eq!(play_items[1].ms_played, Some(200_000));
+        assert_eq!(play_items[1].source, PlayItemSource::AppleMusic);
+        assert_eq!(play_items[2].kind, PlayItemKind::Video);
+        assert_eq!(play_items[2].master_metadata_album_album_name, None);
+    }
+
+    #[test]
+    fn maps_end_reasons() {
+        let (play_items, _) = extract_plays();
+
+        assert_eq!(play_items[0].reason_end, Some(ReasonEnd::FwdBtn));
+        assert_eq!(play_items[0].skipped, Some(true));
+        assert_eq!(play_items[1].reason_end, Some(ReasonEnd::TrackDone));
+        assert_eq!(play_items[1].skipped, Some(false));
+        assert_eq!(play_items[2].reason_end, Some(ReasonEnd::EndPlay));
+        assert_eq!(play_items[2].skipped, Some(false));
+
+        assert_eq!(get_reason_end_and_skipped("NOT_APPLICABLE"), (None, None));
+        assert_eq!(
+            get_reason_end_and_skipped("SOMETHING_NEW"),
+            (Some(ReasonEnd::Other("something_new".to_owned())), None)
+        );
+    }
+}
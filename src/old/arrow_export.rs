@@ -0,0 +1,805 @@
+use crate::{
+    group::{Group, MetaData},
+    plays::{Platform, PlayItem, ReasonEnd, ReasonStart},
+    sort::SortSpotifyDataBy,
+};
+use arrow::{
+    array::{
+        ArrayRef, BooleanArray, Float64Array, StringArray, TimestampMillisecondArray, UInt32Array,
+        UInt64Array,
+    },
+    datatypes::SchemaRef,
+    ipc::writer::FileWriter,
+    record_batch::RecordBatch,
+};
+use eyre::Result;
+use parquet::arrow::ArrowWriter;
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// How many plays go in each record batch when they are exported, so the columns for every play don't
+/// have to be built at once
+const PLAYS_PER_RECORD_BATCH: usize = 65_536;
+
+/// The file formats that plays and groups can be exported in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// The Arrow IPC file format (also known as Feather v2)
+    ArrowIpc,
+    Parquet,
+}
+
+impl ExportFormat {
+    fn get_extension(&self) -> &'static str {
+        match self {
+            Self::ArrowIpc => "arrow",
+            Self::Parquet => "parquet",
+        }
+    }
+}
+
+fn get_string_array<T>(items: &[T], get_value: impl Fn(&T) -> Option<&str>) -> ArrayRef {
+    Arc::new(items.iter().map(get_value).collect::<StringArray>())
+}
+
+fn get_boolean_array<T>(items: &[T], get_value: impl Fn(&T) -> Option<bool>) -> ArrayRef {
+    Arc::new(items.iter().map(get_value).collect::<BooleanArray>())
+}
+
+fn get_u64_array<T>(items: &[T], get_value: impl Fn(&T) -> Option<u64>) -> ArrayRef {
+    Arc::new(items.iter().map(get_value).collect::<UInt64Array>())
+}
+
+fn get_u32_array<T>(items: &[T], get_value: impl Fn(&T) -> u32) -> ArrayRef {
+    Arc::new(items.iter().map(get_value).collect::<UInt32Array>())
+}
+
+fn get_f64_array<T>(items: &[T], get_value: impl Fn(&T) -> Option<f64>) -> ArrayRef {
+    Arc::new(items.iter().map(get_value).collect::<Float64Array>())
+}
+
+/// Puts plays into an Arrow record batch, with one row for each play. The columns are named after the
+/// fields of the extended streaming history (and in the same order as `PlayItem`), and `ts` is a UTC
+/// timestamp in milliseconds.
+pub fn get_plays_record_batch(play_items: &[PlayItem]) -> Result<RecordBatch> {
+    let ts: ArrayRef = Arc::new(
+        TimestampMillisecondArray::from_iter_values(
+            play_items
+                .iter()
+                .map(|play_item| play_item.ts.timestamp_millis()),
+        )
+        .with_timezone("UTC"),
+    );
+
+    let columns = vec![
+        (
+            "audiobook_chapter_title",
+            get_string_array(play_items, |p| p.audiobook_chapter_title.as_deref()),
+            true,
+        ),
+        (
+            "audiobook_chapter_uri",
+            get_string_array(play_items, |p| p.audiobook_chapter_uri.as_deref()),
+            true,
+        ),
+        (
+            "audiobook_title",
+            get_string_array(play_items, |p| p.audiobook_title.as_deref()),
+            true,
+        ),
+        (
+            "audiobook_uri",
+            get_string_array(play_items, |p| p.audiobook_uri.as_deref()),
+            true,
+        ),
+        (
+            "conn_country",
+            get_string_array(play_items, |p| p.conn_country.as_deref()),
+            true,
+        ),
+        (
+            "episode_name",
+            get_string_array(play_items, |p| p.episode_name.as_deref()),
+            true,
+        ),
+        (
+            "episode_show_name",
+            get_string_array(play_items, |p| p.episode_show_name.as_deref()),
+            true,
+        ),
+        (
+            "incognito_mode",
+            get_boolean_array(play_items, |p| p.incognito_mode),
+            true,
+        ),
+        (
+            "ip_addr_decrypted",
+            get_string_array(play_items, |p| p.ip_addr_decrypted.as_deref()),
+            true,
+        ),
+        (
+            "master_metadata_album_album_name",
+            get_string_array(play_items, |p| {
+                p.master_metadata_album_album_name.as_deref()
+            }),
+            true,
+        ),
+        (
+            "master_metadata_album_artist_name",
+            get_string_array(play_items, |p| {
+                p.master_metadata_album_artist_name.as_deref()
+            }),
+            true,
+        ),
+        (
+            "master_metadata_track_name",
+            get_string_array(play_items, |p| p.master_metadata_track_name.as_deref()),
+            true,
+        ),
+        (
+            "ms_played",
+            get_u64_array(play_items, |p| p.ms_played),
+            true,
+        ),
+        (
+            "offline",
+            get_boolean_array(play_items, |p| p.offline),
+            true,
+        ),
+        (
+            "offline_timestamp",
+            get_u64_array(play_items, |p| p.offline_timestamp),
+            true,
+        ),
+        (
+            "platform",
+            get_string_array(play_items, |p| p.platform.as_ref().map(Platform::as_str)),
+            true,
+        ),
+        (
+            "reason_end",
+            get_string_array(play_items, |p| p.reason_end.as_ref().map(ReasonEnd::as_str)),
+            true,
+        ),
+        (
+            "reason_start",
+            get_string_array(play_items, |p| {
+                p.reason_start.as_ref().map(ReasonStart::as_str)
+            }),
+            true,
+        ),
+        (
+            "shuffle",
+            get_boolean_array(play_items, |p| p.shuffle),
+            true,
+        ),
+        (
+            "skipped",
+            get_boolean_array(play_items, |p| p.skipped),
+            true,
+        ),
+        (
+            "spotify_episode_uri",
+            get_string_array(play_items, |p| p.spotify_episode_uri.as_deref()),
+            true,
+        ),
+        (
+            "spotify_track_uri",
+            get_string_array(play_items, |p| p.spotify_track_uri.as_deref()),
+            true,
+        ),
+        ("ts", ts, false),
+        (
+            "user_agent_decrypted",
+            get_string_array(play_items, |p| p.user_agent_decrypted.as_deref()),
+            true,
+        ),
+        (
+            "username",
+            get_string_array(play_items, |p| p.username.as_deref()),
+            true,
+        ),
+        (
+            "kind",
+            get_string_array(play_items, |p| Some(p.kind.as_str())),
+            false,
+        ),
+        (
+            "source",
+            get_string_array(play_items, |p| Some(p.source.as_str())),
+            false,
+        ),
+        (
+            "source_file",
+            get_string_array(play_items, |p| p.source_file.as_deref()),
+            true,
+        ),
+    ];
+
+    Ok(RecordBatch::try_from_iter_with_nullable(columns)?)
+}
+
+/// The names of a group, split into one column for each kind of name. Names that a kind of group doesn't
+/// have are None.
+#[derive(Default)]
+struct MetaDataColumns<'a> {
+    group_type: &'static str,
+    track_name: Option<&'a str>,
+    album_name: Option<&'a str>,
+    artist_name: Option<&'a str>,
+    episode_name: Option<&'a str>,
+    podcast_name: Option<&'a str>,
+    chapter_title: Option<&'a str>,
+    audiobook_title: Option<&'a str>,
+    device_family: Option<&'static str>,
+    os: Option<&'a str>,
+    device_model: Option<&'a str>,
+    country_code: Option<&'a str>,
+    country_name: Option<&'a str>,
+    /// The time bucket of a group of plays grouped by time, formatted for display
+    time_bucket: Option<String>,
+}
+
+impl<'a> From<&'a MetaData> for MetaDataColumns<'a> {
+    fn from(meta_data: &'a MetaData) -> Self {
+        match meta_data {
+            MetaData::Album {
+                album_name,
+                artist_name,
+            } => MetaDataColumns {
+                group_type: "album",
+                album_name: Some(album_name),
+                artist_name: Some(artist_name),
+                ..MetaDataColumns::default()
+            },
+            MetaData::Artist { artist_name } => MetaDataColumns {
+                group_type: "artist",
+                artist_name: Some(artist_name),
+                ..MetaDataColumns::default()
+            },
+            MetaData::Song {
+                track_name,
+                album_name,
+                artist_name,
+            } => MetaDataColumns {
+                group_type: "song",
+                track_name: Some(track_name),
+                album_name: album_name.as_deref(),
+                artist_name: Some(artist_name),
+                ..MetaDataColumns::default()
+            },
+            MetaData::Podcast { podcast_name } => MetaDataColumns {
+                group_type: "podcast",
+                podcast_name: Some(podcast_name),
+                ..MetaDataColumns::default()
+            },
+            MetaData::PodcastEpisode {
+                episode_name,
+                podcast_name,
+            } => MetaDataColumns {
+                group_type: "podcast_episode",
+                episode_name: Some(episode_name),
+                podcast_name: Some(podcast_name),
+                ..MetaDataColumns::default()
+            },
+            MetaData::Audiobook { audiobook_title } => MetaDataColumns {
+                group_type: "audiobook",
+                audiobook_title: Some(audiobook_title),
+                ..MetaDataColumns::default()
+            },
+            MetaData::AudiobookChapter {
+                chapter_title,
+                audiobook_title,
+            } => MetaDataColumns {
+                group_type: "audiobook_chapter",
+                chapter_title: Some(chapter_title),
+                audiobook_title: Some(audiobook_title),
+                ..MetaDataColumns::default()
+            },
+            MetaData::Platform { family, os, model } => MetaDataColumns {
+                group_type: "platform",
+                device_family: Some(family.as_str()),
+                os: os.as_deref(),
+                device_model: model.as_deref(),
+                ..MetaDataColumns::default()
+            },
+            MetaData::Country {
+                country_code,
+                country_name,
+            } => MetaDataColumns {
+                group_type: "country",
+                country_code: Some(country_code),
+                country_name: Some(country_name),
+                ..MetaDataColumns::default()
+            },
+            MetaData::HourOfDay { .. } => MetaDataColumns {
+                group_type: "hour_of_day",
+                time_bucket: Some(meta_data.as_string()),
+                ..MetaDataColumns::default()
+            },
+            MetaData::DayOfWeek { .. } => MetaDataColumns {
+                group_type: "day_of_week",
+                time_bucket: Some(meta_data.as_string()),
+                ..MetaDataColumns::default()
+            },
+            MetaData::Month { .. } => MetaDataColumns {
+                group_type: "month",
+                time_bucket: Some(meta_data.as_string()),
+                ..MetaDataColumns::default()
+            },
+            MetaData::Year { .. } => MetaDataColumns {
+                group_type: "year",
+                time_bucket: Some(meta_data.as_string()),
+                ..MetaDataColumns::default()
+            },
+            MetaData::Days { .. } => MetaDataColumns {
+                group_type: "days",
+                time_bucket: Some(meta_data.as_string()),
+                ..MetaDataColumns::default()
+            },
+        }
+    }
+}
+
+/// Puts grouped data into an Arrow record batch, with one row for each group. The names of each group
+/// come first, with a column for every kind of name (e.g. `track_name` or `podcast_name`) that is null
+/// when the group doesn't have it, and then every metric of its `AggregatedData`. Percentages that can't
+/// be computed for a group are null.
+pub fn get_groups_record_batch(grouped_data: &[Group]) -> Result<RecordBatch> {
+    let meta_data: Vec<MetaDataColumns> = grouped_data
+        .iter()
+        .map(|group| MetaDataColumns::from(group.get_metadata()))
+        .collect();
+    let aggregated_data: Vec<_> = grouped_data
+        .iter()
+        .map(Group::get_aggregated_data)
+        .collect();
+
+    let columns = vec![
+        (
+            "group_type",
+            get_string_array(&meta_data, |m| Some(m.group_type)),
+            false,
+        ),
+        (
+            "track_name",
+            get_string_array(&meta_data, |m| m.track_name),
+            true,
+        ),
+        (
+            "album_name",
+            get_string_array(&meta_data, |m| m.album_name),
+            true,
+        ),
+        (
+            "artist_name",
+            get_string_array(&meta_data, |m| m.artist_name),
+            true,
+        ),
+        (
+            "episode_name",
+            get_string_array(&meta_data, |m| m.episode_name),
+            true,
+        ),
+        (
+            "podcast_name",
+            get_string_array(&meta_data, |m| m.podcast_name),
+            true,
+        ),
+        (
+            "chapter_title",
+            get_string_array(&meta_data, |m| m.chapter_title),
+            true,
+        ),
+        (
+            "audiobook_title",
+            get_string_array(&meta_data, |m| m.audiobook_title),
+            true,
+        ),
+        (
+            "device_family",
+            get_string_array(&meta_data, |m| m.device_family),
+            true,
+        ),
+        ("os", get_string_array(&meta_data, |m| m.os), true),
+        (
+            "device_model",
+            get_string_array(&meta_data, |m| m.device_model),
+            true,
+        ),
+        (
+            "country_code",
+            get_string_array(&meta_data, |m| m.country_code),
+            true,
+        ),
+        (
+            "country_name",
+            get_string_array(&meta_data, |m| m.country_name),
+            true,
+        ),
+        (
+            "time_bucket",
+            get_string_array(&meta_data, |m| m.time_bucket.as_deref()),
+            true,
+        ),
+        (
+            "ms_played",
+            get_u64_array(&aggregated_data, |a| Some(a.ms_played)),
+            false,
+        ),
+        (
+            "play_count",
+            get_u32_array(&aggregated_data, |a| a.play_count),
+            false,
+        ),
+        (
+            "skip_count",
+            get_u32_array(&aggregated_data, |a| a.skip_count),
+            false,
+        ),
+        (
+            "skip_valid_plays",
+            get_u32_array(&aggregated_data, |a| a.skip_valid_plays),
+            false,
+        ),
+        (
+            "skip_pct",
+            get_f64_array(&aggregated_data, |a| {
+                a.get_metric(&SortSpotifyDataBy::SkipPct)
+            }),
+            true,
+        ),
+        (
+            "click_count",
+            get_u32_array(&aggregated_data, |a| a.click_count),
+            false,
+        ),
+        (
+            "click_valid_plays",
+            get_u32_array(&aggregated_data, |a| a.click_valid_plays),
+            false,
+        ),
+        (
+            "click_pct",
+            get_f64_array(&aggregated_data, |a| {
+                a.get_metric(&SortSpotifyDataBy::ClickPct)
+            }),
+            true,
+        ),
+        (
+            "shuffle_count",
+            get_u32_array(&aggregated_data, |a| a.shuffle_count),
+            false,
+        ),
+        (
+            "shuffle_valid_plays",
+            get_u32_array(&aggregated_data, |a| a.shuffle_valid_plays),
+            false,
+        ),
+        (
+            "shuffle_pct",
+            get_f64_array(&aggregated_data, |a| {
+                a.get_metric(&SortSpotifyDataBy::ShufflePct)
+            }),
+            true,
+        ),
+        (
+            "autoplay_count",
+            get_u32_array(&aggregated_data, |a| a.autoplay_count),
+            false,
+        ),
+        (
+            "autoplay_valid_plays",
+            get_u32_array(&aggregated_data, |a| a.autoplay_valid_plays),
+            false,
+        ),
+        (
+            "autoplay_pct",
+            get_f64_array(&aggregated_data, |a| {
+                a.get_metric(&SortSpotifyDataBy::AutoPlayPct)
+            }),
+            true,
+        ),
+        (
+            "completed_count",
+            get_u32_array(&aggregated_data, |a| a.completed_count),
+            false,
+        ),
+        (
+            "completed_valid_plays",
+            get_u32_array(&aggregated_data, |a| a.completed_valid_plays),
+            false,
+        ),
+        (
+            "completion_pct",
+            get_f64_array(&aggregated_data, |a| {
+                a.get_metric(&SortSpotifyDataBy::CompletionPct)
+            }),
+            true,
+        ),
+        (
+            "chapters_started",
+            get_u32_array(&aggregated_data, |a| a.chapters_started),
+            false,
+        ),
+        (
+            "chapters_completed",
+            get_u32_array(&aggregated_data, |a| a.chapters_completed),
+            false,
+        ),
+    ];
+
+    Ok(RecordBatch::try_from_iter_with_nullable(columns)?)
+}
+
+/// Writes a record batch in one of the export formats. Only the writer is needed, so the files can also
+/// be written where there is no filesystem (e.g. the wasm build).
+pub fn write_record_batch(
+    record_batch: &RecordBatch,
+    format: ExportFormat,
+    writer: impl Write + Send,
+) -> Result<()> {
+    write_record_batches(
+        &record_batch.schema(),
+        [Ok(record_batch.clone())],
+        format,
+        writer,
+    )
+}
+
+/// Writes record batches that all have the same schema to one file in one of the export formats. The
+/// batches are written one at a time, so they can be made as they are needed.
+pub fn write_record_batches(
+    schema: &SchemaRef,
+    record_batches: impl IntoIterator<Item = Result<RecordBatch>>,
+    format: ExportFormat,
+    writer: impl Write + Send,
+) -> Result<()> {
+    match format {
+        ExportFormat::ArrowIpc => {
+            let mut file_writer = FileWriter::try_new(writer, schema)?;
+            for record_batch in record_batches {
+                file_writer.write(&record_batch?)?;
+            }
+            file_writer.finish()?;
+        }
+        ExportFormat::Parquet => {
+            let mut arrow_writer = ArrowWriter::try_new(writer, schema.clone(), None)?;
+            for record_batch in record_batches {
+                arrow_writer.write(&record_batch?)?;
+            }
+            arrow_writer.close()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes plays and grouped data to `plays.<extension>` and `groups.<extension>` in a folder, returning
+/// the paths of the files that were written. The plays are written `PLAYS_PER_RECORD_BATCH` at a time.
+pub fn export_plays_and_groups_to_folder(
+    play_items: impl IntoIterator<Item = PlayItem>,
+    grouped_data: &[Group],
+    format: ExportFormat,
+    folder_path: &Path,
+) -> Result<Vec<PathBuf>> {
+    let plays_path = folder_path.join(format!("plays.{}", format.get_extension()));
+    let mut play_items = play_items.into_iter().peekable();
+    let plays_record_batches = std::iter::from_fn(|| {
+        play_items.peek()?;
+        let chunk: Vec<PlayItem> = play_items.by_ref().take(PLAYS_PER_RECORD_BATCH).collect();
+        Some(get_plays_record_batch(&chunk))
+    });
+    write_record_batches(
+        &get_plays_record_batch(&[])?.schema(),
+        plays_record_batches,
+        format,
+        fs::File::create(&plays_path)?,
+    )?;
+
+    let groups_path = folder_path.join(format!("groups.{}", format.get_extension()));
+    write_record_batch(
+        &get_groups_record_batch(grouped_data)?,
+        format,
+        fs::File::create(&groups_path)?,
+    )?;
+
+    Ok(vec![plays_path, groups_path])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        group::{self, GroupBy, GroupOptions},
+        plays::{self, PlayItemKind},
+    };
+    use arrow::{
+        array::AsArray,
+        datatypes::{DataType, Float64Type, TimeUnit},
+        ipc::reader::FileReader,
+    };
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::io::Cursor;
+
+    fn get_play_items() -> Vec<PlayItem> {
+        [
+            r#"{"ts": "2023-01-01T10:00:00Z", "ms_played": 1000, "master_metadata_track_name": "Song",
+                "master_metadata_album_artist_name": "Artist", "spotify_track_uri": "spotify:track:1",
+                "skipped": true, "platform": "android", "reason_end": "fwdbtn"}"#,
+            r#"{"ts": "2023-01-01T11:00:00Z", "ms_played": 2000, "master_metadata_track_name": "Song",
+                "master_metadata_album_artist_name": "Artist", "spotify_track_uri": "spotify:track:1",
+                "skipped": false}"#,
+            // Without a skipped field, so that the skip percentage of its group can't be computed
+            r#"{"ts": "2023-01-01T12:00:00Z", "ms_played": 3000, "master_metadata_track_name": "Other",
+                "master_metadata_album_artist_name": "Artist", "spotify_track_uri": "spotify:track:2"}"#,
+        ]
+        .iter()
+        .map(|record| {
+            plays::get_play_item_from_record_bytes(record.as_bytes(), PlayItemKind::Audio).unwrap()
+        })
+        .collect()
+    }
+
+    /// Writes a record batch and reads it back, as the only batch in the file
+    fn round_trip(record_batch: &RecordBatch, format: ExportFormat) -> RecordBatch {
+        let mut file = vec![];
+        write_record_batch(record_batch, format, &mut file).unwrap();
+
+        let mut record_batches: Vec<RecordBatch> = match format {
+            ExportFormat::ArrowIpc => FileReader::try_new(Cursor::new(file), None)
+                .unwrap()
+                .collect::<std::result::Result<_, _>>()
+                .unwrap(),
+            ExportFormat::Parquet => {
+                ParquetRecordBatchReaderBuilder::try_new(bytes::Bytes::from(file))
+                    .unwrap()
+                    .build()
+                    .unwrap()
+                    .collect::<std::result::Result<_, _>>()
+                    .unwrap()
+            }
+        };
+
+        assert_eq!(record_batches.len(), 1);
+        record_batches.remove(0)
+    }
+
+    fn assert_schema(record_batch: &RecordBatch, fields: &[(&str, DataType, bool)]) {
+        let schema = record_batch.schema();
+        let actual_fields: Vec<(&str, DataType, bool)> = schema
+            .fields()
+            .iter()
+            .map(|field| {
+                (
+                    field.name().as_str(),
+                    field.data_type().clone(),
+                    field.is_nullable(),
+                )
+            })
+            .collect();
+
+        assert_eq!(actual_fields, fields);
+    }
+
+    fn get_plays_fields() -> Vec<(&'static str, DataType, bool)> {
+        let string = |name| (name, DataType::Utf8, true);
+        let boolean = |name| (name, DataType::Boolean, true);
+
+        vec![
+            string("audiobook_chapter_title"),
+            string("audiobook_chapter_uri"),
+            string("audiobook_title"),
+            string("audiobook_uri"),
+            string("conn_country"),
+            string("episode_name"),
+            string("episode_show_name"),
+            boolean("incognito_mode"),
+            string("ip_addr_decrypted"),
+            string("master_metadata_album_album_name"),
+            string("master_metadata_album_artist_name"),
+            string("master_metadata_track_name"),
+            ("ms_played", DataType::UInt64, true),
+            boolean("offline"),
+            ("offline_timestamp", DataType::UInt64, true),
+            string("platform"),
+            string("reason_end"),
+            string("reason_start"),
+            boolean("shuffle"),
+            boolean("skipped"),
+            string("spotify_episode_uri"),
+            string("spotify_track_uri"),
+            (
+                "ts",
+                DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+                false,
+            ),
+            string("user_agent_decrypted"),
+            string("username"),
+            ("kind", DataType::Utf8, false),
+            ("source", DataType::Utf8, false),
+            string("source_file"),
+        ]
+    }
+
+    fn get_groups_fields() -> Vec<(&'static str, DataType, bool)> {
+        let string = |name| (name, DataType::Utf8, true);
+        let count = |name| (name, DataType::UInt32, false);
+        let pct = |name| (name, DataType::Float64, true);
+
+        vec![
+            ("group_type", DataType::Utf8, false),
+            string("track_name"),
+            string("album_name"),
+            string("artist_name"),
+            string("episode_name"),
+            string("podcast_name"),
+            string("chapter_title"),
+            string("audiobook_title"),
+            string("device_family"),
+            string("os"),
+            string("device_model"),
+            string("country_code"),
+            string("country_name"),
+            string("time_bucket"),
+            ("ms_played", DataType::UInt64, false),
+            count("play_count"),
+            count("skip_count"),
+            count("skip_valid_plays"),
+            pct("skip_pct"),
+            count("click_count"),
+            count("click_valid_plays"),
+            pct("click_pct"),
+            count("shuffle_count"),
+            count("shuffle_valid_plays"),
+            pct("shuffle_pct"),
+            count("autoplay_count"),
+            count("autoplay_valid_plays"),
+            pct("autoplay_pct"),
+            count("completed_count"),
+            count("completed_valid_plays"),
+            pct("completion_pct"),
+            count("chapters_started"),
+            count("chapters_completed"),
+        ]
+    }
+
+    #[test]
+    fn plays_round_trip_with_their_schema() {
+        let record_batch = get_plays_record_batch(&get_play_items()).unwrap();
+        assert_schema(&record_batch, &get_plays_fields());
+
+        for format in [ExportFormat::ArrowIpc, ExportFormat::Parquet] {
+            let read_record_batch = round_trip(&record_batch, format);
+
+            assert_schema(&read_record_batch, &get_plays_fields());
+            assert_eq!(read_record_batch, record_batch, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn groups_round_trip_with_their_schema() {
+        let grouped_data =
+            group::get_grouped_data(&GroupBy::Song, &GroupOptions::default(), get_play_items());
+        let record_batch = get_groups_record_batch(&grouped_data).unwrap();
+        assert_schema(&record_batch, &get_groups_fields());
+
+        // Percentages are exported as they are computed, and are null where they can't be
+        let skip_pcts = record_batch
+            .column_by_name("skip_pct")
+            .unwrap()
+            .as_primitive::<Float64Type>();
+        assert_eq!(skip_pcts.iter().collect::<Vec<_>>(), [Some(50.), None]);
+
+        for format in [ExportFormat::ArrowIpc, ExportFormat::Parquet] {
+            let read_record_batch = round_trip(&record_batch, format);
+
+            assert_schema(&read_record_batch, &get_groups_fields());
+            assert_eq!(read_record_batch, record_batch, "{:?}", format);
+        }
+    }
+}
@@ -0,0 +1,305 @@
+use crate::plays::{self, FileIngestionReport, IngestionReport, LoadOptions, PlayItem};
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    borrow::Cow,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Written at the start of every cache file, so that other files are never mistaken for one
+const CACHE_MAGIC: &[u8; 8] = b"DIOPLAYS";
+
+/// Has to be increased whenever `PlayItem` (or anything else stored in the cache) changes, so that caches
+/// written by older versions of dio are read again from the source data instead of being misread
+const CACHE_VERSION: u32 = 2;
+
+/// The plays loaded from some Spotify data, exactly as they were loaded, and what they were loaded from.
+/// Plays are borrowed while the cache is written, so that they don't have to be copied.
+#[derive(Deserialize, Serialize)]
+struct PlaysCache<'a> {
+    source_hash: Cow<'a, str>,
+    /// The salt that the plays' hashed fields were hashed with, so that plays loaded later can be hashed
+    /// the same way
+    hash_salt: u64,
+    play_items: Cow<'a, [PlayItem]>,
+    files: Cow<'a, [FileIngestionReport]>,
+}
+
+/// Where the cache for a folder or zip file of Spotify data is stored. It is kept next to the data, as
+/// `.dio-cache` inside of a folder or `<name>.zip.dio-cache` beside a zip file.
+pub fn get_cache_path(data_path: &Path, from_zip: bool) -> PathBuf {
+    if from_zip {
+        let mut file_name = data_path.file_name().unwrap_or_default().to_owned();
+        file_name.push(".dio-cache");
+        data_path.with_file_name(file_name)
+    } else {
+        data_path.join(".dio-cache")
+    }
+}
+
+/// Starts a hash of the source data with the options that change how plays are loaded from it, since
+/// plays loaded with other options can't be reused
+fn get_source_hasher(options: &LoadOptions) -> Sha256 {
+    let mut hasher = Sha256::new();
+    hasher.update(
+        format!(
+            "{:?} {:?} {:?} {:?}",
+            options.parse_mode,
+            options.privacy_policy.ip_addr,
+            options.privacy_policy.user_agent,
+            options.privacy_policy.username
+        )
+        .as_bytes(),
+    );
+
+    hasher
+}
+
+fn finish_source_hash(hasher: Sha256) -> String {
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Hashes the streaming history files in a folder, so that a cache of them can tell when they've changed
+pub fn get_source_hash_from_path(base_path: &PathBuf, options: &LoadOptions) -> Result<String> {
+    let mut hasher = get_source_hasher(options);
+
+    for (path, _) in plays::get_song_history_file_paths(base_path)? {
+        hasher.update(
+            path.file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .as_bytes(),
+        );
+        hasher.update(fs::metadata(&path)?.len().to_le_bytes());
+        io::copy(&mut fs::File::open(&path)?, &mut hasher)?;
+    }
+
+    Ok(finish_source_hash(hasher))
+}
+
+/// Hashes a zip file of Spotify data, so that a cache of it can tell when it has changed
+pub fn get_source_hash_from_zip_bytes(zip_bytes: &[u8], options: &LoadOptions) -> String {
+    let mut hasher = get_source_hasher(options);
+    hasher.update(zip_bytes);
+
+    finish_source_hash(hasher)
+}
+
+/// Writes loaded plays (and the report of loading them) into the bytes of a cache file
+pub fn get_cache_bytes(
+    play_items: &[PlayItem],
+    ingestion_report: &IngestionReport,
+    source_hash: &str,
+) -> Result<Vec<u8>> {
+    let cache = PlaysCache {
+        source_hash: Cow::Borrowed(source_hash),
+        hash_salt: ingestion_report.privacy_policy.get_hash_salt(),
+        play_items: Cow::Borrowed(play_items),
+        files: Cow::Borrowed(&ingestion_report.files),
+    };
+
+    let mut cache_bytes = CACHE_MAGIC.to_vec();
+    cache_bytes.extend_from_slice(&CACHE_VERSION.to_le_bytes());
+    bincode::serialize_into(&mut cache_bytes, &cache)?;
+
+    Ok(cache_bytes)
+}
+
+/// Reads the plays (and the report of loading them) out of the bytes of a cache file. Fails if the cache
+/// was written by another version of dio, or for different source data or options, in which case the
+/// plays have to be loaded from the source data again. The plays keep the salt that they were hashed
+/// with, unless the options have to keep their own salt and it is a different one.
+///
+/// This does not touch the filesystem, so it can also be used where there isn't one (e.g. the wasm build).
+pub fn get_plays_from_cache_bytes(
+    cache_bytes: &[u8],
+    source_hash: &str,
+    options: &LoadOptions,
+) -> Result<(Vec<PlayItem>, IngestionReport)> {
+    let Some(cache_bytes) = cache_bytes.strip_prefix(CACHE_MAGIC) else {
+        return Err(eyre!("Not a cache file."));
+    };
+
+    let (version_bytes, cache_bytes) = cache_bytes.split_at(cache_bytes.len().min(4));
+    if version_bytes != CACHE_VERSION.to_le_bytes() {
+        return Err(eyre!("The cache is from another version of dio."));
+    }
+
+    let cache: PlaysCache = bincode::deserialize(cache_bytes)?;
+    if cache.source_hash != source_hash {
+        return Err(eyre!("The cache is out of date."));
+    }
+
+    if options.keep_hash_salt
+        && options.privacy_policy.hashes_any_field()
+        && cache.hash_salt != options.privacy_policy.get_hash_salt()
+    {
+        return Err(eyre!("The cache was hashed with another salt."));
+    }
+
+    let ingestion_report = IngestionReport {
+        privacy_policy: options
+            .privacy_policy
+            .clone()
+            .with_hash_salt(cache.hash_salt),
+        files: cache.files.into_owned(),
+        ..IngestionReport::new(options)
+    };
+
+    Ok((cache.play_items.into_owned(), ingestion_report))
+}
+
+/// Loads plays from the cache at `cache_path` if it is up to date with the source data, or else with
+/// `extract_plays` and then writes them to the cache for next time.
+pub fn extract_plays_with_cache(
+    cache_path: &Path,
+    source_hash: &str,
+    options: &LoadOptions,
+    extract_plays: impl FnOnce() -> Result<(Vec<PlayItem>, IngestionReport)>,
+) -> Result<(Vec<PlayItem>, IngestionReport)> {
+    if let Ok(cache_bytes) = fs::read(cache_path) {
+        if let Ok(cached) = get_plays_from_cache_bytes(&cache_bytes, source_hash, options) {
+            return Ok(cached);
+        }
+    }
+
+    let (play_items, ingestion_report) = extract_plays()?;
+
+    // The cache only makes loading faster, so the plays are still returned if it can't be written (e.g.
+    // if the data is somewhere read-only)
+    if let Ok(cache_bytes) = get_cache_bytes(&play_items, &ingestion_report, source_hash) {
+        let _ = fs::write(cache_path, cache_bytes);
+    }
+
+    Ok((play_items, ingestion_report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plays::PlayItemKind;
+
+    const SOURCE_HASH: &str = "source hash";
+
+    fn get_cached_plays() -> (Vec<PlayItem>, IngestionReport) {
+        let record = r#"{"ts": "2023-01-01T10:00:00Z", "ms_played": 1000, "username": "user",
+            "master_metadata_track_name": "Song", "master_metadata_album_artist_name": "Artist"}"#;
+        let play_item =
+            plays::get_play_item_from_record_bytes(record.as_bytes(), PlayItemKind::Audio).unwrap();
+
+        let mut ingestion_report = IngestionReport::new(&LoadOptions::default());
+        ingestion_report.files.push(FileIngestionReport {
+            file_name: "Streaming_History_Audio_2023.json".to_owned(),
+            plays_loaded: 1,
+            ..FileIngestionReport::default()
+        });
+
+        (vec![play_item], ingestion_report)
+    }
+
+    fn get_cache_bytes_of_plays() -> Vec<u8> {
+        let (play_items, ingestion_report) = get_cached_plays();
+        get_cache_bytes(&play_items, &ingestion_report, SOURCE_HASH).unwrap()
+    }
+
+    #[test]
+    fn plays_round_trip_through_cache() {
+        let (play_items, ingestion_report) = get_cached_plays();
+        let cache_bytes = get_cache_bytes(&play_items, &ingestion_report, SOURCE_HASH).unwrap();
+
+        let (cached_play_items, cached_ingestion_report) =
+            get_plays_from_cache_bytes(&cache_bytes, SOURCE_HASH, &LoadOptions::default()).unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&cached_play_items).unwrap(),
+            serde_json::to_value(&play_items).unwrap()
+        );
+        assert_eq!(
+            serde_json::to_value(&cached_ingestion_report.files).unwrap(),
+            serde_json::to_value(&ingestion_report.files).unwrap()
+        );
+    }
+
+    #[test]
+    fn hash_salt_round_trips_through_cache() {
+        let (play_items, ingestion_report) = get_cached_plays();
+        let cache_bytes = get_cache_bytes(&play_items, &ingestion_report, SOURCE_HASH).unwrap();
+
+        // The options that the plays are read with have a salt of their own
+        let (_, cached_ingestion_report) =
+            get_plays_from_cache_bytes(&cache_bytes, SOURCE_HASH, &LoadOptions::default()).unwrap();
+
+        assert_eq!(
+            cached_ingestion_report.privacy_policy,
+            ingestion_report.privacy_policy
+        );
+        assert_eq!(
+            cached_ingestion_report.privacy_policy.get_hash_salt(),
+            ingestion_report.privacy_policy.get_hash_salt()
+        );
+    }
+
+    #[test]
+    fn rejects_cache_with_other_hash_salt_when_keeping_salt() {
+        let cache_bytes = get_cache_bytes_of_plays();
+
+        // Plays that are added to loaded ones have to be hashed with the loaded plays' salt
+        let options = LoadOptions {
+            keep_hash_salt: true,
+            ..LoadOptions::default()
+        };
+
+        assert!(get_plays_from_cache_bytes(&cache_bytes, SOURCE_HASH, &options).is_err());
+    }
+
+    #[test]
+    fn reads_cache_with_same_hash_salt_when_keeping_salt() {
+        let (play_items, ingestion_report) = get_cached_plays();
+        let cache_bytes = get_cache_bytes(&play_items, &ingestion_report, SOURCE_HASH).unwrap();
+
+        let options = LoadOptions {
+            privacy_policy: ingestion_report.privacy_policy.clone(),
+            keep_hash_salt: true,
+            ..LoadOptions::default()
+        };
+
+        assert!(get_plays_from_cache_bytes(&cache_bytes, SOURCE_HASH, &options).is_ok());
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let mut cache_bytes = get_cache_bytes_of_plays();
+        cache_bytes[0] = b'X';
+
+        assert!(
+            get_plays_from_cache_bytes(&cache_bytes, SOURCE_HASH, &LoadOptions::default()).is_err()
+        );
+    }
+
+    #[test]
+    fn rejects_cache_from_other_version() {
+        let mut cache_bytes = get_cache_bytes_of_plays();
+        cache_bytes[CACHE_MAGIC.len()..CACHE_MAGIC.len() + 4]
+            .copy_from_slice(&(CACHE_VERSION + 1).to_le_bytes());
+
+        assert!(
+            get_plays_from_cache_bytes(&cache_bytes, SOURCE_HASH, &LoadOptions::default()).is_err()
+        );
+    }
+
+    #[test]
+    fn rejects_cache_of_changed_source() {
+        assert!(get_plays_from_cache_bytes(
+            &get_cache_bytes_of_plays(),
+            "other source hash",
+            &LoadOptions::default()
+        )
+        .is_err());
+    }
+}
@@ -1,19 +1,523 @@
 use crate::group::{Group, GroupBy};
+use crate::play_store::PlayStore;
+use crate::privacy::{FieldPolicy, IpAddrPolicy, PrivacyPolicy};
 use crate::sort::SortSpotifyDataBy;
-use crate::Dio;
-use crate::{dates, filter, group, plays, sort};
+use crate::{
+    apple_music, arrow_export, cache, country, dates, db, dedup, estimate, filter, group,
+    group_tree, lastfm, library, listenbrainz, pivot, plays, sort,
+};
+use crate::{Dio, DioState};
 use rfd::FileDialog;
+use std::{fs, path::PathBuf};
 
 #[tauri::command]
-pub async fn load_spotify_data(unlocked_state: tauri::State<'_, Dio>) -> Result<(), String> {
+pub async fn load_spotify_data(
+    window: tauri::Window,
+    unlocked_state: tauri::State<'_, Dio>,
+) -> Result<(), String> {
+    let load_options = get_load_options(&unlocked_state, false)?;
+    let extracted_data = pick_and_extract_spotify_data(window, load_options, false).await?;
+
+    store_spotify_plays_data(&unlocked_state, extracted_data)?;
+
+    let Ok(mut state) = unlocked_state.0.lock() else {
+        return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+    };
+
+    // JAKE: Testing
+    state.group_by = GroupBy::Song;
+
+    let mut grouped_data = group::get_grouped_data_from_store(
+        &state.group_by,
+        &state.group_options,
+        &state.spotify_plays_data,
+        &(0..state.spotify_plays_data.len()).collect::<Vec<_>>(),
+    );
+    sort::sort_grouped_data(&mut grouped_data, sort::SortSpotifyDataBy::PlayCount, true);
+
+    println!("");
+    for (i, group) in grouped_data.iter().enumerate() {
+        if i == 25 {
+            break;
+        }
+        println!("{}. {}", i + 1, group);
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn load_spotify_data_from_zip(
+    window: tauri::Window,
+    unlocked_state: tauri::State<'_, Dio>,
+) -> Result<(), String> {
+    let load_options = get_load_options(&unlocked_state, false)?;
+    let extracted_data = pick_and_extract_spotify_data(window, load_options, true).await?;
+
+    store_spotify_plays_data(&unlocked_state, extracted_data)
+}
+
+/// Adds the plays from another folder or zip file of Spotify data to the ones that are already loaded,
+/// e.g. from a newer export. Plays that were already loaded are skipped, and the processed data is updated
+/// with the new plays instead of being grouped again from scratch. The library that was loaded first is
+/// kept, unless there wasn't one.
+#[tauri::command]
+pub async fn add_spotify_data(
+    window: tauri::Window,
+    unlocked_state: tauri::State<'_, Dio>,
+    from_zip: bool,
+) -> Result<(), String> {
+    let load_options = get_load_options(&unlocked_state, true)?;
+    let extracted_data = pick_and_extract_spotify_data(window, load_options, from_zip).await?;
+
+    {
+        let Ok(mut state) = unlocked_state.0.lock() else {
+            return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+        };
+
+        if state.library_data.is_empty() {
+            state.library_data = extracted_data.library_data;
+        }
+    }
+
+    add_to_spotify_plays_data(
+        &unlocked_state,
+        extracted_data.spotify_plays_data,
+        extracted_data.ingestion_report,
+    )
+}
+
+/// Adds plays from Last.fm exports (CSV files or JSON dumps of its API) to the ones that are already
+/// loaded. What Last.fm doesn't know about each play, like how long it lasted, is estimated from the loaded
+/// Spotify plays of the same song.
+#[tauri::command]
+pub async fn import_lastfm_data(unlocked_state: tauri::State<'_, Dio>) -> Result<(), String> {
+    let Some(file_paths) = FileDialog::new()
+        .add_filter("Last.fm scrobbles", &["csv", "json"])
+        .pick_files() else {
+        return Err("Error while choosing Last.fm export files.".to_owned());
+    };
+
+    let load_options = get_load_options(&unlocked_state, true)?;
+
+    let extraction = tauri::async_runtime::spawn_blocking(move || {
+        lastfm::extract_plays_from_lastfm_paths(&file_paths, &load_options)
+    });
+
+    let (lastfm_plays_data, ingestion_report) = match extraction.await {
+        Ok(Ok(extracted)) => extracted,
+        Ok(Err(e)) => {
+            return Err(format!(
+                "Error while attempting to load Last.fm data. {}",
+                e
+            ))
+        }
+        Err(_) => return Err("Error while attempting to load Last.fm data.".to_owned()),
+    };
+
+    add_imported_plays_data(&unlocked_state, lastfm_plays_data, ingestion_report)
+}
+
+/// Adds plays from a ListenBrainz export (the export zip, or the JSONL files of listens in it) to the ones
+/// that are already loaded
+#[tauri::command]
+pub async fn import_listenbrainz_data(unlocked_state: tauri::State<'_, Dio>) -> Result<(), String> {
+    let Some(file_paths) = FileDialog::new()
+        .add_filter("ListenBrainz listens", &["zip", "jsonl", "json"])
+        .pick_files() else {
+        return Err("Error while choosing ListenBrainz export files.".to_owned());
+    };
+
+    let load_options = get_load_options(&unlocked_state, true)?;
+
+    let extraction = tauri::async_runtime::spawn_blocking(move || {
+        listenbrainz::extract_plays_from_listenbrainz_paths(&file_paths, &load_options)
+    });
+
+    let (listenbrainz_plays_data, ingestion_report) = match extraction.await {
+        Ok(Ok(extracted)) => extracted,
+        Ok(Err(e)) => {
+            return Err(format!(
+                "Error while attempting to load ListenBrainz data. {}",
+                e
+            ))
+        }
+        Err(_) => return Err("Error while attempting to load ListenBrainz data.".to_owned()),
+    };
+
+    add_imported_plays_data(&unlocked_state, listenbrainz_plays_data, ingestion_report)
+}
+
+/// Adds plays from the `Apple Music Play Activity.csv` file of Apple's data and privacy export to the ones
+/// that are already loaded
+#[tauri::command]
+pub async fn import_apple_music_data(unlocked_state: tauri::State<'_, Dio>) -> Result<(), String> {
+    let Some(file_paths) = FileDialog::new()
+        .add_filter("Apple Music Play Activity", &["csv"])
+        .pick_files() else {
+        return Err("Error while choosing Apple Music Play Activity files.".to_owned());
+    };
+
+    let load_options = get_load_options(&unlocked_state, true)?;
+
+    let extraction = tauri::async_runtime::spawn_blocking(move || {
+        apple_music::extract_plays_from_apple_music_paths(&file_paths, &load_options)
+    });
+
+    let (apple_music_plays_data, ingestion_report) = match extraction.await {
+        Ok(Ok(extracted)) => extracted,
+        Ok(Err(e)) => {
+            return Err(format!(
+                "Error while attempting to load Apple Music data. {}",
+                e
+            ))
+        }
+        Err(_) => return Err("Error while attempting to load Apple Music data.".to_owned()),
+    };
+
+    add_imported_plays_data(&unlocked_state, apple_music_plays_data, ingestion_report)
+}
+
+/// Writes the loaded Spotify plays to a folder in the format that ListenBrainz imports listens in, so
+/// they can be moved to ListenBrainz. Returns the number of files written.
+#[tauri::command]
+pub fn export_listenbrainz_data(unlocked_state: tauri::State<Dio>) -> Result<usize, String> {
+    let Some(folder_path) = FileDialog::new().pick_folder() else {
+        return Err("Error while choosing a folder to export ListenBrainz listens to.".to_owned());
+    };
+
+    let Ok(state) = unlocked_state.0.lock() else {
+        return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+    };
+
+    listenbrainz::export_plays_to_listenbrainz_folder(
+        state.spotify_plays_data.iter_play_items(),
+        &folder_path,
+    )
+    .map_err(|e| format!("Error while exporting ListenBrainz listens. {}", e))
+}
+
+/// Writes the loaded plays and the processed data to a folder as Arrow IPC (`format` is "arrow") or
+/// Parquet (`format` is "parquet") files, so they can be analysed elsewhere. Returns the paths of the files
+/// that were written.
+#[tauri::command]
+pub fn export_columnar_data(
+    unlocked_state: tauri::State<Dio>,
+    format: String,
+) -> Result<Vec<PathBuf>, String> {
+    let format = match format.as_str() {
+        "arrow" => arrow_export::ExportFormat::ArrowIpc,
+        "parquet" => arrow_export::ExportFormat::Parquet,
+        _ => return Err("Invalid format string passed into export_columnar_data()".to_owned()),
+    };
+
+    let Some(folder_path) = FileDialog::new().pick_folder() else {
+        return Err("Error while choosing a folder to export the data to.".to_owned());
+    };
+
+    let Ok(state) = unlocked_state.0.lock() else {
+        return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+    };
+
+    arrow_export::export_plays_and_groups_to_folder(
+        state.spotify_plays_data.iter_play_items(),
+        &state.processed_data,
+        format,
+        &folder_path,
+    )
+    .map_err(|e| format!("Error while exporting the data. {}", e))
+}
+
+/// Opens (or creates) an SQLite database to keep the play history in. The loaded plays that aren't in the
+/// database yet are saved into it, and the plays in the database that aren't loaded are added to the loaded
+/// plays, so that nothing that was loaded is lost and both have every play. Plays that are loaded while the
+/// database is open are saved into it too.
+#[tauri::command]
+pub async fn open_database(unlocked_state: tauri::State<'_, Dio>) -> Result<(), String> {
+    let Some(database_path) = FileDialog::new()
+        .add_filter("SQLite database", &["sqlite", "db"])
+        .save_file() else {
+        return Err("Error while choosing a database file.".to_owned());
+    };
+
+    let opening_path = database_path.clone();
+    let opening = tauri::async_runtime::spawn_blocking(move || {
+        db::open_database(&opening_path).and_then(|conn| {
+            let database_plays_data = db::get_plays(&conn)?;
+            Ok((conn, database_plays_data))
+        })
+    });
+
+    let (mut conn, database_plays_data) = match opening.await {
+        Ok(Ok(opened_database)) => opened_database,
+        Ok(Err(e)) => {
+            return Err(format!(
+                "Error while attempting to open the database. {}",
+                e
+            ))
+        }
+        Err(_) => return Err("Error while attempting to open the database.".to_owned()),
+    };
+
+    let mut loaded_plays_data: Vec<plays::PlayItem> = {
+        let Ok(mut state) = unlocked_state.0.lock() else {
+            return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+        };
+
+        // Plays that are loaded while the new database is filled shouldn't go into the old one
+        state.database_path = None;
+        state.spotify_plays_data.iter_play_items().collect()
+    };
+    dedup::remove_duplicates_of_existing_plays(
+        &PlayStore::from_play_items(&database_plays_data),
+        &mut loaded_plays_data,
+    );
+
+    // Saving every loaded play can take a while, so it's done without holding on to the state
+    let saving = tauri::async_runtime::spawn_blocking(move || {
+        db::insert_plays(&mut conn, &loaded_plays_data)
+    });
+
+    match saving.await {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => {
+            return Err(format!(
+                "Error while saving the loaded plays to the database. {}",
+                e
+            ))
+        }
+        Err(_) => return Err("Error while saving the loaded plays to the database.".to_owned()),
+    }
+
+    let Ok(mut state) = unlocked_state.0.lock() else {
+        return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+    };
+
+    // The plays in the database that weren't loaded are added like newly loaded plays, except that they
+    // aren't from any file and weren't found twice while loading, so they don't add to the ingestion or
+    // dedup reports. They are already in the database, so it's only kept as open once they have been added.
+    let mut new_spotify_plays_data = database_plays_data;
+    dedup::remove_duplicates_of_existing_plays(
+        &state.spotify_plays_data,
+        &mut new_spotify_plays_data,
+    );
+    add_new_plays_to_state(&mut state, new_spotify_plays_data);
+
+    state.database_path = Some(database_path);
+
+    Ok(())
+}
+
+/// Gets the path of the database that was opened with `open_database()`
+fn get_database_path(unlocked_state: &tauri::State<'_, Dio>) -> Result<PathBuf, String> {
+    let Ok(state) = unlocked_state.0.lock() else {
+        return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+    };
+
+    let Some(database_path) = state.database_path.clone() else {
+        return Err("No database is open. One has to be opened with open_database() first.".to_owned());
+    };
+
+    Ok(database_path)
+}
+
+/// Adds the plays from a folder of Spotify data to the open database, and to the plays that are loaded.
+/// Plays that are already in the database are skipped.
+#[tauri::command]
+pub async fn import_spotify_data_into_database(
+    window: tauri::Window,
+    unlocked_state: tauri::State<'_, Dio>,
+) -> Result<(), String> {
+    // Plays are saved into the open database when they are added to the loaded plays
+    get_database_path(&unlocked_state)?;
+
     let Some(folder_path) = FileDialog::new().pick_folder() else {
         return Err("Error while choosing a folder containing Spotify data.".to_owned());
     };
 
-    let Ok(spotify_plays_data) = plays::extract_plays_from_path(&folder_path).await else {
-        return Err("Error while attempting to load Spotify data.".to_owned());
+    let load_options = get_load_options(&unlocked_state, true)?;
+
+    let extraction = tauri::async_runtime::spawn_blocking(move || {
+        let on_progress = |progress: &plays::LoadProgress| emit_load_progress(&window, progress);
+
+        plays::extract_plays_from_path(&folder_path, &load_options, on_progress)
+    });
+
+    let (spotify_plays_data, ingestion_report) = match extraction.await {
+        Ok(Ok(extracted)) => extracted,
+        Ok(Err(e)) => {
+            return Err(format!(
+                "Error while attempting to import Spotify data into the database. {}",
+                e
+            ))
+        }
+        Err(_) => {
+            return Err(
+                "Error while attempting to import Spotify data into the database.".to_owned(),
+            )
+        }
+    };
+
+    add_to_spotify_plays_data(&unlocked_state, spotify_plays_data, ingestion_report)
+}
+
+/// Runs an SQL query over the plays in the open database, which are in a table called `plays` that has
+/// the same columns as the extended streaming history. Only queries that don't change the database are
+/// allowed.
+#[tauri::command]
+pub fn run_sql_query(
+    unlocked_state: tauri::State<Dio>,
+    sql: String,
+) -> Result<db::QueryResult, String> {
+    let database_path = get_database_path(&unlocked_state)?;
+
+    db::run_query(&database_path, &sql)
+        .map_err(|e| format!("Error while running the SQL query. {}", e))
+}
+
+/// Adds plays imported from another service to the ones that are already loaded, after estimating what
+/// the other service doesn't know about them from the loaded Spotify plays. Plays that are already loaded
+/// from Spotify are skipped.
+fn add_imported_plays_data(
+    unlocked_state: &tauri::State<'_, Dio>,
+    mut imported_plays_data: Vec<plays::PlayItem>,
+    ingestion_report: plays::IngestionReport,
+) -> Result<(), String> {
+    {
+        let Ok(mut state) = unlocked_state.0.lock() else {
+            return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+        };
+
+        estimate::estimate_missing_fields(&mut imported_plays_data, &state.spotify_plays_data);
+
+        let dedup_report = dedup::remove_imported_plays_already_on_spotify(
+            &state.spotify_plays_data,
+            &mut imported_plays_data,
+        );
+        state.dedup_report.append(dedup_report);
+    }
+
+    add_to_spotify_plays_data(unlocked_state, imported_plays_data, ingestion_report)
+}
+
+/// Everything that is read from a folder or zip file of Spotify data
+struct ExtractedSpotifyData {
+    data_path: PathBuf,
+    spotify_plays_data: Vec<plays::PlayItem>,
+    ingestion_report: plays::IngestionReport,
+    library_data: library::LibraryData,
+}
+
+/// Asks the user for a folder (or a zip file if `from_zip` is set) of Spotify data, and extracts the plays
+/// and library from it
+async fn pick_and_extract_spotify_data(
+    window: tauri::Window,
+    load_options: plays::LoadOptions,
+    from_zip: bool,
+) -> Result<ExtractedSpotifyData, String> {
+    let data_path = if from_zip {
+        let Some(zip_path) = FileDialog::new().add_filter("Spotify data", &["zip"]).pick_file() else {
+            return Err("Error while choosing a zip file containing Spotify data.".to_owned());
+        };
+        zip_path
+    } else {
+        let Some(folder_path) = FileDialog::new().pick_folder() else {
+            return Err("Error while choosing a folder containing Spotify data.".to_owned());
+        };
+        folder_path
     };
 
+    let extraction_path = data_path.clone();
+    let extraction = tauri::async_runtime::spawn_blocking(move || -> eyre::Result<_> {
+        let on_progress = |progress: &plays::LoadProgress| emit_load_progress(&window, progress);
+
+        // Plays are loaded from a cache of them when the data hasn't changed since it was last loaded,
+        // which is much faster than parsing all of the streaming history files again
+        let cache_path = cache::get_cache_path(&extraction_path, from_zip);
+
+        if from_zip {
+            let zip_bytes = fs::read(&extraction_path)?;
+            let source_hash = cache::get_source_hash_from_zip_bytes(&zip_bytes, &load_options);
+            let (spotify_plays_data, mut ingestion_report) =
+                cache::extract_plays_with_cache(&cache_path, &source_hash, &load_options, || {
+                    plays::extract_plays_from_zip_bytes(
+                        &zip_bytes,
+                        &extraction_path.to_string_lossy(),
+                        &load_options,
+                        on_progress,
+                    )
+                })?;
+            let library_data = library::extract_library_from_zip_bytes(
+                &zip_bytes,
+                &load_options,
+                &mut ingestion_report,
+            )?;
+            Ok((spotify_plays_data, ingestion_report, library_data))
+        } else {
+            let source_hash = cache::get_source_hash_from_path(&extraction_path, &load_options)?;
+            let (spotify_plays_data, mut ingestion_report) =
+                cache::extract_plays_with_cache(&cache_path, &source_hash, &load_options, || {
+                    plays::extract_plays_from_path(&extraction_path, &load_options, on_progress)
+                })?;
+            let library_data = library::extract_library_from_path(
+                &extraction_path,
+                &load_options,
+                &mut ingestion_report,
+            )?;
+            Ok((spotify_plays_data, ingestion_report, library_data))
+        }
+    });
+
+    match extraction.await {
+        Ok(Ok((spotify_plays_data, ingestion_report, library_data))) => Ok(ExtractedSpotifyData {
+            data_path,
+            spotify_plays_data,
+            ingestion_report,
+            library_data,
+        }),
+        Ok(Err(e)) => Err(format!(
+            "Error while attempting to load Spotify data. {}",
+            e
+        )),
+        Err(_) => Err("Error while attempting to load Spotify data.".to_owned()),
+    }
+}
+
+/// Gets the options to load Spotify data with. Plays that are added to already loaded ones are scrubbed
+/// with the same privacy policy as those, so that their hashed fields can be compared.
+fn get_load_options(
+    unlocked_state: &tauri::State<'_, Dio>,
+    adding_to_loaded_plays: bool,
+) -> Result<plays::LoadOptions, String> {
+    let Ok(state) = unlocked_state.0.lock() else {
+        return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+    };
+
+    let mut load_options = state.load_options.clone();
+    if adding_to_loaded_plays && !state.spotify_plays_data.is_empty() {
+        load_options.privacy_policy = state.ingestion_report.privacy_policy.clone();
+        load_options.keep_hash_salt = true;
+    }
+
+    Ok(load_options)
+}
+
+/// Forwards the progress of loading Spotify data to the frontend as a "load_progress" event
+fn emit_load_progress(window: &tauri::Window, progress: &plays::LoadProgress) {
+    // Progress updates are only informational, so failing to send one shouldn't stop the loading
+    let _ = window.emit("load_progress", progress.clone());
+}
+
+/// Replaces the plays held in the global state with newly loaded ones (minus any duplicates), and resets
+/// the date range boundaries of the filter to match them. An open database is closed, since it doesn't
+/// have the plays that are loaded any more.
+fn store_spotify_plays_data(
+    unlocked_state: &tauri::State<'_, Dio>,
+    extracted_data: ExtractedSpotifyData,
+) -> Result<(), String> {
+    let mut spotify_plays_data = extracted_data.spotify_plays_data;
+    let dedup_report = dedup::remove_duplicate_plays(&mut spotify_plays_data);
+
     let Ok(date_range_boundaries) = dates::get_date_bounds_from_play_items(&spotify_plays_data) else {
         return Err("Unable to find the earliest and latest dates from the Spotify data.".to_owned());
     };
@@ -22,28 +526,249 @@ pub async fn load_spotify_data(unlocked_state: tauri::State<'_, Dio>) -> Result<
         return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
     };
 
-    state.spotify_data_folder_path = Some(folder_path);
-    state.spotify_plays_data = spotify_plays_data;
+    state.spotify_data_path = Some(extracted_data.data_path);
+    state.spotify_plays_data = PlayStore::from_play_items(&spotify_plays_data);
+    state.database_path = None;
+    state.ingestion_report = extracted_data.ingestion_report;
+    state.library_data = extracted_data.library_data;
+    state.dedup_report = dedup_report;
     state.filter.date_range_boundaries = date_range_boundaries;
+    state.processed_data.clear();
+    state.processed_data_grouping = None;
+    state.group_tree = None;
 
-    // JAKE: Testing
-    state.group_by = GroupBy::Song;
+    Ok(())
+}
 
-    let mut grouped_data =
-        group::get_grouped_data(&state.group_by, state.spotify_plays_data.clone());
-    sort::sort_grouped_data(&mut grouped_data, sort::SortSpotifyDataBy::PlayCount, true);
+/// Adds newly loaded plays to the ones held in the global state, skipping any that were already loaded
+/// and reporting them as duplicates. If a database is open, the new plays are saved into it.
+fn add_to_spotify_plays_data(
+    unlocked_state: &tauri::State<'_, Dio>,
+    mut new_spotify_plays_data: Vec<plays::PlayItem>,
+    ingestion_report: plays::IngestionReport,
+) -> Result<(), String> {
+    let Ok(mut state) = unlocked_state.0.lock() else {
+        return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+    };
 
-    println!("");
-    for (i, group) in grouped_data.iter().enumerate() {
-        if i == 25 {
-            break;
+    let dedup_report = dedup::remove_duplicates_of_existing_plays(
+        &state.spotify_plays_data,
+        &mut new_spotify_plays_data,
+    );
+
+    // The open database keeps every loaded play
+    if let Some(database_path) = &state.database_path {
+        db::open_database(database_path)
+            .and_then(|mut conn| db::insert_new_plays(&mut conn, &mut new_spotify_plays_data))
+            .map_err(|e| format!("Error while saving the new plays to the database. {}", e))?;
+    }
+
+    state.ingestion_report.files.extend(ingestion_report.files);
+    state.dedup_report.append(dedup_report);
+
+    add_new_plays_to_state(&mut state, new_spotify_plays_data);
+
+    Ok(())
+}
+
+/// Adds plays that aren't loaded yet to the ones held in the global state. The date range boundaries of
+/// the filter are widened to fit the new plays, and the new plays that are within the filter are added to
+/// the processed data.
+fn add_new_plays_to_state(state: &mut DioState, new_spotify_plays_data: Vec<plays::PlayItem>) {
+    // Every new play might have been a duplicate
+    let Ok((new_min_datetime, new_max_datetime)) =
+        dates::get_date_bounds_from_play_items(&new_spotify_plays_data) else {
+        return;
+    };
+
+    state.filter.date_range_boundaries = if state.spotify_plays_data.is_empty() {
+        (new_min_datetime, new_max_datetime)
+    } else {
+        let (min_datetime, max_datetime) = state.filter.date_range_boundaries;
+        (
+            min_datetime.min(new_min_datetime),
+            max_datetime.max(new_max_datetime),
+        )
+    };
+
+    let new_play_data_within_filter_dates =
+        filter::get_play_items_between_dates(&new_spotify_plays_data, &state.filter);
+    state.spotify_plays_data.extend(&new_spotify_plays_data);
+    // The group tree would leave out the new plays, so it has to be built again
+    state.group_tree = None;
+
+    // With nothing grouped yet there's nothing to add to, and the data is grouped when the filters are
+    // applied. Data that has been grouped can still have no groups, e.g. if no plays were in the date range.
+    if state.processed_data_grouping.is_some() {
+        let grouping = (state.group_by.clone(), state.group_options.clone());
+
+        if state.processed_data_grouping.as_ref() == Some(&grouping) {
+            group::add_plays_to_grouped_data(
+                &mut state.processed_data,
+                &state.group_by,
+                &state.group_options,
+                &new_play_data_within_filter_dates,
+            );
+        } else {
+            // The grouping has been changed since the data was processed, so the new plays can't be added
+            // to the groups that are there and everything is grouped again
+            let play_indices_within_filter_dates =
+                filter::get_play_indices_between_dates(&state.spotify_plays_data, &state.filter);
+
+            state.processed_data = group::get_grouped_data_from_store(
+                &state.group_by,
+                &state.group_options,
+                &state.spotify_plays_data,
+                &play_indices_within_filter_dates,
+            );
+            state.processed_data_grouping = Some(grouping);
         }
-        println!("{}. {}", i + 1, group);
+
+        sort::sort_grouped_data(
+            &mut state.processed_data,
+            state.sort_by.clone(),
+            state.sort_order_descending,
+        );
     }
+}
+
+#[tauri::command]
+pub fn get_ingestion_report(
+    unlocked_state: tauri::State<Dio>,
+) -> Result<plays::IngestionReport, String> {
+    let Ok(state) = unlocked_state.0.lock() else {
+        return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+    };
+
+    Ok(state.ingestion_report.clone())
+}
+
+#[tauri::command]
+pub fn get_dedup_report(unlocked_state: tauri::State<Dio>) -> Result<dedup::DedupReport, String> {
+    let Ok(state) = unlocked_state.0.lock() else {
+        return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+    };
+
+    Ok(state.dedup_report.clone())
+}
+
+#[tauri::command]
+pub fn set_parse_mode(
+    unlocked_state: tauri::State<Dio>,
+    new_parse_mode: String,
+) -> Result<(), String> {
+    let Ok(mut state) = unlocked_state.0.lock() else {
+        return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+    };
+
+    state.load_options.parse_mode = match new_parse_mode.as_str() {
+        "strict" => plays::ParseMode::Strict,
+        "lenient" => plays::ParseMode::Lenient,
+        _ => return Err("Invalid parse mode string passed into set_parse_mode()".to_owned()),
+    };
+
+    Ok(())
+}
+
+/// Sets how the IP address, user agent and username of plays are scrubbed the next time Spotify data is
+/// loaded. Plays that are already loaded keep the policy that they were loaded with.
+#[tauri::command]
+pub fn set_privacy_policy(
+    unlocked_state: tauri::State<Dio>,
+    ip_addr_policy: String,
+    user_agent_policy: String,
+    username_policy: String,
+) -> Result<(), String> {
+    let Ok(mut state) = unlocked_state.0.lock() else {
+        return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+    };
+
+    let ip_addr = match ip_addr_policy.as_str() {
+        "keep" => IpAddrPolicy::Keep,
+        "drop" => IpAddrPolicy::Drop,
+        "hash" => IpAddrPolicy::Hash,
+        "prefix" => IpAddrPolicy::Prefix,
+        _ => return Err("Invalid IP address policy string passed into set_privacy_policy()".to_owned()),
+    };
+    let Some(user_agent) = get_field_policy(&user_agent_policy) else {
+        return Err("Invalid user agent policy string passed into set_privacy_policy()".to_owned());
+    };
+    let Some(username) = get_field_policy(&username_policy) else {
+        return Err("Invalid username policy string passed into set_privacy_policy()".to_owned());
+    };
+
+    state.load_options.privacy_policy = PrivacyPolicy::new(ip_addr, user_agent, username);
 
     Ok(())
 }
 
+fn get_field_policy(field_policy: &str) -> Option<FieldPolicy> {
+    match field_policy {
+        "keep" => Some(FieldPolicy::Keep),
+        "drop" => Some(FieldPolicy::Drop),
+        "hash" => Some(FieldPolicy::Hash),
+        _ => None,
+    }
+}
+
+#[tauri::command]
+pub fn get_library_data(unlocked_state: tauri::State<Dio>) -> Result<library::LibraryData, String> {
+    let Ok(state) = unlocked_state.0.lock() else {
+        return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+    };
+
+    Ok(state.library_data.clone())
+}
+
+/// Returns the saved tracks ("Liked Songs") that have never been played
+#[tauri::command]
+pub fn get_unplayed_saved_tracks(
+    unlocked_state: tauri::State<Dio>,
+) -> Result<Vec<library::SavedTrack>, String> {
+    let Ok(state) = unlocked_state.0.lock() else {
+        return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+    };
+
+    Ok(
+        library::get_unplayed_saved_tracks(&state.library_data, &state.spotify_plays_data)
+            .into_iter()
+            .cloned()
+            .collect(),
+    )
+}
+
+/// Returns how much of each playlist's content has been played
+#[tauri::command]
+pub fn get_playlist_play_stats(
+    unlocked_state: tauri::State<Dio>,
+) -> Result<Vec<library::PlaylistPlayStats>, String> {
+    let Ok(state) = unlocked_state.0.lock() else {
+        return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+    };
+
+    Ok(library::get_playlist_play_stats(
+        &state.library_data,
+        &state.spotify_plays_data,
+    ))
+}
+
+/// Returns the searches that led to a play of one of their results within the given number of minutes
+#[tauri::command]
+pub fn get_searches_followed_by_plays(
+    unlocked_state: tauri::State<Dio>,
+    within_minutes: i64,
+) -> Result<Vec<library::SearchFollowedByPlay>, String> {
+    let Ok(state) = unlocked_state.0.lock() else {
+        return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+    };
+
+    Ok(library::get_searches_followed_by_plays(
+        &state.library_data,
+        &state.spotify_plays_data,
+        chrono::Duration::minutes(within_minutes),
+    ))
+}
+
 #[tauri::command]
 pub fn get_processed_data(unlocked_state: tauri::State<Dio>) -> Result<Vec<Group>, String> {
     let Ok(state) = unlocked_state.0.lock() else {
@@ -53,6 +778,50 @@ pub fn get_processed_data(unlocked_state: tauri::State<Dio>) -> Result<Vec<Group
     Ok(state.processed_data.clone())
 }
 
+/// Returns the metrics that can't be computed for any of the processed groups, e.g. because all of the
+/// plays came from the "Account data" export, which doesn't say whether plays were skipped or shuffled.
+#[tauri::command]
+pub fn get_unavailable_metrics(
+    unlocked_state: tauri::State<Dio>,
+) -> Result<Vec<SortSpotifyDataBy>, String> {
+    let Ok(state) = unlocked_state.0.lock() else {
+        return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+    };
+
+    let mut unavailable_metrics: Option<Vec<SortSpotifyDataBy>> = None;
+    for group in state.processed_data.iter() {
+        let group_unavailable_metrics = group.get_aggregated_data().get_unavailable_metrics();
+
+        unavailable_metrics = Some(match unavailable_metrics {
+            None => group_unavailable_metrics,
+            Some(metrics) => metrics
+                .into_iter()
+                .filter(|metric| group_unavailable_metrics.contains(metric))
+                .collect(),
+        });
+    }
+
+    Ok(unavailable_metrics.unwrap_or_default())
+}
+
+/// Returns where plays within the filter's date range happened, as stays in one country after another
+#[tauri::command]
+pub fn get_travel_timeline(
+    unlocked_state: tauri::State<Dio>,
+) -> Result<Vec<country::Stay>, String> {
+    let Ok(state) = unlocked_state.0.lock() else {
+        return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+    };
+
+    let play_indices_within_filter_dates =
+        filter::get_play_indices_between_dates(&state.spotify_plays_data, &state.filter);
+
+    Ok(country::get_travel_timeline(
+        &state.spotify_plays_data,
+        &play_indices_within_filter_dates,
+    ))
+}
+
 #[tauri::command]
 pub fn set_group_by(
     unlocked_state: tauri::State<Dio>,
@@ -62,15 +831,45 @@ pub fn set_group_by(
         return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
     };
 
-    state.group_by = match new_filter_group.as_str() {
-        "songs" => GroupBy::Song,
-        "artists" => GroupBy::Artist,
-        "albums" => GroupBy::Album,
-        "podcasts" => GroupBy::Podcast,
-        "podcast_episodes" => GroupBy::PodcastEpisode,
-        _ => return Err("Invalid filter group string passed.".to_owned()),
+    let Some(group_by) = group::get_group_by(&new_filter_group) else {
+        return Err("Invalid filter group string passed.".to_owned());
+    };
+    state.group_by = group_by;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub fn set_merge_same_name_releases(
+    unlocked_state: tauri::State<Dio>,
+    merge_same_name_releases: bool,
+) -> Result<(), String> {
+    let Ok(mut state) = unlocked_state.0.lock() else {
+        return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
     };
 
+    state.group_options.merge_same_name_releases = merge_same_name_releases;
+
+    Ok(())
+}
+
+/// Sets how far ahead of UTC (in minutes) the listener's time zone is, for grouping plays by time. This is a
+/// fixed offset, so it doesn't follow daylight saving time.
+#[tauri::command]
+pub fn set_utc_offset(
+    unlocked_state: tauri::State<Dio>,
+    utc_offset_minutes: i32,
+) -> Result<(), String> {
+    let Ok(mut state) = unlocked_state.0.lock() else {
+        return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+    };
+
+    if utc_offset_minutes.abs() >= 24 * 60 {
+        return Err("Invalid UTC offset passed into set_utc_offset()".to_owned());
+    }
+
+    state.group_options.utc_offset_minutes = utc_offset_minutes;
+
     Ok(())
 }
 
@@ -83,17 +882,247 @@ pub fn apply_filters_and_group(unlocked_state: tauri::State<Dio>) -> Result<(),
         return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
     };
 
-    let play_data_within_filter_dates =
-        filter::get_play_items_between_dates(&state.spotify_plays_data, &state.filter);
+    let play_indices_within_filter_dates =
+        filter::get_play_indices_between_dates(&state.spotify_plays_data, &state.filter);
 
-    let grouped_data = group::get_grouped_data(&state.group_by, play_data_within_filter_dates);
+    let grouped_data = group::get_grouped_data_from_store(
+        &state.group_by,
+        &state.group_options,
+        &state.spotify_plays_data,
+        &play_indices_within_filter_dates,
+    );
 
     // TODO: Add more filters to the data
     state.processed_data = grouped_data;
+    state.processed_data_grouping = Some((state.group_by.clone(), state.group_options.clone()));
+
+    Ok(())
+}
+
+/// The same as `apply_filters_and_group()`, but the plays are filtered by a query on the open database
+/// instead of in memory
+#[tauri::command]
+pub fn apply_filters_and_group_in_database(unlocked_state: tauri::State<Dio>) -> Result<(), String> {
+    let database_path = get_database_path(&unlocked_state)?;
+
+    let Ok(mut state) = unlocked_state.0.lock() else {
+        return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+    };
+
+    let grouped_data = db::open_database(&database_path)
+        .and_then(|conn| {
+            db::get_grouped_data(&conn, &state.filter, &state.group_by, &state.group_options)
+        })
+        .map_err(|e| format!("Error while grouping the plays in the database. {}", e))?;
+
+    state.processed_data = grouped_data;
+    state.processed_data_grouping = Some((state.group_by.clone(), state.group_options.clone()));
+
+    Ok(())
+}
+
+/// Groups the plays within the filter's date range into a tree that can be drilled down through, e.g. from
+/// artists to their albums to their songs. Only the top level is grouped unless `expand_all` is set.
+#[tauri::command]
+pub fn apply_filters_and_build_group_tree(
+    unlocked_state: tauri::State<Dio>,
+    hierarchy: String,
+    expand_all: bool,
+) -> Result<(), String> {
+    let Ok(mut state) = unlocked_state.0.lock() else {
+        return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+    };
+
+    let hierarchy = match hierarchy.as_str() {
+        "artists_albums_songs" => group_tree::Hierarchy::ArtistAlbumSong,
+        "podcasts_episodes" => group_tree::Hierarchy::PodcastEpisode,
+        "audiobooks_chapters" => group_tree::Hierarchy::AudiobookChapter,
+        _ => {
+            return Err(
+                "Invalid hierarchy string passed into apply_filters_and_build_group_tree()"
+                    .to_owned(),
+            )
+        }
+    };
+
+    let play_indices_within_filter_dates =
+        filter::get_play_indices_between_dates(&state.spotify_plays_data, &state.filter);
+
+    let mut group_tree = group_tree::GroupTree::new(
+        hierarchy,
+        &state.group_options,
+        &state.spotify_plays_data,
+        &play_indices_within_filter_dates,
+    );
+    if expand_all {
+        group_tree.expand_all(&state.spotify_plays_data);
+    }
+    group_tree.sort_all(state.sort_by.clone(), state.sort_order_descending);
+
+    state.group_tree = Some(group_tree);
 
     Ok(())
 }
 
+#[tauri::command]
+pub fn get_group_tree(
+    unlocked_state: tauri::State<Dio>,
+) -> Result<Vec<group_tree::GroupNode>, String> {
+    let Ok(state) = unlocked_state.0.lock() else {
+        return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+    };
+
+    let Some(group_tree) = &state.group_tree else {
+        return Err("The group tree hasn't been built yet.".to_owned());
+    };
+
+    Ok(group_tree.get_nodes().to_vec())
+}
+
+/// Groups the plays of the node at `path` (the key of a node at each level from the top) by the level
+/// below it, and returns its children sorted the same way as the rest of the data
+#[tauri::command]
+pub fn expand_group_node(
+    unlocked_state: tauri::State<Dio>,
+    path: Vec<group::GroupKey>,
+) -> Result<Vec<group_tree::GroupNode>, String> {
+    let Ok(mut state) = unlocked_state.0.lock() else {
+        return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+    };
+
+    let state = &mut *state;
+    let Some(group_tree) = &mut state.group_tree else {
+        return Err("The group tree hasn't been built yet.".to_owned());
+    };
+
+    if let Err(e) = group_tree.expand(&path, &state.spotify_plays_data) {
+        return Err(format!("Error while expanding group node: {}", e));
+    }
+
+    match group_tree.sort_children(&path, state.sort_by.clone(), state.sort_order_descending) {
+        Ok(children) => Ok(children.to_vec()),
+        Err(e) => Err(format!("Error while sorting group node: {}", e)),
+    }
+}
+
+/// Sorts the children of the node at `path` (or the top level of the tree, if `path` is empty) by the
+/// current sort, without sorting the rest of the tree
+#[tauri::command]
+pub fn sort_group_node_children(
+    unlocked_state: tauri::State<Dio>,
+    path: Vec<group::GroupKey>,
+) -> Result<Vec<group_tree::GroupNode>, String> {
+    let Ok(mut state) = unlocked_state.0.lock() else {
+        return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+    };
+
+    let state = &mut *state;
+    let Some(group_tree) = &mut state.group_tree else {
+        return Err("The group tree hasn't been built yet.".to_owned());
+    };
+
+    match group_tree.sort_children(&path, state.sort_by.clone(), state.sort_order_descending) {
+        Ok(children) => Ok(children.to_vec()),
+        Err(e) => Err(format!("Error while sorting group node: {}", e)),
+    }
+}
+
+fn get_pivot_spec(
+    row_group_by: &str,
+    column_group_by: &str,
+    metric: &str,
+    max_rows: Option<usize>,
+    max_columns: Option<usize>,
+) -> Result<pivot::PivotSpec, String> {
+    let Some(rows) = group::get_group_by(row_group_by) else {
+        return Err("Invalid row group string passed.".to_owned());
+    };
+    let Some(columns) = group::get_group_by(column_group_by) else {
+        return Err("Invalid column group string passed.".to_owned());
+    };
+    let Some(metric) = get_sort_by(metric) else {
+        return Err("Invalid metric string passed.".to_owned());
+    };
+
+    Ok(pivot::PivotSpec {
+        rows,
+        columns,
+        metric,
+        max_rows,
+        max_columns,
+    })
+}
+
+/// Breaks the plays within the filter's date range out by two groupings at once (e.g. artists and years),
+/// showing one metric (the same strings as `set_sort()`) for each pair
+#[tauri::command]
+pub fn get_pivot_table(
+    unlocked_state: tauri::State<Dio>,
+    row_group_by: String,
+    column_group_by: String,
+    metric: String,
+    max_rows: Option<usize>,
+    max_columns: Option<usize>,
+) -> Result<pivot::PivotTable, String> {
+    let spec = get_pivot_spec(
+        &row_group_by,
+        &column_group_by,
+        &metric,
+        max_rows,
+        max_columns,
+    )?;
+
+    let Ok(state) = unlocked_state.0.lock() else {
+        return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
+    };
+
+    let play_indices_within_filter_dates =
+        filter::get_play_indices_between_dates(&state.spotify_plays_data, &state.filter);
+
+    Ok(pivot::get_pivot_table(
+        &spec,
+        &state.group_options,
+        &state.spotify_plays_data,
+        &play_indices_within_filter_dates,
+    ))
+}
+
+/// Makes the same pivot table as `get_pivot_table()` and saves it as a CSV file
+#[tauri::command]
+pub fn export_pivot_table_to_csv(
+    unlocked_state: tauri::State<Dio>,
+    row_group_by: String,
+    column_group_by: String,
+    metric: String,
+    max_rows: Option<usize>,
+    max_columns: Option<usize>,
+) -> Result<PathBuf, String> {
+    let pivot_table = get_pivot_table(
+        unlocked_state,
+        row_group_by,
+        column_group_by,
+        metric,
+        max_rows,
+        max_columns,
+    )?;
+
+    let Some(csv_path) = FileDialog::new()
+        .add_filter("CSV", &["csv"])
+        .save_file() else {
+        return Err("Error while choosing where to save the pivot table.".to_owned());
+    };
+
+    let Ok(csv_file) = fs::File::create(&csv_path) else {
+        return Err("Error while creating the pivot table's CSV file.".to_owned());
+    };
+
+    pivot_table
+        .write_csv(csv_file)
+        .map_err(|e| format!("Error while writing the pivot table. {}", e))?;
+
+    Ok(csv_path)
+}
+
 #[tauri::command]
 pub fn set_sort(
     unlocked_state: tauri::State<Dio>,
@@ -104,19 +1133,26 @@ pub fn set_sort(
         return Err("Unable to acquire lock on global state managed by Tauri.".to_owned());
     };
 
-    state.sort_by = match new_sort.as_str() {
+    let Some(sort_by) = get_sort_by(&new_sort) else {
+        return Err("Invalid sort_by string passed into set_sort()".to_owned());
+    };
+    state.sort_by = sort_by;
+    state.sort_order_descending = descending;
+
+    Ok(())
+}
+
+fn get_sort_by(sort_by: &str) -> Option<SortSpotifyDataBy> {
+    Some(match sort_by {
         "auto_play_pct" => SortSpotifyDataBy::AutoPlayPct,
         "click_pct" => SortSpotifyDataBy::ClickPct,
+        "completion_pct" => SortSpotifyDataBy::CompletionPct,
         "play_count" => SortSpotifyDataBy::PlayCount,
         "shuffle_pct" => SortSpotifyDataBy::ShufflePct,
         "skip_pct" => SortSpotifyDataBy::SkipPct,
         "total_listening_time" => SortSpotifyDataBy::TotalListenTime,
-        _ => return Err("Invalid sort_by string passed into set_sort()".to_owned()),
-    };
-
-    state.sort_order_descending = descending;
-
-    Ok(())
+        _ => return None,
+    })
 }
 
 #[tauri::command]
@@ -0,0 +1,434 @@
+use crate::play_store::PlayStore;
+use chrono::prelude::*;
+use serde::Serialize;
+
+/// What Spotify puts in `conn_country` when it doesn't know which country a play was in
+const UNKNOWN_COUNTRY_CODE: &str = "ZZ";
+
+/// The names of countries by their ISO 3166-1 alpha-2 code, sorted by code. Kosovo isn't in ISO 3166-1,
+/// but Spotify uses "XK" for it.
+const COUNTRY_NAMES: [(&str, &str); 250] = [
+    ("AD", "Andorra"),
+    ("AE", "United Arab Emirates"),
+    ("AF", "Afghanistan"),
+    ("AG", "Antigua and Barbuda"),
+    ("AI", "Anguilla"),
+    ("AL", "Albania"),
+    ("AM", "Armenia"),
+    ("AO", "Angola"),
+    ("AQ", "Antarctica"),
+    ("AR", "Argentina"),
+    ("AS", "American Samoa"),
+    ("AT", "Austria"),
+    ("AU", "Australia"),
+    ("AW", "Aruba"),
+    ("AX", "Åland Islands"),
+    ("AZ", "Azerbaijan"),
+    ("BA", "Bosnia and Herzegovina"),
+    ("BB", "Barbados"),
+    ("BD", "Bangladesh"),
+    ("BE", "Belgium"),
+    ("BF", "Burkina Faso"),
+    ("BG", "Bulgaria"),
+    ("BH", "Bahrain"),
+    ("BI", "Burundi"),
+    ("BJ", "Benin"),
+    ("BL", "Saint Barthélemy"),
+    ("BM", "Bermuda"),
+    ("BN", "Brunei Darussalam"),
+    ("BO", "Bolivia"),
+    ("BQ", "Caribbean Netherlands"),
+    ("BR", "Brazil"),
+    ("BS", "Bahamas"),
+    ("BT", "Bhutan"),
+    ("BV", "Bouvet Island"),
+    ("BW", "Botswana"),
+    ("BY", "Belarus"),
+    ("BZ", "Belize"),
+    ("CA", "Canada"),
+    ("CC", "Cocos Islands"),
+    ("CD", "Democratic Republic of the Congo"),
+    ("CF", "Central African Republic"),
+    ("CG", "Congo"),
+    ("CH", "Switzerland"),
+    ("CI", "Côte d'Ivoire"),
+    ("CK", "Cook Islands"),
+    ("CL", "Chile"),
+    ("CM", "Cameroon"),
+    ("CN", "China"),
+    ("CO", "Colombia"),
+    ("CR", "Costa Rica"),
+    ("CU", "Cuba"),
+    ("CV", "Cabo Verde"),
+    ("CW", "Curaçao"),
+    ("CX", "Christmas Island"),
+    ("CY", "Cyprus"),
+    ("CZ", "Czechia"),
+    ("DE", "Germany"),
+    ("DJ", "Djibouti"),
+    ("DK", "Denmark"),
+    ("DM", "Dominica"),
+    ("DO", "Dominican Republic"),
+    ("DZ", "Algeria"),
+    ("EC", "Ecuador"),
+    ("EE", "Estonia"),
+    ("EG", "Egypt"),
+    ("EH", "Western Sahara"),
+    ("ER", "Eritrea"),
+    ("ES", "Spain"),
+    ("ET", "Ethiopia"),
+    ("FI", "Finland"),
+    ("FJ", "Fiji"),
+    ("FK", "Falkland Islands"),
+    ("FM", "Micronesia"),
+    ("FO", "Faroe Islands"),
+    ("FR", "France"),
+    ("GA", "Gabon"),
+    ("GB", "United Kingdom"),
+    ("GD", "Grenada"),
+    ("GE", "Georgia"),
+    ("GF", "French Guiana"),
+    ("GG", "Guernsey"),
+    ("GH", "Ghana"),
+    ("GI", "Gibraltar"),
+    ("GL", "Greenland"),
+    ("GM", "Gambia"),
+    ("GN", "Guinea"),
+    ("GP", "Guadeloupe"),
+    ("GQ", "Equatorial Guinea"),
+    ("GR", "Greece"),
+    ("GS", "South Georgia and the South Sandwich Islands"),
+    ("GT", "Guatemala"),
+    ("GU", "Guam"),
+    ("GW", "Guinea-Bissau"),
+    ("GY", "Guyana"),
+    ("HK", "Hong Kong"),
+    ("HM", "Heard Island and McDonald Islands"),
+    ("HN", "Honduras"),
+    ("HR", "Croatia"),
+    ("HT", "Haiti"),
+    ("HU", "Hungary"),
+    ("ID", "Indonesia"),
+    ("IE", "Ireland"),
+    ("IL", "Israel"),
+    ("IM", "Isle of Man"),
+    ("IN", "India"),
+    ("IO", "British Indian Ocean Territory"),
+    ("IQ", "Iraq"),
+    ("IR", "Iran"),
+    ("IS", "Iceland"),
+    ("IT", "Italy"),
+    ("JE", "Jersey"),
+    ("JM", "Jamaica"),
+    ("JO", "Jordan"),
+    ("JP", "Japan"),
+    ("KE", "Kenya"),
+    ("KG", "Kyrgyzstan"),
+    ("KH", "Cambodia"),
+    ("KI", "Kiribati"),
+    ("KM", "Comoros"),
+    ("KN", "Saint Kitts and Nevis"),
+    ("KP", "North Korea"),
+    ("KR", "South Korea"),
+    ("KW", "Kuwait"),
+    ("KY", "Cayman Islands"),
+    ("KZ", "Kazakhstan"),
+    ("LA", "Laos"),
+    ("LB", "Lebanon"),
+    ("LC", "Saint Lucia"),
+    ("LI", "Liechtenstein"),
+    ("LK", "Sri Lanka"),
+    ("LR", "Liberia"),
+    ("LS", "Lesotho"),
+    ("LT", "Lithuania"),
+    ("LU", "Luxembourg"),
+    ("LV", "Latvia"),
+    ("LY", "Libya"),
+    ("MA", "Morocco"),
+    ("MC", "Monaco"),
+    ("MD", "Moldova"),
+    ("ME", "Montenegro"),
+    ("MF", "Saint Martin"),
+    ("MG", "Madagascar"),
+    ("MH", "Marshall Islands"),
+    ("MK", "North Macedonia"),
+    ("ML", "Mali"),
+    ("MM", "Myanmar"),
+    ("MN", "Mongolia"),
+    ("MO", "Macao"),
+    ("MP", "Northern Mariana Islands"),
+    ("MQ", "Martinique"),
+    ("MR", "Mauritania"),
+    ("MS", "Montserrat"),
+    ("MT", "Malta"),
+    ("MU", "Mauritius"),
+    ("MV", "Maldives"),
+    ("MW", "Malawi"),
+    ("MX", "Mexico"),
+    ("MY", "Malaysia"),
+    ("MZ", "Mozambique"),
+    ("NA", "Namibia"),
+    ("NC", "New Caledonia"),
+    ("NE", "Niger"),
+    ("NF", "Norfolk Island"),
+    ("NG", "Nigeria"),
+    ("NI", "Nicaragua"),
+    ("NL", "Netherlands"),
+    ("NO", "Norway"),
+    ("NP", "Nepal"),
+    ("NR", "Nauru"),
+    ("NU", "Niue"),
+    ("NZ", "New Zealand"),
+    ("OM", "Oman"),
+    ("PA", "Panama"),
+    ("PE", "Peru"),
+    ("PF", "French Polynesia"),
+    ("PG", "Papua New Guinea"),
+    ("PH", "Philippines"),
+    ("PK", "Pakistan"),
+    ("PL", "Poland"),
+    ("PM", "Saint Pierre and Miquelon"),
+    ("PN", "Pitcairn"),
+    ("PR", "Puerto Rico"),
+    ("PS", "Palestine"),
+    ("PT", "Portugal"),
+    ("PW", "Palau"),
+    ("PY", "Paraguay"),
+    ("QA", "Qatar"),
+    ("RE", "Réunion"),
+    ("RO", "Romania"),
+    ("RS", "Serbia"),
+    ("RU", "Russia"),
+    ("RW", "Rwanda"),
+    ("SA", "Saudi Arabia"),
+    ("SB", "Solomon Islands"),
+    ("SC", "Seychelles"),
+    ("SD", "Sudan"),
+    ("SE", "Sweden"),
+    ("SG", "Singapore"),
+    ("SH", "Saint Helena"),
+    ("SI", "Slovenia"),
+    ("SJ", "Svalbard and Jan Mayen"),
+    ("SK", "Slovakia"),
+    ("SL", "Sierra Leone"),
+    ("SM", "San Marino"),
+    ("SN", "Senegal"),
+    ("SO", "Somalia"),
+    ("SR", "Suriname"),
+    ("SS", "South Sudan"),
+    ("ST", "Sao Tome and Principe"),
+    ("SV", "El Salvador"),
+    ("SX", "Sint Maarten"),
+    ("SY", "Syria"),
+    ("SZ", "Eswatini"),
+    ("TC", "Turks and Caicos Islands"),
+    ("TD", "Chad"),
+    ("TF", "French Southern Territories"),
+    ("TG", "Togo"),
+    ("TH", "Thailand"),
+    ("TJ", "Tajikistan"),
+    ("TK", "Tokelau"),
+    ("TL", "Timor-Leste"),
+    ("TM", "Turkmenistan"),
+    ("TN", "Tunisia"),
+    ("TO", "Tonga"),
+    ("TR", "Türkiye"),
+    ("TT", "Trinidad and Tobago"),
+    ("TV", "Tuvalu"),
+    ("TW", "Taiwan"),
+    ("TZ", "Tanzania"),
+    ("UA", "Ukraine"),
+    ("UG", "Uganda"),
+    ("UM", "United States Minor Outlying Islands"),
+    ("US", "United States"),
+    ("UY", "Uruguay"),
+    ("UZ", "Uzbekistan"),
+    ("VA", "Vatican City"),
+    ("VC", "Saint Vincent and the Grenadines"),
+    ("VE", "Venezuela"),
+    ("VG", "British Virgin Islands"),
+    ("VI", "U.S. Virgin Islands"),
+    ("VN", "Vietnam"),
+    ("VU", "Vanuatu"),
+    ("WF", "Wallis and Futuna"),
+    ("WS", "Samoa"),
+    ("XK", "Kosovo"),
+    ("YE", "Yemen"),
+    ("YT", "Mayotte"),
+    ("ZA", "South Africa"),
+    ("ZM", "Zambia"),
+    ("ZW", "Zimbabwe"),
+];
+
+/// Turns the `conn_country` of a play into a country code, or None if the country isn't known
+pub fn get_country_code(conn_country: &str) -> Option<String> {
+    let country_code = conn_country.trim().to_uppercase();
+
+    if country_code.is_empty() || country_code == UNKNOWN_COUNTRY_CODE {
+        None
+    } else {
+        Some(country_code)
+    }
+}
+
+/// Gets the full name of a country from its code. Codes that aren't known (e.g. ones added to ISO 3166-1
+/// after this list was made) are their own name.
+pub fn get_country_name(country_code: &str) -> String {
+    match COUNTRY_NAMES.binary_search_by_key(&country_code, |(code, _)| code) {
+        Ok(index) => COUNTRY_NAMES[index].1.to_owned(),
+        Err(_) => country_code.to_owned(),
+    }
+}
+
+/// A stretch of consecutive plays in one country
+#[derive(Clone, Debug, Serialize)]
+pub struct Stay {
+    pub country_code: String,
+    pub country_name: String,
+    pub first_play: DateTime<Utc>,
+    pub last_play: DateTime<Utc>,
+    pub play_count: u32,
+}
+
+/// Collapses the plays at `play_indices` into stays, in the order that they happened, so that trips stand
+/// out as a stay in another country between two stays at home. Plays without a known country don't end a
+/// stay.
+pub fn get_travel_timeline(play_store: &PlayStore, play_indices: &[usize]) -> Vec<Stay> {
+    let mut play_indices = play_indices.to_vec();
+    play_indices.sort_by_key(|&index| play_store.get_ts_millis(index));
+
+    let mut stays: Vec<Stay> = Vec::new();
+
+    for index in play_indices {
+        let Some(country_code) = play_store
+            .get_conn_country(index)
+            .and_then(get_country_code)
+        else {
+            continue;
+        };
+        let ts = play_store.get_ts(index);
+
+        match stays.last_mut() {
+            Some(stay) if stay.country_code == country_code => {
+                stay.last_play = ts;
+                stay.play_count += 1;
+            }
+            _ => stays.push(Stay {
+                country_name: get_country_name(&country_code),
+                country_code,
+                first_play: ts,
+                last_play: ts,
+                play_count: 1,
+            }),
+        }
+    }
+
+    stays
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plays::{self, PlayItemKind};
+
+    fn get_play_store(plays: &[(&str, &str)]) -> PlayStore {
+        let play_items: Vec<_> = plays
+            .iter()
+            .map(|(ts, conn_country)| {
+                let record =
+                    serde_json::json!({ "ts": ts, "conn_country": conn_country }).to_string();
+                plays::get_play_item_from_record_bytes(record.as_bytes(), PlayItemKind::Audio)
+                    .unwrap()
+            })
+            .collect();
+
+        PlayStore::from_play_items(&play_items)
+    }
+
+    fn get_stays(play_store: &PlayStore) -> Vec<(String, u32)> {
+        let play_indices: Vec<_> = (0..play_store.len()).collect();
+
+        get_travel_timeline(play_store, &play_indices)
+            .into_iter()
+            .map(|stay| (stay.country_code, stay.play_count))
+            .collect()
+    }
+
+    #[test]
+    fn country_names_are_sorted_by_code() {
+        // get_country_name binary searches the list, which only works while it is sorted
+        assert!(COUNTRY_NAMES.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn gets_country_names() {
+        assert_eq!(get_country_name("AD"), "Andorra");
+        assert_eq!(get_country_name("DE"), "Germany");
+        assert_eq!(get_country_name("ZW"), "Zimbabwe");
+
+        // Codes that aren't known are their own name
+        assert_eq!(get_country_name("QQ"), "QQ");
+    }
+
+    #[test]
+    fn gets_country_codes() {
+        assert_eq!(get_country_code(" de ").as_deref(), Some("DE"));
+        assert_eq!(get_country_code("ZZ"), None);
+        assert_eq!(get_country_code(""), None);
+    }
+
+    #[test]
+    fn collapses_consecutive_plays_into_stays() {
+        let play_store = get_play_store(&[
+            ("2023-01-01T10:00:00Z", "DE"),
+            ("2023-01-02T10:00:00Z", "DE"),
+            ("2023-01-03T10:00:00Z", "FR"),
+            ("2023-01-04T10:00:00Z", "DE"),
+            ("2023-01-05T10:00:00Z", "DE"),
+        ]);
+
+        let stays = get_travel_timeline(&play_store, &[0, 1, 2, 3, 4]);
+
+        assert_eq!(
+            stays
+                .iter()
+                .map(|stay| (stay.country_code.as_str(), stay.play_count))
+                .collect::<Vec<_>>(),
+            [("DE", 2), ("FR", 1), ("DE", 2)]
+        );
+        assert_eq!(stays[0].country_name, "Germany");
+        assert_eq!(stays[0].first_play, play_store.get_ts(0));
+        assert_eq!(stays[0].last_play, play_store.get_ts(1));
+    }
+
+    #[test]
+    fn unknown_country_does_not_end_stay() {
+        let play_store = get_play_store(&[
+            ("2023-01-01T10:00:00Z", "DE"),
+            ("2023-01-02T10:00:00Z", "ZZ"),
+            ("2023-01-03T10:00:00Z", ""),
+            ("2023-01-04T10:00:00Z", "DE"),
+        ]);
+
+        assert_eq!(get_stays(&play_store), [("DE".to_owned(), 2)]);
+    }
+
+    #[test]
+    fn orders_plays_by_time() {
+        let play_store = get_play_store(&[
+            ("2023-01-04T10:00:00Z", "DE"),
+            ("2023-01-02T10:00:00Z", "FR"),
+            ("2023-01-01T10:00:00Z", "DE"),
+            ("2023-01-03T10:00:00Z", "FR"),
+        ]);
+
+        assert_eq!(
+            get_stays(&play_store),
+            [
+                ("DE".to_owned(), 1),
+                ("FR".to_owned(), 2),
+                ("DE".to_owned(), 1)
+            ]
+        );
+    }
+}
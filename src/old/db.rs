@@ -0,0 +1,521 @@
+use crate::{
+    dedup,
+    filter::Filter,
+    group::{self, Group, GroupBy, GroupOptions},
+    plays::{Platform, PlayItem, ReasonEnd, ReasonStart},
+};
+use chrono::prelude::*;
+use rusqlite::{
+    params,
+    types::{Type, ValueRef},
+    Connection, OpenFlags, Row, Transaction,
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    path::Path,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+/// The migrations that make up the schema of the database. Each one brings the database from the version
+/// before it up to its own version (its index + 1), which is kept in SQLite's `user_version`. Migrations
+/// must never be changed once they have been released. The schema is changed by adding a new one.
+const MIGRATIONS: &[&str] = &["
+    CREATE TABLE plays (
+        id INTEGER PRIMARY KEY,
+        audiobook_chapter_title TEXT,
+        audiobook_chapter_uri TEXT,
+        audiobook_title TEXT,
+        audiobook_uri TEXT,
+        conn_country TEXT,
+        episode_name TEXT,
+        episode_show_name TEXT,
+        incognito_mode INTEGER,
+        ip_addr_decrypted TEXT,
+        master_metadata_album_album_name TEXT,
+        master_metadata_album_artist_name TEXT,
+        master_metadata_track_name TEXT,
+        ms_played INTEGER,
+        offline INTEGER,
+        offline_timestamp INTEGER,
+        platform TEXT,
+        reason_end TEXT,
+        reason_start TEXT,
+        shuffle INTEGER,
+        skipped INTEGER,
+        spotify_episode_uri TEXT,
+        spotify_track_uri TEXT,
+        -- When the play ended, as an RFC 3339 timestamp in UTC with milliseconds, so that timestamps can
+        -- be compared as text
+        ts TEXT NOT NULL,
+        user_agent_decrypted TEXT,
+        username TEXT,
+        kind TEXT NOT NULL,
+        source TEXT NOT NULL,
+        source_file TEXT
+    );
+    CREATE INDEX plays_ts ON plays (ts);
+"];
+
+/// How long a query written by the user can run for before it's interrupted
+const QUERY_TIME_LIMIT: Duration = Duration::from_secs(30);
+
+/// Every column of the plays table except for its id, in the order that they are inserted in
+const PLAY_COLUMNS: &str = "audiobook_chapter_title, audiobook_chapter_uri, audiobook_title, \
+    audiobook_uri, conn_country, episode_name, episode_show_name, incognito_mode, ip_addr_decrypted, \
+    master_metadata_album_album_name, master_metadata_album_artist_name, master_metadata_track_name, \
+    ms_played, offline, offline_timestamp, platform, reason_end, reason_start, shuffle, skipped, \
+    spotify_episode_uri, spotify_track_uri, ts, user_agent_decrypted, username, kind, source, source_file";
+
+/// The result of an SQL query, with the value of each column as JSON so that it can be sent to the
+/// frontend
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+/// Opens the database at `path`, creating it if it doesn't exist yet, and brings its schema up to date
+pub fn open_database(path: &Path) -> rusqlite::Result<Connection> {
+    let mut conn = Connection::open(path)?;
+    migrate(&mut conn)?;
+
+    Ok(conn)
+}
+
+/// Runs the migrations that haven't been run on the database yet. Each migration is run in its own
+/// transaction, so a database is never left between two versions.
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = conn.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", i + 1)?;
+        transaction.commit()?;
+    }
+
+    Ok(())
+}
+
+fn get_ts_string(ts: &DateTime<Utc>) -> String {
+    ts.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Adds plays to the database, returning how many were added
+pub fn insert_plays(
+    conn: &mut Connection,
+    play_items: impl IntoIterator<Item = impl Borrow<PlayItem>>,
+) -> rusqlite::Result<usize> {
+    let transaction = conn.transaction()?;
+    let plays_inserted = insert_plays_into(&transaction, play_items)?;
+    transaction.commit()?;
+
+    Ok(plays_inserted)
+}
+
+/// The same as `insert_plays`, as part of a transaction that has already been started
+fn insert_plays_into(
+    transaction: &Transaction,
+    play_items: impl IntoIterator<Item = impl Borrow<PlayItem>>,
+) -> rusqlite::Result<usize> {
+    let mut plays_inserted = 0;
+
+    let placeholders = vec!["?"; PLAY_COLUMNS.split(',').count()].join(", ");
+    let mut statement = transaction.prepare_cached(&format!(
+        "INSERT INTO plays ({}) VALUES ({})",
+        PLAY_COLUMNS, placeholders
+    ))?;
+
+    for play_item in play_items {
+        let play_item = play_item.borrow();
+        statement.execute(params![
+            play_item.audiobook_chapter_title,
+            play_item.audiobook_chapter_uri,
+            play_item.audiobook_title,
+            play_item.audiobook_uri,
+            play_item.conn_country,
+            play_item.episode_name,
+            play_item.episode_show_name,
+            play_item.incognito_mode,
+            play_item.ip_addr_decrypted,
+            play_item.master_metadata_album_album_name,
+            play_item.master_metadata_album_artist_name,
+            play_item.master_metadata_track_name,
+            play_item.ms_played,
+            play_item.offline,
+            play_item.offline_timestamp,
+            play_item.platform.as_ref().map(Platform::as_str),
+            play_item.reason_end.as_ref().map(ReasonEnd::as_str),
+            play_item.reason_start.as_ref().map(ReasonStart::as_str),
+            play_item.shuffle,
+            play_item.skipped,
+            play_item.spotify_episode_uri,
+            play_item.spotify_track_uri,
+            get_ts_string(&play_item.ts),
+            play_item.user_agent_decrypted,
+            play_item.username,
+            play_item.kind.as_str(),
+            play_item.source.as_str(),
+            play_item.source_file.as_deref(),
+        ])?;
+        plays_inserted += 1;
+    }
+
+    Ok(plays_inserted)
+}
+
+/// Makes a PlayItem out of a row of the plays table. Plays from the same file share the name of the file,
+/// like they do when they are first loaded.
+fn get_play_item_from_row(
+    row: &Row,
+    source_files: &mut HashMap<String, Arc<str>>,
+) -> rusqlite::Result<PlayItem> {
+    let conversion_error = |column: &str, error: String| {
+        let column_index = row.as_ref().column_index(column).unwrap_or_default();
+        rusqlite::Error::FromSqlConversionFailure(column_index, Type::Text, error.into())
+    };
+
+    let ts: String = row.get("ts")?;
+    let Ok(ts) = DateTime::parse_from_rfc3339(&ts) else {
+        return Err(conversion_error(
+            "ts",
+            format!("\"{}\" is not a timestamp.", ts),
+        ));
+    };
+
+    let kind: String = row.get("kind")?;
+    let kind = kind.parse().map_err(|e| conversion_error("kind", e))?;
+
+    let source: String = row.get("source")?;
+    let source = source.parse().map_err(|e| conversion_error("source", e))?;
+
+    let source_file: Option<String> = row.get("source_file")?;
+    let source_file = source_file.map(|source_file| {
+        Arc::clone(
+            source_files
+                .entry(source_file)
+                .or_insert_with_key(|source_file| Arc::from(source_file.as_str())),
+        )
+    });
+
+    Ok(PlayItem {
+        audiobook_chapter_title: row.get("audiobook_chapter_title")?,
+        audiobook_chapter_uri: row.get("audiobook_chapter_uri")?,
+        audiobook_title: row.get("audiobook_title")?,
+        audiobook_uri: row.get("audiobook_uri")?,
+        conn_country: row.get("conn_country")?,
+        episode_name: row.get("episode_name")?,
+        episode_show_name: row.get("episode_show_name")?,
+        incognito_mode: row.get("incognito_mode")?,
+        ip_addr_decrypted: row.get("ip_addr_decrypted")?,
+        master_metadata_album_album_name: row.get("master_metadata_album_album_name")?,
+        master_metadata_album_artist_name: row.get("master_metadata_album_artist_name")?,
+        master_metadata_track_name: row.get("master_metadata_track_name")?,
+        ms_played: row.get("ms_played")?,
+        offline: row.get("offline")?,
+        offline_timestamp: row.get("offline_timestamp")?,
+        platform: row.get::<_, Option<String>>("platform")?.map(From::from),
+        reason_end: row.get::<_, Option<String>>("reason_end")?.map(From::from),
+        reason_start: row
+            .get::<_, Option<String>>("reason_start")?
+            .map(From::from),
+        shuffle: row.get("shuffle")?,
+        skipped: row.get("skipped")?,
+        spotify_episode_uri: row.get("spotify_episode_uri")?,
+        spotify_track_uri: row.get("spotify_track_uri")?,
+        ts: ts.with_timezone(&Utc),
+        user_agent_decrypted: row.get("user_agent_decrypted")?,
+        username: row.get("username")?,
+        kind,
+        source,
+        source_file,
+    })
+}
+
+/// Gets the plays that match an SQL condition, in the order that they were added to the database
+fn get_plays_where(
+    conn: &Connection,
+    condition: &str,
+    params: impl rusqlite::Params,
+) -> rusqlite::Result<Vec<PlayItem>> {
+    let mut statement = conn.prepare(&format!(
+        "SELECT {} FROM plays WHERE {} ORDER BY id",
+        PLAY_COLUMNS, condition
+    ))?;
+
+    let mut source_files = HashMap::new();
+    let mut rows = statement.query(params)?;
+
+    let mut play_items = vec![];
+    while let Some(row) = rows.next()? {
+        play_items.push(get_play_item_from_row(row, &mut source_files)?);
+    }
+
+    Ok(play_items)
+}
+
+/// Gets every play in the database, in the order that they were added
+pub fn get_plays(conn: &Connection) -> rusqlite::Result<Vec<PlayItem>> {
+    get_plays_where(conn, "TRUE", [])
+}
+
+/// The condition that plays need to meet to be put into a group, so that plays that can't be (e.g.
+/// podcast plays when grouping by song) aren't read out of the database at all
+fn get_group_by_condition(group_by: &GroupBy) -> &'static str {
+    match group_by {
+        GroupBy::Album => {
+            "kind = 'audio' AND master_metadata_album_album_name IS NOT NULL \
+                AND master_metadata_album_artist_name IS NOT NULL"
+        }
+        GroupBy::Artist => "kind = 'audio' AND master_metadata_album_artist_name IS NOT NULL",
+        GroupBy::Song => {
+            "kind = 'audio' AND master_metadata_track_name IS NOT NULL \
+                AND master_metadata_album_artist_name IS NOT NULL"
+        }
+        GroupBy::Podcast => "episode_show_name IS NOT NULL",
+        GroupBy::PodcastEpisode => "episode_name IS NOT NULL AND episode_show_name IS NOT NULL",
+        GroupBy::Audiobook => "audiobook_title IS NOT NULL",
+        GroupBy::AudiobookChapter => {
+            "audiobook_chapter_title IS NOT NULL AND audiobook_title IS NOT NULL"
+        }
+        GroupBy::Platform => "platform IS NOT NULL",
+        GroupBy::Country => "conn_country IS NOT NULL AND upper(conn_country) != 'ZZ'",
+        GroupBy::HourOfDay
+        | GroupBy::DayOfWeek
+        | GroupBy::Month
+        | GroupBy::Year
+        | GroupBy::Days(_) => "TRUE",
+    }
+}
+
+/// Filters and groups the plays in the database, the same as `filter::get_play_items_between_dates` and
+/// `group::get_grouped_data` do with plays in memory. The filter is applied by the query, so only the plays
+/// that end up in a group are read out of the database. Without a date range, every play is used. Like in
+/// `group::get_known_ms_played`, only Spotify plays are left out for not having a length.
+pub fn get_grouped_data(
+    conn: &Connection,
+    filter: &Filter,
+    group_by: &GroupBy,
+    options: &GroupOptions,
+) -> rusqlite::Result<Vec<Group>> {
+    let condition = format!(
+        "(ms_played IS NOT NULL OR source != 'spotify') AND {}",
+        get_group_by_condition(group_by)
+    );
+
+    let play_items = match filter.date_range {
+        Some((start_date, end_date)) => get_plays_where(
+            conn,
+            &format!("{} AND ts BETWEEN ?1 AND ?2", condition),
+            [get_ts_string(&start_date), get_ts_string(&end_date)],
+        )?,
+        None => get_plays_where(conn, &condition, [])?,
+    };
+
+    Ok(group::get_grouped_data(group_by, options, play_items))
+}
+
+fn get_json_value(value: ValueRef) -> Value {
+    match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(integer) => Value::from(integer),
+        ValueRef::Real(real) => Value::from(real),
+        ValueRef::Text(text) => Value::from(String::from_utf8_lossy(text)),
+        ValueRef::Blob(blob) => Value::from(blob),
+    }
+}
+
+/// Runs an SQL query written by the user over the database at `path`, e.g. `SELECT platform, COUNT(*) FROM
+/// plays GROUP BY platform`. The database is opened read-only just for the query, so nothing can be
+/// changed by it (not even by attaching another database), and the query is interrupted if it takes
+/// longer than `QUERY_TIME_LIMIT`.
+pub fn run_query(path: &Path, sql: &str) -> rusqlite::Result<QueryResult> {
+    let conn = Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+
+    let interrupt_handle = conn.get_interrupt_handle();
+    let (query_finished, wait_for_query) = mpsc::channel::<()>();
+    let timer = thread::spawn(move || {
+        if let Err(RecvTimeoutError::Timeout) = wait_for_query.recv_timeout(QUERY_TIME_LIMIT) {
+            interrupt_handle.interrupt();
+        }
+    });
+
+    let query_result = get_query_result(&conn, sql);
+
+    // Dropping the sender stops the timer
+    drop(query_finished);
+    let _ = timer.join();
+
+    query_result
+}
+
+fn get_query_result(conn: &Connection, sql: &str) -> rusqlite::Result<QueryResult> {
+    let mut statement = conn.prepare(sql)?;
+    if !statement.readonly() {
+        return Err(rusqlite::Error::InvalidQuery);
+    }
+
+    let columns: Vec<String> = statement
+        .column_names()
+        .into_iter()
+        .map(str::to_owned)
+        .collect();
+
+    let mut rows = statement.query([])?;
+    let mut result_rows = vec![];
+    while let Some(row) = rows.next()? {
+        let mut result_row = Vec::with_capacity(columns.len());
+        for i in 0..columns.len() {
+            result_row.push(get_json_value(row.get_ref(i)?));
+        }
+        result_rows.push(result_row);
+    }
+
+    Ok(QueryResult {
+        columns,
+        rows: result_rows,
+    })
+}
+
+/// Adds the plays that aren't in the database yet to it, and removes the ones that are from `play_items`.
+/// Plays are looked up by when they ended, which is indexed, so the rest of the table isn't read. Returns
+/// how many plays were added.
+pub fn insert_new_plays(
+    conn: &mut Connection,
+    play_items: &mut Vec<PlayItem>,
+) -> rusqlite::Result<usize> {
+    let mut is_in_database = Vec::with_capacity(play_items.len());
+
+    let transaction = conn.transaction()?;
+    {
+        let mut statement =
+            transaction.prepare(&format!("SELECT {} FROM plays WHERE ts = ?1", PLAY_COLUMNS))?;
+        let mut source_files = HashMap::new();
+
+        for play_item in play_items.iter() {
+            let mut rows = statement.query([get_ts_string(&play_item.ts)])?;
+
+            let mut found = false;
+            while let Some(row) = rows.next()? {
+                let database_play_item = get_play_item_from_row(row, &mut source_files)?;
+                if dedup::is_same_play(&database_play_item, play_item) {
+                    found = true;
+                    break;
+                }
+            }
+            drop(rows);
+
+            // Plays are added as they are checked, so that duplicates within `play_items` are found too
+            if !found {
+                insert_plays_into(&transaction, [play_item])?;
+            }
+            is_in_database.push(found);
+        }
+    }
+    transaction.commit()?;
+
+    let mut is_in_database = is_in_database.into_iter();
+    play_items.retain(|_| !is_in_database.next().unwrap_or(false));
+
+    Ok(play_items.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        play_store::PlayStore,
+        plays::{self, PlayItemKind, PlayItemSource},
+    };
+
+    fn get_play_item(
+        ts: &str,
+        ms_played: Option<u64>,
+        track_name: &str,
+        source: PlayItemSource,
+    ) -> PlayItem {
+        let record = format!(
+            r#"{{"ts": "{}", "master_metadata_track_name": "{}",
+            "master_metadata_album_artist_name": "Artist", "master_metadata_album_album_name": "Album"}}"#,
+            ts, track_name
+        );
+
+        let mut play_item =
+            plays::get_play_item_from_record_bytes(record.as_bytes(), PlayItemKind::Audio).unwrap();
+        play_item.ms_played = ms_played;
+        play_item.source = source;
+        play_item
+    }
+
+    #[test]
+    fn grouping_in_database_matches_grouping_in_memory() {
+        let play_items = vec![
+            get_play_item(
+                "2023-01-01T10:00:00Z",
+                Some(1000),
+                "A",
+                PlayItemSource::Spotify,
+            ),
+            // Spotify always knows how long a play lasted, so this play is left out
+            get_play_item("2023-01-01T11:00:00Z", None, "A", PlayItemSource::Spotify),
+            get_play_item("2023-01-01T12:00:00Z", None, "A", PlayItemSource::LastFm),
+            get_play_item(
+                "2023-01-01T13:00:00Z",
+                None,
+                "B",
+                PlayItemSource::ListenBrainz,
+            ),
+            get_play_item(
+                "2023-01-01T14:00:00Z",
+                Some(3000),
+                "B",
+                PlayItemSource::ListenBrainz,
+            ),
+            get_play_item(
+                "2023-01-01T15:00:00Z",
+                None,
+                "C",
+                PlayItemSource::AppleMusic,
+            ),
+        ];
+
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        insert_plays(&mut conn, &play_items).unwrap();
+
+        let play_store = PlayStore::from_play_items(&play_items);
+        let play_indices: Vec<usize> = (0..play_store.len()).collect();
+        let filter = Filter::default();
+        let options = GroupOptions::default();
+
+        for group_by in [GroupBy::Song, GroupBy::Artist, GroupBy::Year] {
+            let database_grouped_data =
+                get_grouped_data(&conn, &filter, &group_by, &options).unwrap();
+            let memory_grouped_data =
+                group::get_grouped_data_from_store(&group_by, &options, &play_store, &play_indices);
+
+            assert_eq!(
+                serde_json::to_value(&database_grouped_data).unwrap(),
+                serde_json::to_value(&memory_grouped_data).unwrap(),
+                "{:?}",
+                group_by
+            );
+        }
+
+        let artist_grouped_data =
+            get_grouped_data(&conn, &filter, &GroupBy::Artist, &options).unwrap();
+        assert_eq!(artist_grouped_data.len(), 1);
+        assert_eq!(artist_grouped_data[0].get_aggregated_data().play_count, 5);
+    }
+}
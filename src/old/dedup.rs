@@ -0,0 +1,371 @@
+use crate::{
+    play_store::PlayStore,
+    plays::{Platform, PlayItem, PlayItemSource},
+};
+use chrono::{prelude::*, Duration};
+use serde::Serialize;
+use std::{
+    cmp::Reverse,
+    collections::{hash_map::Entry, HashMap},
+    sync::Arc,
+};
+
+/// How far apart the start of a play from another service and the start of a Spotify play of the same
+/// song can be for them to be the same play. Services don't agree on exactly when a play started (Last.fm
+/// scrobbles are sent by the player, and Spotify's `ts` is when the play ended).
+const CROSS_SOURCE_TOLERANCE_SECONDS: i64 = 120;
+
+/// What identifies a play when looking for duplicates. The same play can show up more than once when
+/// exports that cover overlapping periods are loaded together, but two different plays can't have ended
+/// at the same time with the same URI, length and platform.
+///
+/// Plays from the "Account data" export have no URI or platform, so their names are used in place of the
+/// URI.
+#[derive(PartialEq, Eq, Hash)]
+struct PlayIdentity<'a> {
+    ts: DateTime<Utc>,
+    uri: Option<&'a str>,
+    names: Option<[Option<&'a str>; 3]>,
+    ms_played: Option<u64>,
+    platform: Option<&'a Platform>,
+}
+
+impl<'a> PlayIdentity<'a> {
+    fn new(play_item: &'a PlayItem) -> Self {
+        let uri = play_item
+            .spotify_track_uri
+            .as_deref()
+            .or(play_item.spotify_episode_uri.as_deref())
+            .or(play_item.audiobook_chapter_uri.as_deref());
+
+        let names = match uri {
+            Some(_) => None,
+            None => Some([
+                play_item.master_metadata_track_name.as_deref(),
+                play_item.master_metadata_album_artist_name.as_deref(),
+                play_item.episode_name.as_deref(),
+            ]),
+        };
+
+        PlayIdentity {
+            ts: play_item.ts,
+            uri,
+            names,
+            ms_played: play_item.ms_played,
+            platform: play_item.platform.as_ref(),
+        }
+    }
+
+    /// The same as `new`, for the play at `index` in a PlayStore
+    fn from_store(play_store: &'a PlayStore, index: usize) -> Self {
+        let uri = play_store
+            .get_spotify_track_uri(index)
+            .or(play_store.get_spotify_episode_uri(index))
+            .or(play_store.get_audiobook_chapter_uri(index));
+
+        let names = match uri {
+            Some(_) => None,
+            None => Some([
+                play_store.get_track_name(index),
+                play_store.get_artist_name(index),
+                play_store.get_episode_name(index),
+            ]),
+        };
+
+        PlayIdentity {
+            ts: play_store.get_ts(index),
+            uri,
+            names,
+            ms_played: play_store.get_ms_played(index),
+            platform: play_store.get_platform(index),
+        }
+    }
+}
+
+/// How many duplicates of plays from one file were found in another (or the same) file. Files are given by
+/// their paths, as in `PlayItem::source_file`.
+#[derive(Clone, Debug, Serialize)]
+pub struct DuplicateSource {
+    /// The file that the kept copy of each play came from
+    pub kept_from: Option<Arc<str>>,
+    /// The file that the removed copy of each play came from
+    pub duplicate_from: Option<Arc<str>>,
+    pub count: usize,
+}
+
+type SourceFilePair = (Option<Arc<str>>, Option<Arc<str>>);
+
+/// What was removed by `remove_duplicate_plays`
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DedupReport {
+    pub duplicate_count: usize,
+    pub duplicate_sources: Vec<DuplicateSource>,
+}
+
+impl DedupReport {
+    /// Adds what was removed in a later round of deduplication to this report
+    pub fn append(&mut self, other: DedupReport) {
+        self.duplicate_count += other.duplicate_count;
+        self.duplicate_sources.extend(other.duplicate_sources);
+    }
+}
+
+/// Whether two plays are the same play, e.g. from exports that cover overlapping periods
+pub fn is_same_play(play_item: &PlayItem, other_play_item: &PlayItem) -> bool {
+    PlayIdentity::new(play_item) == PlayIdentity::new(other_play_item)
+}
+
+/// Removes plays that are identical to an earlier play, keeping the first copy of each. The order of the
+/// remaining plays is unchanged.
+pub fn remove_duplicate_plays(play_items: &mut Vec<PlayItem>) -> DedupReport {
+    remove_duplicates_of_existing_plays(&PlayStore::default(), play_items)
+}
+
+/// Removes plays from `new_play_items` that are identical to one of the plays in `existing_plays`, or to
+/// an earlier play in `new_play_items`. This is used to add plays to ones that were already loaded without
+/// going through all of the existing plays again.
+pub fn remove_duplicates_of_existing_plays(
+    existing_plays: &PlayStore,
+    new_play_items: &mut Vec<PlayItem>,
+) -> DedupReport {
+    let mut is_duplicate = vec![false; new_play_items.len()];
+    // Counts of duplicates for each (file of the kept copy, file of the duplicate) pair
+    let mut duplicate_counts: HashMap<SourceFilePair, usize> = HashMap::new();
+
+    // The source file of the first play with each identity
+    let mut first_plays: HashMap<PlayIdentity, Option<&Arc<str>>> = (0..existing_plays.len())
+        .map(|index| {
+            (
+                PlayIdentity::from_store(existing_plays, index),
+                existing_plays.get_source_file(index),
+            )
+        })
+        .collect();
+
+    for (i, play_item) in new_play_items.iter().enumerate() {
+        match first_plays.entry(PlayIdentity::new(play_item)) {
+            Entry::Occupied(first_play) => {
+                is_duplicate[i] = true;
+
+                let sources = (first_play.get().cloned(), play_item.source_file.clone());
+                *duplicate_counts.entry(sources).or_default() += 1;
+            }
+            Entry::Vacant(first_play) => {
+                first_play.insert(play_item.source_file.as_ref());
+            }
+        }
+    }
+
+    let mut is_duplicate = is_duplicate.into_iter();
+    new_play_items.retain(|_| !is_duplicate.next().unwrap_or(false));
+
+    get_dedup_report(duplicate_counts)
+}
+
+/// Removes plays from another service (like Last.fm) that are also among the loaded Spotify plays, which
+/// happens when the service was following what was played on Spotify. Plays are the same if they are of the
+/// same song (by artist and track name, ignoring case) and started within `CROSS_SOURCE_TOLERANCE_SECONDS`
+/// of each other. Each Spotify play can only match one imported play.
+///
+/// A play's start is its `ts` less its `ms_played`, or just its `ts` if its length isn't known (which is
+/// when plays from other services started).
+pub fn remove_imported_plays_already_on_spotify(
+    spotify_plays: &PlayStore,
+    imported_play_items: &mut Vec<PlayItem>,
+) -> DedupReport {
+    let get_song_key = |artist_name: Option<&str>, track_name: Option<&str>| {
+        Some((artist_name?.to_lowercase(), track_name?.to_lowercase()))
+    };
+    let get_started_at = |ts: DateTime<Utc>, ms_played: Option<u64>| {
+        ts - Duration::milliseconds(ms_played.unwrap_or_default() as i64)
+    };
+    let tolerance = Duration::seconds(CROSS_SOURCE_TOLERANCE_SECONDS);
+
+    // When each song was started on Spotify, the file that each play came from and whether it has been
+    // matched yet
+    type SpotifyStart<'a> = (DateTime<Utc>, Option<&'a Arc<str>>, bool);
+    let mut spotify_starts: HashMap<(String, String), Vec<SpotifyStart>> = HashMap::new();
+    for index in (0..spotify_plays.len())
+        .filter(|&index| spotify_plays.get_source(index) == PlayItemSource::Spotify)
+    {
+        let Some(song_key) = get_song_key(
+            spotify_plays.get_artist_name(index),
+            spotify_plays.get_track_name(index),
+        ) else {
+            continue;
+        };
+
+        spotify_starts.entry(song_key).or_default().push((
+            get_started_at(
+                spotify_plays.get_ts(index),
+                spotify_plays.get_ms_played(index),
+            ),
+            spotify_plays.get_source_file(index),
+            false,
+        ));
+    }
+    for song_starts in spotify_starts.values_mut() {
+        song_starts.sort_unstable_by_key(|(started_at, _, _)| *started_at);
+    }
+
+    let mut is_duplicate = vec![false; imported_play_items.len()];
+    let mut duplicate_counts: HashMap<SourceFilePair, usize> = HashMap::new();
+
+    for (i, play_item) in imported_play_items.iter().enumerate() {
+        if play_item.source == PlayItemSource::Spotify {
+            continue;
+        }
+        let Some(song_starts) = get_song_key(
+            play_item.master_metadata_album_artist_name.as_deref(),
+            play_item.master_metadata_track_name.as_deref(),
+        )
+        .and_then(|song_key| spotify_starts.get_mut(&song_key)) else {
+            continue;
+        };
+
+        let started_at = get_started_at(play_item.ts, play_item.ms_played);
+        let first_candidate = song_starts.partition_point(|(spotify_started_at, _, _)| {
+            *spotify_started_at < started_at - tolerance
+        });
+        let closest_spotify_start = song_starts[first_candidate..]
+            .iter_mut()
+            .take_while(|(spotify_started_at, _, _)| *spotify_started_at <= started_at + tolerance)
+            .filter(|(_, _, matched)| !matched)
+            .min_by_key(|(spotify_started_at, _, _)| {
+                (*spotify_started_at - started_at).num_milliseconds().abs()
+            });
+        let Some((_, spotify_source_file, matched)) = closest_spotify_start else {
+            continue;
+        };
+
+        *matched = true;
+        is_duplicate[i] = true;
+        let sources = (spotify_source_file.cloned(), play_item.source_file.clone());
+        *duplicate_counts.entry(sources).or_default() += 1;
+    }
+
+    let mut is_duplicate = is_duplicate.into_iter();
+    imported_play_items.retain(|_| !is_duplicate.next().unwrap_or(false));
+
+    get_dedup_report(duplicate_counts)
+}
+
+/// Makes a DedupReport out of the counts of duplicates for each pair of files, listing the pairs with the
+/// most duplicates first
+fn get_dedup_report(duplicate_counts: HashMap<SourceFilePair, usize>) -> DedupReport {
+    let mut duplicate_sources: Vec<DuplicateSource> = duplicate_counts
+        .into_iter()
+        .map(|((kept_from, duplicate_from), count)| DuplicateSource {
+            kept_from,
+            duplicate_from,
+            count,
+        })
+        .collect();
+    duplicate_sources.sort_by_key(|source| Reverse(source.count));
+
+    DedupReport {
+        duplicate_count: duplicate_sources.iter().map(|source| source.count).sum(),
+        duplicate_sources,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plays::{self, PlayItemKind};
+
+    fn get_play_item(ts: &str, ms_played: u64, platform: &str, source_file: &str) -> PlayItem {
+        let record = format!(
+            r#"{{"ts": "{}", "ms_played": {}, "platform": "{}", "master_metadata_track_name": "Song",
+            "master_metadata_album_artist_name": "Artist", "spotify_track_uri": "spotify:track:1"}}"#,
+            ts, ms_played, platform
+        );
+
+        let mut play_item =
+            plays::get_play_item_from_record_bytes(record.as_bytes(), PlayItemKind::Audio).unwrap();
+        play_item.source_file = Some(Arc::from(source_file));
+        play_item
+    }
+
+    /// A Last.fm scrobble of the same song, which only knows when it started
+    fn get_imported_play_item(ts: &str) -> PlayItem {
+        let mut play_item = get_play_item(ts, 0, "", "scrobbles.csv");
+        play_item.ms_played = None;
+        play_item.platform = None;
+        play_item.spotify_track_uri = None;
+        play_item.source = PlayItemSource::LastFm;
+        play_item
+    }
+
+    #[test]
+    fn removes_play_loaded_from_two_files() {
+        let existing_plays = PlayStore::from_play_items(&[get_play_item(
+            "2023-01-01T10:00:00Z",
+            1000,
+            "android",
+            "a.json",
+        )]);
+        let mut new_play_items = vec![
+            get_play_item("2023-01-01T10:00:00Z", 1000, "android", "b.json"),
+            get_play_item("2023-01-01T11:00:00Z", 1000, "android", "b.json"),
+        ];
+
+        let dedup_report =
+            remove_duplicates_of_existing_plays(&existing_plays, &mut new_play_items);
+
+        assert_eq!(new_play_items.len(), 1);
+        assert_eq!(new_play_items[0].ts.hour(), 11);
+        assert_eq!(dedup_report.duplicate_count, 1);
+        assert_eq!(
+            dedup_report.duplicate_sources[0].kept_from.as_deref(),
+            Some("a.json")
+        );
+        assert_eq!(
+            dedup_report.duplicate_sources[0].duplicate_from.as_deref(),
+            Some("b.json")
+        );
+    }
+
+    #[test]
+    fn keeps_plays_on_other_platform_or_with_other_length() {
+        let existing_plays = PlayStore::from_play_items(&[get_play_item(
+            "2023-01-01T10:00:00Z",
+            1000,
+            "android",
+            "a.json",
+        )]);
+        let mut new_play_items = vec![
+            get_play_item("2023-01-01T10:00:00Z", 1000, "ios", "b.json"),
+            get_play_item("2023-01-01T10:00:00Z", 2000, "android", "b.json"),
+        ];
+
+        let dedup_report =
+            remove_duplicates_of_existing_plays(&existing_plays, &mut new_play_items);
+
+        assert_eq!(new_play_items.len(), 2);
+        assert_eq!(dedup_report.duplicate_count, 0);
+    }
+
+    #[test]
+    fn removes_imported_plays_started_within_tolerance() {
+        // Started at 10:00:00
+        let spotify_plays = PlayStore::from_play_items(&[get_play_item(
+            "2023-01-01T10:05:00Z",
+            300_000,
+            "android",
+            "a.json",
+        )]);
+
+        let mut imported_play_items = vec![get_imported_play_item("2023-01-01T10:01:59Z")];
+        let dedup_report =
+            remove_imported_plays_already_on_spotify(&spotify_plays, &mut imported_play_items);
+        assert!(imported_play_items.is_empty());
+        assert_eq!(dedup_report.duplicate_count, 1);
+
+        let mut imported_play_items = vec![get_imported_play_item("2023-01-01T10:02:01Z")];
+        let dedup_report =
+            remove_imported_plays_already_on_spotify(&spotify_plays, &mut imported_play_items);
+        assert_eq!(imported_play_items.len(), 1);
+        assert_eq!(dedup_report.duplicate_count, 0);
+    }
+}
@@ -0,0 +1,295 @@
+use crate::plays::Platform;
+use serde::Serialize;
+use std::fmt::Display;
+
+/// The kind of device that a play happened on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceFamily {
+    Phone,
+    Tablet,
+    Computer,
+    WebBrowser,
+    Speaker,
+    Tv,
+    GameConsole,
+    Car,
+    Wearable,
+    Unknown,
+}
+
+impl DeviceFamily {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Phone => "phone",
+            Self::Tablet => "tablet",
+            Self::Computer => "computer",
+            Self::WebBrowser => "web_browser",
+            Self::Speaker => "speaker",
+            Self::Tv => "tv",
+            Self::GameConsole => "game_console",
+            Self::Car => "car",
+            Self::Wearable => "wearable",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+impl Display for DeviceFamily {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Phone => "Phone",
+            Self::Tablet => "Tablet",
+            Self::Computer => "Computer",
+            Self::WebBrowser => "Web browser",
+            Self::Speaker => "Speaker",
+            Self::Tv => "TV",
+            Self::GameConsole => "Game console",
+            Self::Car => "Car",
+            Self::Wearable => "Wearable",
+            Self::Unknown => "Unknown device",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// A device that plays happened on, normalized from the platform string that Spotify gives for each play.
+/// The OS is only its name (e.g. "Android" or "macOS") and not its version, so that a device keeps being
+/// the same device after it's updated.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Device {
+    pub family: DeviceFamily,
+    pub os: Option<String>,
+    /// The manufacturer and model of a phone, tablet or speaker (e.g. "samsung SM-G960U" or "iPhone10,4"),
+    /// or the browser that the web player was used in
+    pub model: Option<String>,
+}
+
+impl From<&Platform> for Device {
+    fn from(platform: &Platform) -> Self {
+        let platform = platform.as_str().trim();
+        let lowercase_platform = platform.to_lowercase();
+
+        if lowercase_platform.starts_with("web_player")
+            || lowercase_platform.starts_with("webplayer")
+        {
+            get_web_player_device(platform)
+        } else if lowercase_platform.starts_with("partner") {
+            get_partner_device(platform, &lowercase_platform)
+        } else if lowercase_platform.starts_with("android") {
+            // e.g. "Android OS 9 API 28 (samsung, SM-G960U)" or "Android-tablet OS 5.1 API 22 (Amazon,
+            // KFGIWI)"
+            let family = if lowercase_platform.starts_with("android-tablet") {
+                DeviceFamily::Tablet
+            } else {
+                DeviceFamily::Phone
+            };
+
+            Device {
+                family,
+                os: Some("Android".to_owned()),
+                model: get_parenthesized(platform).map(|model| {
+                    model
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|part| !part.is_empty())
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                }),
+            }
+        } else if lowercase_platform.starts_with("ios") {
+            // e.g. "iOS 12.1 (iPhone10,4)", where the comma is part of the model's name
+            let model = get_parenthesized(platform).map(str::to_owned);
+            let family = match &model {
+                Some(model) if model.starts_with("iPad") => DeviceFamily::Tablet,
+                Some(model) if model.starts_with("Watch") => DeviceFamily::Wearable,
+                _ => DeviceFamily::Phone,
+            };
+
+            Device {
+                family,
+                os: Some("iOS".to_owned()),
+                model,
+            }
+        } else if let Some(os) = get_os_name(&lowercase_platform) {
+            // e.g. "OS X 10.15.7 [x86 8]" or "Windows 10 (10.0.19041; x64)"
+            Device {
+                family: DeviceFamily::Computer,
+                os: Some(os.to_owned()),
+                model: None,
+            }
+        } else {
+            // Platforms that aren't recognized are kept apart from each other by their whole string
+            Device {
+                family: DeviceFamily::Unknown,
+                os: None,
+                model: Some(platform.to_owned()).filter(|platform| !platform.is_empty()),
+            }
+        }
+    }
+}
+
+/// Gets the text between the first "(" and the last ")" in a platform string
+fn get_parenthesized(platform: &str) -> Option<&str> {
+    let start = platform.find('(')? + 1;
+    let end = platform.rfind(')')?;
+
+    platform
+        .get(start..end)
+        .map(str::trim)
+        .filter(|parenthesized| !parenthesized.is_empty())
+}
+
+/// Gets the name of the desktop OS at the start of a lowercase platform string, or of the OS that a web
+/// player was used on
+fn get_os_name(lowercase_platform: &str) -> Option<&'static str> {
+    if lowercase_platform.starts_with("windows") {
+        Some("Windows")
+    } else if ["os x", "osx", "macos", "mac os"]
+        .iter()
+        .any(|prefix| lowercase_platform.starts_with(prefix))
+    {
+        Some("macOS")
+    } else if lowercase_platform.starts_with("linux") {
+        Some("Linux")
+    } else if lowercase_platform.starts_with("chrome os") || lowercase_platform.starts_with("cros")
+    {
+        Some("Chrome OS")
+    } else if lowercase_platform.starts_with("android") {
+        Some("Android")
+    } else if lowercase_platform.starts_with("ios") {
+        Some("iOS")
+    } else {
+        None
+    }
+}
+
+/// e.g. "web_player windows 10;chrome 110" or "web_player osx 10.15.7;chrome 88.0.4324.150;desktop"
+fn get_web_player_device(platform: &str) -> Device {
+    let mut parts = platform.split(';');
+
+    let os = parts.next().and_then(|os_part| {
+        let os_part = os_part.split_once(' ').map_or("", |(_, os_part)| os_part);
+        get_os_name(&os_part.trim().to_lowercase()).map(str::to_owned)
+    });
+
+    let model = parts.next().and_then(|browser_part| {
+        let browser = browser_part.split_whitespace().next()?;
+
+        Some(match browser.to_lowercase().as_str() {
+            "chrome" => "Chrome".to_owned(),
+            "edge" => "Edge".to_owned(),
+            "firefox" => "Firefox".to_owned(),
+            "opera" => "Opera".to_owned(),
+            "safari" => "Safari".to_owned(),
+            _ => browser.to_owned(),
+        })
+    });
+
+    Device {
+        family: DeviceFamily::WebBrowser,
+        os,
+        model,
+    }
+}
+
+/// Devices made by Spotify's partners, e.g. "Partner sonos_ZPS1 Sonos;Play:1" or "Partner android_tv
+/// Nvidia;SHIELD Android TV". The first word after "Partner" is the partner's ID for the kind of device,
+/// and after it come the manufacturer and model.
+fn get_partner_device(platform: &str, lowercase_platform: &str) -> Device {
+    let model = platform.splitn(3, ' ').nth(2).map(|model| {
+        model
+            .split(';')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+            .take(2)
+            .collect::<Vec<_>>()
+            .join(" ")
+    });
+
+    let is_kind = |keywords: &[&str]| {
+        keywords
+            .iter()
+            .any(|keyword| lowercase_platform.contains(keyword))
+    };
+
+    let family = if is_kind(&["_tv", "tv_", "television", "roku", "shield"]) {
+        DeviceFamily::Tv
+    } else if is_kind(&["playstation", "xbox"]) {
+        DeviceFamily::GameConsole
+    } else if is_kind(&[
+        "car_thing",
+        "tesla",
+        "android_auto",
+        "carplay",
+        "automotive",
+    ]) {
+        DeviceFamily::Car
+    } else if is_kind(&["watch", "wear"]) {
+        DeviceFamily::Wearable
+    } else if is_kind(&[
+        "sonos",
+        "speaker",
+        "echo",
+        "amazon",
+        "bose",
+        "cast_audio",
+        "audio",
+    ]) {
+        DeviceFamily::Speaker
+    } else {
+        DeviceFamily::Unknown
+    };
+
+    Device {
+        family,
+        os: None,
+        model: model.filter(|model| !model.is_empty()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_device(platform: &str) -> Device {
+        Device::from(&Platform::from(platform.to_owned()))
+    }
+
+    #[test]
+    fn android_phone() {
+        assert_eq!(
+            get_device("Android OS 9 API 28 (samsung, SM-G960U)"),
+            Device {
+                family: DeviceFamily::Phone,
+                os: Some("Android".to_owned()),
+                model: Some("samsung SM-G960U".to_owned()),
+            }
+        );
+    }
+
+    #[test]
+    fn mac_computer() {
+        assert_eq!(
+            get_device("OS X 10.15.7 [x86 8]"),
+            Device {
+                family: DeviceFamily::Computer,
+                os: Some("macOS".to_owned()),
+                model: None,
+            }
+        );
+    }
+
+    #[test]
+    fn web_player() {
+        assert_eq!(
+            get_device("web_player windows 10;chrome 110"),
+            Device {
+                family: DeviceFamily::WebBrowser,
+                os: Some("Windows".to_owned()),
+                model: Some("Chrome".to_owned()),
+            }
+        );
+    }
+}
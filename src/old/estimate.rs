@@ -0,0 +1,93 @@
+use crate::{
+    play_store::PlayStore,
+    plays::{PlayItem, PlayItemSource},
+};
+use chrono::Duration;
+use std::collections::HashMap;
+
+/// What is known about a song from its Spotify plays
+#[derive(Default)]
+struct KnownSong {
+    ms_played: Vec<u64>,
+    album_name: Option<String>,
+    spotify_track_uri: Option<String>,
+}
+
+/// Fills in what other services (like Last.fm) don't know about their plays using Spotify plays of the
+/// same song (matched by artist and track name, ignoring case). The song's URI and album are filled in,
+/// so that it's grouped together with its Spotify plays. If a play's length isn't known, it's estimated
+/// as the median length of the song's unskipped Spotify plays and its `ts` is moved from when it started
+/// to when it would have ended. Without any such plays, the length is left as `None` and `ts` as when the
+/// play started, rather than making up a length.
+///
+/// Only fields that are missing are filled in, so this can be used more than once.
+pub fn estimate_missing_fields(imported_play_items: &mut [PlayItem], loaded_plays: &PlayStore) {
+    let get_song_key = |artist_name: Option<&str>, track_name: Option<&str>| {
+        Some((artist_name?.to_lowercase(), track_name?.to_lowercase()))
+    };
+
+    let mut known_songs: HashMap<(String, String), KnownSong> = HashMap::new();
+    for index in (0..loaded_plays.len())
+        .filter(|&index| loaded_plays.get_source(index) == PlayItemSource::Spotify)
+    {
+        let Some(song_key) = get_song_key(
+            loaded_plays.get_artist_name(index),
+            loaded_plays.get_track_name(index),
+        ) else {
+            continue;
+        };
+
+        let known_song = known_songs.entry(song_key).or_default();
+
+        // Skipped plays say more about the listener than about how long the song is
+        if let (Some(ms_played), false) = (
+            loaded_plays.get_ms_played(index),
+            loaded_plays.get_skipped(index) == Some(true),
+        ) {
+            known_song.ms_played.push(ms_played);
+        }
+        if known_song.album_name.is_none() {
+            known_song.album_name = loaded_plays.get_album_name(index).map(str::to_owned);
+        }
+        if known_song.spotify_track_uri.is_none() {
+            known_song.spotify_track_uri =
+                loaded_plays.get_spotify_track_uri(index).map(str::to_owned);
+        }
+    }
+
+    for known_song in known_songs.values_mut() {
+        known_song.ms_played.sort_unstable();
+    }
+
+    for play_item in imported_play_items
+        .iter_mut()
+        .filter(|play_item| play_item.source != PlayItemSource::Spotify)
+    {
+        let known_song = get_song_key(
+            play_item.master_metadata_album_artist_name.as_deref(),
+            play_item.master_metadata_track_name.as_deref(),
+        )
+        .and_then(|song_key| known_songs.get(&song_key));
+
+        if let Some(known_song) = known_song {
+            if play_item.master_metadata_album_album_name.is_none() {
+                play_item.master_metadata_album_album_name = known_song.album_name.clone();
+            }
+            if play_item.spotify_track_uri.is_none() {
+                play_item.spotify_track_uri = known_song.spotify_track_uri.clone();
+            }
+        }
+
+        if play_item.ms_played.is_some() {
+            continue;
+        }
+
+        let Some(&ms_played) = known_song
+            .and_then(|known_song| known_song.ms_played.get(known_song.ms_played.len() / 2)) else {
+            continue;
+        };
+
+        play_item.ms_played = Some(ms_played);
+        play_item.ts += Duration::milliseconds(ms_played as i64);
+    }
+}
@@ -1,21 +1,13 @@
-use crate::plays::PlayItem;
+use crate::{play_store::PlayStore, plays::PlayItem};
 use chrono::prelude::*;
 
+#[derive(Default)]
 pub struct Filter {
     pub date_range: Option<(DateTime<Utc>, DateTime<Utc>)>,
     pub date_range_boundaries: (DateTime<Utc>, DateTime<Utc>),
     // pub
 }
 
-impl Default for Filter {
-    fn default() -> Self {
-        Filter {
-            date_range: None,
-            date_range_boundaries: (DateTime::default(), DateTime::default()),
-        }
-    }
-}
-
 // impl Filter {
 //     fn
 // }
@@ -24,7 +16,7 @@ impl Default for Filter {
 // "filter for all artists with skip % below 25%", then sort those results with the normal sorts
 
 pub fn get_play_items_between_dates(
-    all_play_items: &Vec<PlayItem>,
+    all_play_items: &[PlayItem],
     filter: &Filter,
 ) -> Vec<PlayItem> {
     let (start_date, end_date) = match filter.date_range {
@@ -35,14 +27,27 @@ pub fn get_play_items_between_dates(
     let mut play_items_in_range: Vec<PlayItem> = Vec::new();
 
     for single_played_item in all_play_items.iter() {
-        if let Some(ts) = &single_played_item.ts {
-            if let Ok(timestamp_dt) = ts.parse::<DateTime<Utc>>() {
-                if start_date <= timestamp_dt && timestamp_dt <= end_date {
-                    play_items_in_range.push(single_played_item.clone());
-                }
-            }
+        if start_date <= single_played_item.ts && single_played_item.ts <= end_date {
+            play_items_in_range.push(single_played_item.clone());
         }
     }
 
     play_items_in_range
 }
+
+/// The same as `get_play_items_between_dates`, but for plays in a PlayStore. Returns the indices of the
+/// plays in the date range instead of copies of them.
+pub fn get_play_indices_between_dates(play_store: &PlayStore, filter: &Filter) -> Vec<usize> {
+    let (start_date, end_date) = match filter.date_range {
+        Some((start_date, end_date)) => (start_date, end_date),
+        None => filter.date_range_boundaries,
+    };
+    let (start_millis, end_millis) = (start_date.timestamp_millis(), end_date.timestamp_millis());
+
+    (0..play_store.len())
+        .filter(|&index| {
+            let ts_millis = play_store.get_ts_millis(index);
+            start_millis <= ts_millis && ts_millis <= end_millis
+        })
+        .collect()
+}
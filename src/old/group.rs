@@ -1,23 +1,164 @@
 use std::{collections::HashMap, fmt::Display};
 
-use serde::Serialize;
+use chrono::{prelude::*, Duration};
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
-use crate::{plays::PlayItem, util};
+use crate::{
+    country,
+    device::{Device, DeviceFamily},
+    play_store::{GroupSymbolKey, PlayStore},
+    plays::{PlayItem, PlayItemKind, PlayItemSource, ReasonEnd, ReasonStart},
+    sort::SortSpotifyDataBy,
+    util,
+};
 
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum GroupBy {
     Album,
     Artist,
     Song,
     Podcast,
     PodcastEpisode,
+    Audiobook,
+    AudiobookChapter,
+    Platform,
+    Country,
+    HourOfDay,
+    DayOfWeek,
+    /// Each month of each year, e.g. May 2023
+    Month,
+    Year,
+    /// Windows of this many days, counted from a Monday so that windows of 7 days are weeks
+    Days(u32),
+}
+
+impl GroupBy {
+    /// Whether plays are put into groups by when they were played, rather than by what was played or where
+    pub fn is_by_time(&self) -> bool {
+        matches!(
+            self,
+            Self::HourOfDay | Self::DayOfWeek | Self::Month | Self::Year | Self::Days(_)
+        )
+    }
+}
+
+/// Gets a way of grouping plays from its name in the frontend, e.g. "artists" or "every_7_days"
+pub fn get_group_by(group_by: &str) -> Option<GroupBy> {
+    Some(match group_by {
+        "songs" => GroupBy::Song,
+        "artists" => GroupBy::Artist,
+        "albums" => GroupBy::Album,
+        "podcasts" => GroupBy::Podcast,
+        "podcast_episodes" => GroupBy::PodcastEpisode,
+        "audiobooks" => GroupBy::Audiobook,
+        "audiobook_chapters" => GroupBy::AudiobookChapter,
+        "devices" => GroupBy::Platform,
+        "countries" => GroupBy::Country,
+        "hours_of_day" => GroupBy::HourOfDay,
+        "days_of_week" => GroupBy::DayOfWeek,
+        "months" => GroupBy::Month,
+        "years" => GroupBy::Year,
+        // e.g. "every_7_days"
+        every_n_days => match every_n_days
+            .strip_prefix("every_")
+            .and_then(|days| days.strip_suffix("_days"))
+            .and_then(|days| days.parse::<u32>().ok())
+        {
+            Some(days) if days > 0 => GroupBy::Days(days),
+            _ => return None,
+        },
+    })
+}
+
+/// Options for how plays are put into groups
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GroupOptions {
+    /// Groups songs and podcast episodes by name even when they have different URIs, so that re-releases
+    /// of a song (e.g. on a single and then an album) are counted as one song. Songs are then identified
+    /// by their track and artist names only.
+    pub merge_same_name_releases: bool,
+    /// How far ahead of UTC the listener's time zone is, so that plays are put into time buckets (e.g. hours
+    /// of the day) by their local time. The same offset is used for every play, so in time zones with
+    /// daylight saving time, plays from the other half of the year are an hour off.
+    pub utc_offset_minutes: i32,
+}
+
+/// What identifies a group. Songs, podcast episodes, audiobooks and audiobook chapters are identified by
+/// their Spotify URI when they have one, so that different songs with the same names stay apart and renamed
+/// songs stay together. Everything else, including local files (which have no URI), is identified by its
+/// names.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GroupKey {
+    Uri(String),
+    Names(Vec<String>),
+}
+
+impl GroupKey {
+    fn new(uri: Option<&String>, meta_data: &MetaData, options: &GroupOptions) -> Self {
+        // Local files have URIs made up of their names, so they aren't any better than the names
+        let uri = uri.filter(|uri| !uri.starts_with("spotify:local:"));
+
+        match uri {
+            Some(uri) if !options.merge_same_name_releases => Self::Uri(uri.to_owned()),
+            _ => Self::Names(meta_data.get_names(options)),
+        }
+    }
 }
 
 #[derive(Clone, Serialize)]
 pub struct GroupData {
+    #[serde(skip)]
+    key: GroupKey,
     meta_data: MetaData,
     aggregated_data: AggregatedData,
 }
 
+/// The data of an audiobook group, which also keeps track of its chapters so that each one is only counted
+/// once however many times it was played
+#[derive(Clone, Serialize)]
+pub struct AudiobookGroupData {
+    #[serde(flatten)]
+    group_data: GroupData,
+    /// Whether each chapter that was started has been completed, by its URI (or title, if it has none)
+    #[serde(skip)]
+    chapters: HashMap<String, bool>,
+}
+
+impl AudiobookGroupData {
+    fn add_to_chapters(&mut self, chapter: &str, completed: bool) {
+        let aggregated_data = &mut self.group_data.aggregated_data;
+
+        if !self.chapters.contains_key(chapter) {
+            self.chapters.insert(chapter.to_owned(), false);
+            aggregated_data.chapters_started += 1;
+        }
+
+        let chapter_completed = self.chapters.get_mut(chapter).unwrap();
+
+        if completed && !*chapter_completed {
+            *chapter_completed = true;
+            aggregated_data.chapters_completed += 1;
+        }
+    }
+
+    fn merge(&mut self, other: AudiobookGroupData) {
+        // The chapters are counted again below, so that chapters that were started in both are only counted
+        // once
+        let other_aggregated_data = AggregatedData {
+            chapters_started: 0,
+            chapters_completed: 0,
+            ..other.group_data.aggregated_data
+        };
+        self.group_data.aggregated_data.merge(other_aggregated_data);
+
+        for (chapter, completed) in other.chapters {
+            self.add_to_chapters(&chapter, completed);
+        }
+    }
+}
+
 #[derive(Clone, Serialize)]
 pub enum Group {
     Album(GroupData),
@@ -25,10 +166,42 @@ pub enum Group {
     Song(GroupData),
     Podcast(GroupData),
     PodcastEpisode(GroupData),
+    Audiobook(AudiobookGroupData),
+    AudiobookChapter(GroupData),
+    Platform(GroupData),
+    Country(GroupData),
+    HourOfDay(GroupData),
+    DayOfWeek(GroupData),
+    Month(GroupData),
+    Year(GroupData),
+    Days(GroupData),
 }
 
 impl Group {
-    fn new_album(play_item: &PlayItem) -> Result<Self, ()> {
+    fn new(group_by: &GroupBy, play_item: &PlayItem, options: &GroupOptions) -> Result<Self, ()> {
+        match group_by {
+            GroupBy::Album => Self::new_album(play_item, options),
+            GroupBy::Artist => Self::new_artist(play_item, options),
+            GroupBy::Song => Self::new_song(play_item, options),
+            GroupBy::Podcast => Self::new_podcast(play_item, options),
+            GroupBy::PodcastEpisode => Self::new_podcast_episode(play_item, options),
+            GroupBy::Audiobook => Self::new_audiobook(play_item, options),
+            GroupBy::AudiobookChapter => Self::new_audiobook_chapter(play_item, options),
+            GroupBy::Platform => Self::new_platform(play_item, options),
+            GroupBy::Country => Self::new_country(play_item, options),
+            GroupBy::HourOfDay
+            | GroupBy::DayOfWeek
+            | GroupBy::Month
+            | GroupBy::Year
+            | GroupBy::Days(_) => Self::new_time_bucket(group_by, play_item, options),
+        }
+    }
+
+    fn new_album(play_item: &PlayItem, options: &GroupOptions) -> Result<Self, ()> {
+        if play_item.kind == PlayItemKind::Video {
+            return Err(());
+        }
+
         let Some(album_name) = play_item.master_metadata_album_album_name.to_owned() else {
             return Err(());
         };
@@ -42,38 +215,51 @@ impl Group {
             artist_name,
         };
 
+        let key = GroupKey::new(None, &meta_data, options);
+
         let aggregated_data = AggregatedData::default();
 
         Ok(Self::Album(GroupData {
+            key,
             meta_data,
             aggregated_data,
         }))
     }
 
-    fn new_artist(play_item: &PlayItem) -> Result<Self, ()> {
+    fn new_artist(play_item: &PlayItem, options: &GroupOptions) -> Result<Self, ()> {
+        if play_item.kind == PlayItemKind::Video {
+            return Err(());
+        }
+
         let Some(artist_name) = play_item.master_metadata_album_artist_name.to_owned() else {
             return Err(());
         };
 
         let meta_data = MetaData::Artist { artist_name };
 
+        let key = GroupKey::new(None, &meta_data, options);
+
         let aggregated_data = AggregatedData::default();
 
         Ok(Self::Artist(GroupData {
+            key,
             meta_data,
             aggregated_data,
         }))
     }
 
-    fn new_song(play_item: &PlayItem) -> Result<Self, ()> {
-        let Some(track_name) = play_item.master_metadata_track_name.to_owned() else {
+    fn new_song(play_item: &PlayItem, options: &GroupOptions) -> Result<Self, ()> {
+        if play_item.kind == PlayItemKind::Video {
             return Err(());
-        };
+        }
 
-        let Some(album_name) = play_item.master_metadata_album_album_name.to_owned() else {
+        let Some(track_name) = play_item.master_metadata_track_name.to_owned() else {
             return Err(());
         };
 
+        // Plays from the "Account data" export don't know which album a song is from
+        let album_name = play_item.master_metadata_album_album_name.to_owned();
+
         let Some(artist_name) = play_item.master_metadata_album_artist_name.to_owned() else {
             return Err(());
         };
@@ -84,30 +270,36 @@ impl Group {
             artist_name,
         };
 
+        let key = GroupKey::new(play_item.spotify_track_uri.as_ref(), &meta_data, options);
+
         let aggregated_data = AggregatedData::default();
 
         Ok(Self::Song(GroupData {
+            key,
             meta_data,
             aggregated_data,
         }))
     }
 
-    fn new_podcast(play_item: &PlayItem) -> Result<Self, ()> {
+    fn new_podcast(play_item: &PlayItem, options: &GroupOptions) -> Result<Self, ()> {
         let Some(podcast_name) = play_item.episode_show_name.to_owned() else {
             return Err(());
         };
 
         let meta_data = MetaData::Podcast { podcast_name };
 
+        let key = GroupKey::new(None, &meta_data, options);
+
         let aggregated_data = AggregatedData::default();
 
         Ok(Self::Podcast(GroupData {
+            key,
             meta_data,
             aggregated_data,
         }))
     }
 
-    fn new_podcast_episode(play_item: &PlayItem) -> Result<Self, ()> {
+    fn new_podcast_episode(play_item: &PlayItem, options: &GroupOptions) -> Result<Self, ()> {
         let Some(episode_name) = play_item.episode_name.to_owned() else {
             return Err(());
         };
@@ -121,14 +313,145 @@ impl Group {
             podcast_name,
         };
 
+        let key = GroupKey::new(play_item.spotify_episode_uri.as_ref(), &meta_data, options);
+
         let aggregated_data = AggregatedData::default();
 
         Ok(Self::PodcastEpisode(GroupData {
+            key,
+            meta_data,
+            aggregated_data,
+        }))
+    }
+
+    fn new_audiobook(play_item: &PlayItem, options: &GroupOptions) -> Result<Self, ()> {
+        let Some(audiobook_title) = play_item.audiobook_title.to_owned() else {
+            return Err(());
+        };
+
+        let meta_data = MetaData::Audiobook { audiobook_title };
+
+        let key = GroupKey::new(play_item.audiobook_uri.as_ref(), &meta_data, options);
+
+        let aggregated_data = AggregatedData::default();
+
+        Ok(Self::Audiobook(AudiobookGroupData {
+            group_data: GroupData {
+                key,
+                meta_data,
+                aggregated_data,
+            },
+            chapters: HashMap::new(),
+        }))
+    }
+
+    fn new_audiobook_chapter(play_item: &PlayItem, options: &GroupOptions) -> Result<Self, ()> {
+        let Some(chapter_title) = play_item.audiobook_chapter_title.to_owned() else {
+            return Err(());
+        };
+
+        let Some(audiobook_title) = play_item.audiobook_title.to_owned() else {
+            return Err(());
+        };
+
+        let meta_data = MetaData::AudiobookChapter {
+            chapter_title,
+            audiobook_title,
+        };
+
+        let key = GroupKey::new(
+            play_item.audiobook_chapter_uri.as_ref(),
+            &meta_data,
+            options,
+        );
+
+        let aggregated_data = AggregatedData::default();
+
+        Ok(Self::AudiobookChapter(GroupData {
+            key,
             meta_data,
             aggregated_data,
         }))
     }
 
+    fn new_platform(play_item: &PlayItem, options: &GroupOptions) -> Result<Self, ()> {
+        let Some(platform) = play_item.platform.as_ref() else {
+            return Err(());
+        };
+
+        let Device { family, os, model } = Device::from(platform);
+
+        let meta_data = MetaData::Platform { family, os, model };
+
+        let key = GroupKey::new(None, &meta_data, options);
+
+        let aggregated_data = AggregatedData::default();
+
+        Ok(Self::Platform(GroupData {
+            key,
+            meta_data,
+            aggregated_data,
+        }))
+    }
+
+    fn new_country(play_item: &PlayItem, options: &GroupOptions) -> Result<Self, ()> {
+        let Some(country_code) = play_item
+            .conn_country
+            .as_deref()
+            .and_then(country::get_country_code)
+        else {
+            return Err(());
+        };
+
+        let meta_data = MetaData::Country {
+            country_name: country::get_country_name(&country_code),
+            country_code,
+        };
+
+        let key = GroupKey::new(None, &meta_data, options);
+
+        let aggregated_data = AggregatedData::default();
+
+        Ok(Self::Country(GroupData {
+            key,
+            meta_data,
+            aggregated_data,
+        }))
+    }
+
+    fn new_time_bucket(
+        group_by: &GroupBy,
+        play_item: &PlayItem,
+        options: &GroupOptions,
+    ) -> Result<Self, ()> {
+        let Some(time_bucket) = get_time_bucket(&play_item.ts, group_by, options) else {
+            return Err(());
+        };
+
+        let Some(meta_data) = MetaData::from_time_bucket(group_by, time_bucket) else {
+            return Err(());
+        };
+
+        let key = GroupKey::new(None, &meta_data, options);
+
+        let aggregated_data = AggregatedData::default();
+
+        let group_data = GroupData {
+            key,
+            meta_data,
+            aggregated_data,
+        };
+
+        match group_by {
+            GroupBy::HourOfDay => Ok(Self::HourOfDay(group_data)),
+            GroupBy::DayOfWeek => Ok(Self::DayOfWeek(group_data)),
+            GroupBy::Month => Ok(Self::Month(group_data)),
+            GroupBy::Year => Ok(Self::Year(group_data)),
+            GroupBy::Days(_) => Ok(Self::Days(group_data)),
+            _ => Err(()),
+        }
+    }
+
     pub fn get_aggregated_data(&self) -> &AggregatedData {
         match self {
             Self::Album(group_data) => &group_data.aggregated_data,
@@ -136,6 +459,15 @@ impl Group {
             Self::Song(group_data) => &group_data.aggregated_data,
             Self::Podcast(group_data) => &group_data.aggregated_data,
             Self::PodcastEpisode(group_data) => &group_data.aggregated_data,
+            Self::Audiobook(audiobook_data) => &audiobook_data.group_data.aggregated_data,
+            Self::AudiobookChapter(group_data) => &group_data.aggregated_data,
+            Self::Platform(group_data) => &group_data.aggregated_data,
+            Self::Country(group_data) => &group_data.aggregated_data,
+            Self::HourOfDay(group_data) => &group_data.aggregated_data,
+            Self::DayOfWeek(group_data) => &group_data.aggregated_data,
+            Self::Month(group_data) => &group_data.aggregated_data,
+            Self::Year(group_data) => &group_data.aggregated_data,
+            Self::Days(group_data) => &group_data.aggregated_data,
         }
     }
 
@@ -146,6 +478,48 @@ impl Group {
             Self::Song(group_data) => &mut group_data.aggregated_data,
             Self::Podcast(group_data) => &mut group_data.aggregated_data,
             Self::PodcastEpisode(group_data) => &mut group_data.aggregated_data,
+            Self::Audiobook(audiobook_data) => &mut audiobook_data.group_data.aggregated_data,
+            Self::AudiobookChapter(group_data) => &mut group_data.aggregated_data,
+            Self::Platform(group_data) => &mut group_data.aggregated_data,
+            Self::Country(group_data) => &mut group_data.aggregated_data,
+            Self::HourOfDay(group_data) => &mut group_data.aggregated_data,
+            Self::DayOfWeek(group_data) => &mut group_data.aggregated_data,
+            Self::Month(group_data) => &mut group_data.aggregated_data,
+            Self::Year(group_data) => &mut group_data.aggregated_data,
+            Self::Days(group_data) => &mut group_data.aggregated_data,
+        }
+    }
+
+    /// Adds the aggregated data of another group with the same key into this one. This group's metadata is
+    /// kept, so `other` should be the group that was made from later plays.
+    fn merge(&mut self, other: Group) {
+        match (self, other) {
+            (Self::Audiobook(audiobook_data), Self::Audiobook(other_audiobook_data)) => {
+                audiobook_data.merge(other_audiobook_data);
+            }
+            (group, mut other) => {
+                let other_aggregated_data = std::mem::take(other.get_aggregated_data_mut());
+                group.get_aggregated_data_mut().merge(other_aggregated_data);
+            }
+        }
+    }
+
+    pub fn get_key(&self) -> &GroupKey {
+        match self {
+            Self::Album(group_data) => &group_data.key,
+            Self::Artist(group_data) => &group_data.key,
+            Self::Song(group_data) => &group_data.key,
+            Self::Podcast(group_data) => &group_data.key,
+            Self::PodcastEpisode(group_data) => &group_data.key,
+            Self::Audiobook(audiobook_data) => &audiobook_data.group_data.key,
+            Self::AudiobookChapter(group_data) => &group_data.key,
+            Self::Platform(group_data) => &group_data.key,
+            Self::Country(group_data) => &group_data.key,
+            Self::HourOfDay(group_data) => &group_data.key,
+            Self::DayOfWeek(group_data) => &group_data.key,
+            Self::Month(group_data) => &group_data.key,
+            Self::Year(group_data) => &group_data.key,
+            Self::Days(group_data) => &group_data.key,
         }
     }
 
@@ -156,33 +530,44 @@ impl Group {
             Self::Song(group_data) => &group_data.meta_data,
             Self::Podcast(group_data) => &group_data.meta_data,
             Self::PodcastEpisode(group_data) => &group_data.meta_data,
+            Self::Audiobook(audiobook_data) => &audiobook_data.group_data.meta_data,
+            Self::AudiobookChapter(group_data) => &group_data.meta_data,
+            Self::Platform(group_data) => &group_data.meta_data,
+            Self::Country(group_data) => &group_data.meta_data,
+            Self::HourOfDay(group_data) => &group_data.meta_data,
+            Self::DayOfWeek(group_data) => &group_data.meta_data,
+            Self::Month(group_data) => &group_data.meta_data,
+            Self::Year(group_data) => &group_data.meta_data,
+            Self::Days(group_data) => &group_data.meta_data,
         }
     }
 }
 
 impl Display for Group {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let group_data = match self {
-            Self::Album(group_data) => group_data,
-            Self::Artist(group_data) => group_data,
-            Self::Song(group_data) => group_data,
-            Self::Podcast(group_data) => group_data,
-            Self::PodcastEpisode(group_data) => group_data,
-        };
+        let meta_data = self.get_metadata();
+        let aggregated_data = self.get_aggregated_data();
 
         write!(f,
-            "{}\nTotal Listening Time: {}\nPlays: {}\nSkip: {:.2}%\nClick: {:.2}%\nShuffle: {:.2}%\nAutoplay: {:.2}%\n\n",
-            group_data.meta_data.as_string(),
-            util::get_total_listen_time_from_ms(group_data.aggregated_data.get_ms_played()),
-            group_data.aggregated_data.get_play_count(),
-            group_data.aggregated_data.get_skip_pct(),
-            group_data.aggregated_data.get_click_pct(),
-            group_data.aggregated_data.get_shuffle_pct(),
-            group_data.aggregated_data.get_autoplay_pct(),
+            "{}\nTotal Listening Time: {}\nPlays: {}\nSkip: {}\nClick: {}\nShuffle: {}\nAutoplay: {}\nCompletion: {}\n\n",
+            meta_data.as_string(),
+            util::get_total_listen_time_from_ms(aggregated_data.get_ms_played()),
+            aggregated_data.get_play_count(),
+            util::get_pct_string(aggregated_data.get_skip_pct()),
+            util::get_pct_string(aggregated_data.get_click_pct()),
+            util::get_pct_string(aggregated_data.get_shuffle_pct()),
+            util::get_pct_string(aggregated_data.get_autoplay_pct()),
+            util::get_pct_string(aggregated_data.get_completion_pct()),
         )
     }
 }
 
+impl AsRef<Group> for Group {
+    fn as_ref(&self) -> &Group {
+        self
+    }
+}
+
 /////////////////////
 // METADATA STRUCT //
 /////////////////////
@@ -198,7 +583,7 @@ pub enum MetaData {
     },
     Song {
         track_name: String,
-        album_name: String,
+        album_name: Option<String>,
         artist_name: String,
     },
     Podcast {
@@ -208,10 +593,86 @@ pub enum MetaData {
         episode_name: String,
         podcast_name: String,
     },
+    Audiobook {
+        audiobook_title: String,
+    },
+    AudiobookChapter {
+        chapter_title: String,
+        audiobook_title: String,
+    },
+    Platform {
+        family: DeviceFamily,
+        os: Option<String>,
+        model: Option<String>,
+    },
+    Country {
+        country_code: String,
+        country_name: String,
+    },
+    HourOfDay {
+        hour: u32,
+    },
+    DayOfWeek {
+        weekday: String,
+    },
+    Month {
+        year: i32,
+        month: u32,
+    },
+    Year {
+        year: i32,
+    },
+    Days {
+        start_date: NaiveDate,
+        end_date: NaiveDate,
+    },
 }
 
 impl MetaData {
-    fn as_string(&self) -> String {
+    /// The names that identify a group when it can't be identified by a URI
+    fn get_names(&self, options: &GroupOptions) -> Vec<String> {
+        match self {
+            Self::Album {
+                album_name,
+                artist_name,
+            } => vec![album_name.clone(), artist_name.clone()],
+            Self::Artist { artist_name } => vec![artist_name.clone()],
+            Self::Song {
+                track_name,
+                album_name,
+                artist_name,
+            } => match album_name {
+                Some(album_name) if !options.merge_same_name_releases => {
+                    vec![track_name.clone(), album_name.clone(), artist_name.clone()]
+                }
+                _ => vec![track_name.clone(), artist_name.clone()],
+            },
+            Self::Podcast { podcast_name } => vec![podcast_name.clone()],
+            Self::PodcastEpisode {
+                episode_name,
+                podcast_name,
+            } => vec![episode_name.clone(), podcast_name.clone()],
+            Self::Audiobook { audiobook_title } => vec![audiobook_title.clone()],
+            Self::AudiobookChapter {
+                chapter_title,
+                audiobook_title,
+            } => vec![chapter_title.clone(), audiobook_title.clone()],
+            Self::Platform { family, os, model } => vec![
+                family.as_str().to_owned(),
+                os.clone().unwrap_or_default(),
+                model.clone().unwrap_or_default(),
+            ],
+            Self::Country { country_code, .. } => vec![country_code.clone()],
+            Self::HourOfDay { .. }
+            | Self::DayOfWeek { .. }
+            | Self::Month { .. }
+            | Self::Year { .. }
+            | Self::Days { .. } => vec![self.as_string()],
+        }
+    }
+
+    /// The names of the group, formatted for display
+    pub fn as_string(&self) -> String {
         match self {
             Self::Album {
                 album_name,
@@ -220,17 +681,127 @@ impl MetaData {
             Self::Artist { artist_name } => format!("\"{}\"", artist_name),
             Self::Song {
                 track_name,
-                album_name,
+                album_name: Some(album_name),
                 artist_name,
             } => format!(
                 "\"{}\" on \"{}\" by \"{}\"",
                 track_name, album_name, artist_name
             ),
+            Self::Song {
+                track_name,
+                album_name: None,
+                artist_name,
+            } => format!("\"{}\" by \"{}\"", track_name, artist_name),
             Self::Podcast { podcast_name } => format!("\"{}\"", podcast_name),
             Self::PodcastEpisode {
                 episode_name,
                 podcast_name,
             } => format!("\"{}\" on \"{}\"", episode_name, podcast_name),
+            Self::Audiobook { audiobook_title } => format!("\"{}\"", audiobook_title),
+            Self::AudiobookChapter {
+                chapter_title,
+                audiobook_title,
+            } => format!("\"{}\" from \"{}\"", chapter_title, audiobook_title),
+            Self::Platform { family, os, model } => {
+                let mut device = family.to_string();
+                if let Some(model) = model {
+                    device.push_str(&format!(" \"{}\"", model));
+                }
+                if let Some(os) = os {
+                    device.push_str(&format!(" on {}", os));
+                }
+
+                device
+            }
+            Self::Country {
+                country_code,
+                country_name,
+            } => format!("\"{}\" ({})", country_name, country_code),
+            Self::HourOfDay { hour } => format!("{:02}:00", hour),
+            Self::DayOfWeek { weekday } => weekday.clone(),
+            Self::Month { year, month } => format!("{}-{:02}", year, month),
+            Self::Year { year } => year.to_string(),
+            Self::Days {
+                start_date,
+                end_date,
+            } => format!("{} to {}", start_date, end_date),
+        }
+    }
+}
+
+//////////////////
+// TIME BUCKETS //
+//////////////////
+
+const WEEKDAY_NAMES: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+
+/// The Monday that windows of days are counted from
+fn get_days_window_anchor() -> NaiveDate {
+    NaiveDate::from_ymd_opt(1970, 1, 5).unwrap()
+}
+
+/// The time bucket that a play is in when grouping by time, as a number that is the same for every play in
+/// the bucket (e.g. the hour, or the number of windows of days since `get_days_window_anchor`). Plays are
+/// put into buckets by when they ended, in the listener's local time. Returns None when not grouping by
+/// time.
+pub fn get_time_bucket(
+    ts: &DateTime<Utc>,
+    group_by: &GroupBy,
+    options: &GroupOptions,
+) -> Option<i64> {
+    if !group_by.is_by_time() {
+        return None;
+    }
+
+    let local_ts = ts.naive_utc() + Duration::minutes(options.utc_offset_minutes as i64);
+
+    match group_by {
+        GroupBy::HourOfDay => Some(local_ts.hour() as i64),
+        GroupBy::DayOfWeek => Some(local_ts.weekday().num_days_from_monday() as i64),
+        GroupBy::Month => Some(local_ts.year() as i64 * 12 + local_ts.month0() as i64),
+        GroupBy::Year => Some(local_ts.year() as i64),
+        GroupBy::Days(days) => {
+            let days_since_anchor = (local_ts.date() - get_days_window_anchor()).num_days();
+            Some(days_since_anchor.div_euclid((*days).max(1) as i64))
+        }
+        _ => None,
+    }
+}
+
+impl MetaData {
+    fn from_time_bucket(group_by: &GroupBy, time_bucket: i64) -> Option<Self> {
+        match group_by {
+            GroupBy::HourOfDay => Some(Self::HourOfDay {
+                hour: time_bucket as u32,
+            }),
+            GroupBy::DayOfWeek => Some(Self::DayOfWeek {
+                weekday: WEEKDAY_NAMES.get(time_bucket as usize)?.to_string(),
+            }),
+            GroupBy::Month => Some(Self::Month {
+                year: time_bucket.div_euclid(12) as i32,
+                month: time_bucket.rem_euclid(12) as u32 + 1,
+            }),
+            GroupBy::Year => Some(Self::Year {
+                year: time_bucket as i32,
+            }),
+            GroupBy::Days(days) => {
+                let days = (*days).max(1) as i64;
+                let start_date = get_days_window_anchor() + Duration::days(time_bucket * days);
+
+                Some(Self::Days {
+                    start_date,
+                    end_date: start_date + Duration::days(days - 1),
+                })
+            }
+            _ => None,
         }
     }
 }
@@ -251,6 +822,12 @@ pub struct AggregatedData {
     pub shuffle_valid_plays: u32,
     pub autoplay_count: u32,
     pub autoplay_valid_plays: u32,
+    /// Plays that were listened to until the end, e.g. chapters of an audiobook that were finished
+    pub completed_count: u32,
+    pub completed_valid_plays: u32,
+    /// Only counted for audiobooks. A chapter is completed once any of its plays was.
+    pub chapters_started: u32,
+    pub chapters_completed: u32,
 }
 
 impl AggregatedData {
@@ -276,20 +853,48 @@ impl AggregatedData {
         self.shuffle_valid_plays += 1;
     }
 
-    fn add_to_click_count(&mut self, reason_start: &String) {
-        if reason_start.eq_ignore_ascii_case("clickrow") {
+    fn add_to_click_count(&mut self, reason_start: &ReasonStart) {
+        if *reason_start == ReasonStart::ClickRow {
             self.click_count += 1;
         }
         self.click_valid_plays += 1;
     }
 
-    fn add_to_autoplay_count(&mut self, reason_start: &String) {
-        if reason_start.eq_ignore_ascii_case("trackdone") {
+    fn add_to_autoplay_count(&mut self, reason_start: &ReasonStart) {
+        if *reason_start == ReasonStart::TrackDone {
             self.autoplay_count += 1;
         }
         self.autoplay_valid_plays += 1;
     }
 
+    fn add_to_completed_count(&mut self, reason_end: &ReasonEnd) {
+        if *reason_end == ReasonEnd::TrackDone {
+            self.completed_count += 1;
+        }
+        self.completed_valid_plays += 1;
+    }
+
+    /// Adds the data of other plays into this data, as if they had been added one by one. Merging is
+    /// associative, so plays can be aggregated in chunks and the chunks merged in any grouping. The chapter
+    /// counts are added up, since which chapters were counted isn't kept here (audiobook groups keep it, so
+    /// that they only count each chapter once).
+    pub fn merge(&mut self, other: AggregatedData) {
+        self.ms_played += other.ms_played;
+        self.play_count += other.play_count;
+        self.skip_count += other.skip_count;
+        self.skip_valid_plays += other.skip_valid_plays;
+        self.click_count += other.click_count;
+        self.click_valid_plays += other.click_valid_plays;
+        self.shuffle_count += other.shuffle_count;
+        self.shuffle_valid_plays += other.shuffle_valid_plays;
+        self.autoplay_count += other.autoplay_count;
+        self.autoplay_valid_plays += other.autoplay_valid_plays;
+        self.completed_count += other.completed_count;
+        self.completed_valid_plays += other.completed_valid_plays;
+        self.chapters_started += other.chapters_started;
+        self.chapters_completed += other.chapters_completed;
+    }
+
     pub fn get_ms_played(&self) -> u64 {
         self.ms_played
     }
@@ -298,36 +903,98 @@ impl AggregatedData {
         self.play_count
     }
 
-    pub fn get_skip_pct(&self) -> f32 {
+    pub fn get_skip_pct(&self) -> Option<f32> {
         if self.skip_valid_plays == 0 {
-            0.
+            None
         } else {
-            100. * self.skip_count as f32 / self.skip_valid_plays as f32
+            Some(100. * self.skip_count as f32 / self.skip_valid_plays as f32)
         }
     }
 
-    pub fn get_click_pct(&self) -> f32 {
+    pub fn get_click_pct(&self) -> Option<f32> {
         if self.click_valid_plays == 0 {
-            0.
+            None
         } else {
-            100. * self.click_count as f32 / self.click_valid_plays as f32
+            Some(100. * self.click_count as f32 / self.click_valid_plays as f32)
         }
     }
 
-    pub fn get_shuffle_pct(&self) -> f32 {
+    pub fn get_shuffle_pct(&self) -> Option<f32> {
         if self.shuffle_valid_plays == 0 {
-            0.
+            None
         } else {
-            100. * self.shuffle_count as f32 / self.shuffle_valid_plays as f32
+            Some(100. * self.shuffle_count as f32 / self.shuffle_valid_plays as f32)
         }
     }
 
-    pub fn get_autoplay_pct(&self) -> f32 {
+    pub fn get_autoplay_pct(&self) -> Option<f32> {
         if self.autoplay_valid_plays == 0 {
-            0.
+            None
         } else {
-            100. * self.autoplay_count as f32 / self.autoplay_valid_plays as f32
+            Some(100. * self.autoplay_count as f32 / self.autoplay_valid_plays as f32)
+        }
+    }
+
+    pub fn get_completion_pct(&self) -> Option<f32> {
+        if self.completed_valid_plays == 0 {
+            None
+        } else {
+            Some(100. * self.completed_count as f32 / self.completed_valid_plays as f32)
+        }
+    }
+
+    /// Gets the value of one of the metrics, or None if it can't be computed for this data.
+    ///
+    /// Percentages are computed as f64 rather than widened from the f32 ones, so that they don't pick up
+    /// digits that were never there (e.g. 33.33333206176758).
+    pub fn get_metric(&self, metric: &SortSpotifyDataBy) -> Option<f64> {
+        let get_pct = |count: u32, valid_plays: u32| match valid_plays {
+            0 => None,
+            _ => Some(100. * count as f64 / valid_plays as f64),
+        };
+
+        match metric {
+            SortSpotifyDataBy::AutoPlayPct => {
+                get_pct(self.autoplay_count, self.autoplay_valid_plays)
+            }
+            SortSpotifyDataBy::ClickPct => get_pct(self.click_count, self.click_valid_plays),
+            SortSpotifyDataBy::CompletionPct => {
+                get_pct(self.completed_count, self.completed_valid_plays)
+            }
+            SortSpotifyDataBy::PlayCount => Some(self.play_count as f64),
+            SortSpotifyDataBy::ShufflePct => get_pct(self.shuffle_count, self.shuffle_valid_plays),
+            SortSpotifyDataBy::SkipPct => get_pct(self.skip_count, self.skip_valid_plays),
+            SortSpotifyDataBy::TotalListenTime => Some(self.ms_played as f64),
+        }
+    }
+
+    /// Returns the metrics that can't be computed for this data, because none of its plays had the
+    /// fields needed for them (e.g. plays from the "Account data" export don't say whether they were
+    /// skipped).
+    pub fn get_unavailable_metrics(&self) -> Vec<SortSpotifyDataBy> {
+        let mut unavailable_metrics = vec![];
+
+        if self.autoplay_valid_plays == 0 {
+            unavailable_metrics.push(SortSpotifyDataBy::AutoPlayPct);
+        }
+
+        if self.click_valid_plays == 0 {
+            unavailable_metrics.push(SortSpotifyDataBy::ClickPct);
+        }
+
+        if self.completed_valid_plays == 0 {
+            unavailable_metrics.push(SortSpotifyDataBy::CompletionPct);
+        }
+
+        if self.shuffle_valid_plays == 0 {
+            unavailable_metrics.push(SortSpotifyDataBy::ShufflePct);
+        }
+
+        if self.skip_valid_plays == 0 {
+            unavailable_metrics.push(SortSpotifyDataBy::SkipPct);
         }
+
+        unavailable_metrics
     }
 }
 
@@ -335,53 +1002,640 @@ impl AggregatedData {
 // GROUP RAW PLAY ITEMS //
 //////////////////////////
 
-fn update_hash_map_entry(entry: &mut Group, play_item: &PlayItem, ms_played: &u64) {
-    let aggregated_data = entry.get_aggregated_data_mut();
+/// The parts of a play that are added to the aggregated data of its group. They are borrowed from either
+/// a PlayItem or a PlayStore, so that neither has to be copied to group its plays.
+pub struct PlayStats<'a> {
+    /// 0 for plays from other services whose length isn't known, so that they are still counted
+    pub ms_played: u64,
+    pub reason_start: Option<&'a ReasonStart>,
+    pub reason_end: Option<&'a ReasonEnd>,
+    pub skipped: Option<bool>,
+    pub shuffle: Option<bool>,
+    /// The URI of the audiobook chapter that was played, or its title if it has no URI
+    pub audiobook_chapter: Option<&'a str>,
+}
+
+impl<'a> PlayStats<'a> {
+    /// Returns None if the play can't be added to a group, because it's a Spotify play and it's unknown how
+    /// long it lasted
+    fn from_play_item(play_item: &'a PlayItem) -> Option<Self> {
+        Some(PlayStats {
+            ms_played: get_known_ms_played(play_item.ms_played, play_item.source)?,
+            reason_start: play_item.reason_start.as_ref(),
+            reason_end: play_item.reason_end.as_ref(),
+            skipped: play_item.skipped,
+            shuffle: play_item.shuffle,
+            audiobook_chapter: play_item
+                .audiobook_chapter_uri
+                .as_deref()
+                .or(play_item.audiobook_chapter_title.as_deref()),
+        })
+    }
+}
+
+/// Gets how long a play lasted for adding it to a group. Spotify always knows how long a play lasted, so a
+/// Spotify play without a length is left out (None), but other services often don't and their plays are
+/// counted without any listening time.
+pub fn get_known_ms_played(ms_played: Option<u64>, source: PlayItemSource) -> Option<u64> {
+    match (ms_played, source) {
+        (Some(ms_played), _) => Some(ms_played),
+        (None, PlayItemSource::Spotify) => None,
+        (None, _) => Some(0),
+    }
+}
+
+fn update_hash_map_entry(entry: &mut Group, play_stats: &PlayStats) {
+    add_play_to_aggregated_data(entry.get_aggregated_data_mut(), play_stats);
+
+    if let Group::Audiobook(audiobook_data) = entry {
+        if let Some(chapter) = play_stats.audiobook_chapter {
+            let completed = play_stats.reason_end == Some(&ReasonEnd::TrackDone);
+            audiobook_data.add_to_chapters(chapter, completed);
+        }
+    }
+}
+
+fn add_play_to_aggregated_data(aggregated_data: &mut AggregatedData, play_stats: &PlayStats) {
     aggregated_data.increment_play_count();
-    aggregated_data.add_time_to_ms_played(ms_played);
+    aggregated_data.add_time_to_ms_played(&play_stats.ms_played);
 
-    if let Some(reason_start) = &play_item.reason_start {
+    if let Some(reason_start) = play_stats.reason_start {
         aggregated_data.add_to_click_count(reason_start);
         aggregated_data.add_to_autoplay_count(reason_start);
     }
 
-    if let Some(skipped) = play_item.skipped {
+    if let Some(skipped) = play_stats.skipped {
         aggregated_data.add_to_skip_count(skipped);
     }
 
-    if let Some(shuffled) = play_item.shuffle {
+    if let Some(shuffled) = play_stats.shuffle {
         aggregated_data.add_to_shuffle_count(shuffled);
     }
+
+    if let Some(reason_end) = play_stats.reason_end {
+        aggregated_data.add_to_completed_count(reason_end);
+    }
+}
+
+/// How many plays are grouped at a time by each thread
+const GROUPING_CHUNK_SIZE: usize = 16_384;
+
+/// Groups made from a chunk of plays, in the order that their first plays were in
+#[derive(Default)]
+struct GroupedChunk {
+    groups: Vec<Group>,
+    group_indices: HashMap<GroupKey, usize>,
+}
+
+impl GroupedChunk {
+    fn from_groups(groups: Vec<Group>) -> Self {
+        let group_indices = groups
+            .iter()
+            .enumerate()
+            .map(|(i, group)| (group.get_key().clone(), i))
+            .collect();
+
+        GroupedChunk {
+            groups,
+            group_indices,
+        }
+    }
+
+    /// Returns the index of the group with the same key as `group`, adding `group` if there isn't one yet
+    fn get_or_insert_group(&mut self, group: Group) -> usize {
+        *self
+            .group_indices
+            .entry(group.get_key().clone())
+            .or_insert_with(|| {
+                self.groups.push(group);
+                self.groups.len() - 1
+            })
+    }
+
+    /// Merges the groups of the chunk of plays that came after this one into these groups. Groups that are
+    /// only in `other` are added after these, so the groups stay in the order that their first plays were in
+    /// and the result is the same as if both chunks had been grouped as one.
+    fn merge(mut self, other: GroupedChunk) -> Self {
+        for group in other.groups {
+            match self.group_indices.get(group.get_key()) {
+                Some(&group_index) => self.groups[group_index].merge(group),
+                None => {
+                    self.get_or_insert_group(group);
+                }
+            }
+        }
+
+        self
+    }
 }
 
-pub fn get_grouped_data(group_by: &GroupBy, played_items: Vec<PlayItem>) -> Vec<Group> {
-    let mut grouped_data_map: HashMap<String, Group> = HashMap::new();
+/// Folds chunks of `items` into accumulators and reduces those into one, with as many threads as there are
+/// available. The chunks are folded and reduced in order, so `reduce` only ever gets an accumulator and the
+/// one that came after it. The wasm build has no threads, so there all of the items are folded at once.
+fn fold_chunks<T, A>(
+    items: &[T],
+    identity: impl Fn() -> A + Send + Sync,
+    fold: impl Fn(A, &[T]) -> A + Send + Sync,
+    reduce: impl Fn(A, A) -> A + Send + Sync,
+) -> A
+where
+    T: Sync,
+    A: Send,
+{
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        items
+            .par_chunks(GROUPING_CHUNK_SIZE)
+            .fold(&identity, &fold)
+            .reduce(&identity, reduce)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = reduce;
+        fold(identity(), items)
+    }
+}
 
+fn add_play_items_to_chunk(
+    mut grouped_chunk: GroupedChunk,
+    group_by: &GroupBy,
+    options: &GroupOptions,
+    played_items: &[PlayItem],
+) -> GroupedChunk {
     for play_item in played_items.iter() {
-        // Play items should be skipped if their ms_played field is None
-        let PlayItem {
-            ms_played: Some(ms_played),
-            ..
-        } = play_item else {continue;};
-
-        let Ok(group) = (match group_by {
-            GroupBy::Album => Group::new_album(play_item),
-            GroupBy::Artist => Group::new_artist(play_item),
-            GroupBy::Song => Group::new_song(play_item),
-            GroupBy::Podcast => Group::new_podcast(play_item),
-            GroupBy::PodcastEpisode => Group::new_podcast_episode(play_item),
+        // Spotify play items should be skipped if their ms_played field is None
+        let Some(play_stats) = PlayStats::from_play_item(play_item) else {continue;};
+
         // Play items should be skipped if they cannot be successfully turned into a group
-        }) else {continue;};
+        let Ok(group) = Group::new(group_by, play_item, options) else {continue;};
+
+        let group_index = grouped_chunk.get_or_insert_group(group);
+        update_hash_map_entry(&mut grouped_chunk.groups[group_index], &play_stats);
+    }
+
+    grouped_chunk
+}
+
+pub fn get_grouped_data(
+    group_by: &GroupBy,
+    options: &GroupOptions,
+    played_items: Vec<PlayItem>,
+) -> Vec<Group> {
+    let mut grouped_data = Vec::new();
+    add_plays_to_grouped_data(&mut grouped_data, group_by, options, &played_items);
+
+    grouped_data
+}
+
+/// Adds plays to data that was already grouped, updating the groups that they belong to and adding new
+/// groups for the ones that don't belong to any yet. The groups must have been made with the same group_by
+/// and options.
+pub fn add_plays_to_grouped_data(
+    grouped_data: &mut Vec<Group>,
+    group_by: &GroupBy,
+    options: &GroupOptions,
+    played_items: &[PlayItem],
+) {
+    let new_grouped_chunk = fold_chunks(
+        played_items,
+        GroupedChunk::default,
+        |grouped_chunk, played_items| {
+            add_play_items_to_chunk(grouped_chunk, group_by, options, played_items)
+        },
+        GroupedChunk::merge,
+    );
+
+    let grouped_chunk = GroupedChunk::from_groups(std::mem::take(grouped_data));
+    *grouped_data = grouped_chunk.merge(new_grouped_chunk).groups;
+}
+
+/// The group that the plays with each symbol key are in, or None if they can't be put into a group
+type SymbolKeyGroupIndices = HashMap<GroupSymbolKey, Option<usize>>;
+
+/// Gets the index of the group in `grouped_chunk` that the play at `play_index` in a PlayStore belongs to,
+/// adding the group if it isn't there yet. Returns None if the play can't be put into a group.
+fn get_store_play_group_index(
+    grouped_chunk: &mut GroupedChunk,
+    symbol_key_group_indices: &mut SymbolKeyGroupIndices,
+    group_by: &GroupBy,
+    options: &GroupOptions,
+    play_store: &PlayStore,
+    play_index: usize,
+) -> Option<usize> {
+    let symbol_key = play_store.get_group_symbol_key(play_index, group_by, options);
+
+    *symbol_key_group_indices
+        .entry(symbol_key)
+        .or_insert_with(|| {
+            // Only the first play with each symbol key has to be made into a PlayItem
+            let play_item = play_store.get_play_item(play_index);
+            let group = Group::new(group_by, &play_item, options).ok()?;
+
+            Some(grouped_chunk.get_or_insert_group(group))
+        })
+}
+
+/// Adds the play at `play_index` in a PlayStore to the group in `grouped_chunk` that it belongs to, and
+/// returns the index of that group. Returns None if the play can't be put into a group.
+fn add_store_play_to_chunk(
+    grouped_chunk: &mut GroupedChunk,
+    symbol_key_group_indices: &mut SymbolKeyGroupIndices,
+    group_by: &GroupBy,
+    options: &GroupOptions,
+    play_store: &PlayStore,
+    play_index: usize,
+) -> Option<usize> {
+    let play_stats = play_store.get_play_stats(play_index)?;
+
+    let group_index = get_store_play_group_index(
+        grouped_chunk,
+        symbol_key_group_indices,
+        group_by,
+        options,
+        play_store,
+        play_index,
+    )?;
+
+    update_hash_map_entry(&mut grouped_chunk.groups[group_index], &play_stats);
+
+    Some(group_index)
+}
+
+/// Groups the plays at `play_indices` in a PlayStore, the same as `get_grouped_data` does with PlayItems.
+/// Each play is only looked at through the IDs of its values, so plays aren't copied out of the store.
+pub fn get_grouped_data_from_store(
+    group_by: &GroupBy,
+    options: &GroupOptions,
+    play_store: &PlayStore,
+    play_indices: &[usize],
+) -> Vec<Group> {
+    // Each thread keeps its own symbol keys, since the group indices are only those of the groups it has
+    // made
+    let (grouped_chunk, _) = fold_chunks(
+        play_indices,
+        <(GroupedChunk, SymbolKeyGroupIndices)>::default,
+        |(mut grouped_chunk, mut symbol_key_group_indices), play_indices| {
+            for &play_index in play_indices {
+                add_store_play_to_chunk(
+                    &mut grouped_chunk,
+                    &mut symbol_key_group_indices,
+                    group_by,
+                    options,
+                    play_store,
+                    play_index,
+                );
+            }
+
+            (grouped_chunk, symbol_key_group_indices)
+        },
+        |(grouped_chunk, _), (other_grouped_chunk, _)| {
+            (grouped_chunk.merge(other_grouped_chunk), HashMap::new())
+        },
+    );
+
+    grouped_chunk.groups
+}
+
+/// Groups plays the same as `get_grouped_data_from_store`, and also returns the indices of the plays that
+/// were put into each group, so that they can be grouped again (e.g. the songs of an album)
+pub fn get_grouped_play_indices_from_store(
+    group_by: &GroupBy,
+    options: &GroupOptions,
+    play_store: &PlayStore,
+    play_indices: &[usize],
+) -> Vec<(Group, Vec<usize>)> {
+    let mut grouped_chunk = GroupedChunk::default();
+    let mut symbol_key_group_indices = SymbolKeyGroupIndices::new();
+    let mut group_play_indices: Vec<Vec<usize>> = Vec::new();
+
+    for &play_index in play_indices {
+        let Some(group_index) = add_store_play_to_chunk(
+            &mut grouped_chunk,
+            &mut symbol_key_group_indices,
+            group_by,
+            options,
+            play_store,
+            play_index,
+        ) else {
+            continue;
+        };
+
+        if group_index == group_play_indices.len() {
+            group_play_indices.push(Vec::new());
+        }
+        group_play_indices[group_index].push(play_index);
+    }
+
+    grouped_chunk
+        .groups
+        .into_iter()
+        .zip(group_play_indices)
+        .collect()
+}
+
+/// Plays grouped by two groupings at once, e.g. for a pivot table. Only plays that can be put into a group
+/// of both groupings are counted, so some groups may not have any plays.
+pub struct CrossGroupedData {
+    /// The groups of the first grouping, with the index of the first play that was put into each
+    pub row_groups: Vec<(Group, usize)>,
+    /// The same as `row_groups`, for the second grouping
+    pub column_groups: Vec<(Group, usize)>,
+    /// The aggregated data of the plays in both a row group and a column group, by the indices of the two
+    /// groups. Pairs of groups without any plays are left out.
+    pub cells: HashMap<(usize, usize), AggregatedData>,
+}
+
+/// Groups the plays at `play_indices` in a PlayStore by two groupings in one go over the plays
+pub fn get_cross_grouped_data_from_store(
+    row_group_by: &GroupBy,
+    column_group_by: &GroupBy,
+    options: &GroupOptions,
+    play_store: &PlayStore,
+    play_indices: &[usize],
+) -> CrossGroupedData {
+    let mut rows = GroupedChunk::default();
+    let mut row_symbol_key_group_indices = SymbolKeyGroupIndices::new();
+    let mut row_first_play_indices = Vec::new();
+    let mut columns = GroupedChunk::default();
+    let mut column_symbol_key_group_indices = SymbolKeyGroupIndices::new();
+    let mut column_first_play_indices = Vec::new();
+    let mut cells: HashMap<(usize, usize), AggregatedData> = HashMap::new();
+
+    for &play_index in play_indices {
+        let Some(play_stats) = play_store.get_play_stats(play_index) else {
+            continue;
+        };
+
+        let get_group_index = |grouped_chunk: &mut GroupedChunk,
+                               symbol_key_group_indices: &mut SymbolKeyGroupIndices,
+                               first_play_indices: &mut Vec<usize>,
+                               group_by: &GroupBy| {
+            let group_index = get_store_play_group_index(
+                grouped_chunk,
+                symbol_key_group_indices,
+                group_by,
+                options,
+                play_store,
+                play_index,
+            )?;
+            if group_index == first_play_indices.len() {
+                first_play_indices.push(play_index);
+            }
+            Some(group_index)
+        };
 
-        let key = group.get_metadata().as_string();
+        let Some(row_index) = get_group_index(
+            &mut rows,
+            &mut row_symbol_key_group_indices,
+            &mut row_first_play_indices,
+            row_group_by,
+        ) else {
+            continue;
+        };
+        let Some(column_index) = get_group_index(
+            &mut columns,
+            &mut column_symbol_key_group_indices,
+            &mut column_first_play_indices,
+            column_group_by,
+        ) else {
+            continue;
+        };
+
+        update_hash_map_entry(&mut rows.groups[row_index], &play_stats);
+        update_hash_map_entry(&mut columns.groups[column_index], &play_stats);
+        add_play_to_aggregated_data(
+            cells.entry((row_index, column_index)).or_default(),
+            &play_stats,
+        );
+    }
+
+    CrossGroupedData {
+        row_groups: rows
+            .groups
+            .into_iter()
+            .zip(row_first_play_indices)
+            .collect(),
+        column_groups: columns
+            .groups
+            .into_iter()
+            .zip(column_first_play_indices)
+            .collect(),
+        cells,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plays;
+
+    /// Makes enough plays that they are grouped in several chunks. Some songs are only played in later
+    /// chunks, so that groups are also added while merging chunks.
+    fn get_play_items() -> Vec<PlayItem> {
+        let first_ts = Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap();
+
+        (0..GROUPING_CHUNK_SIZE * 2 + 1000)
+            .map(|i| {
+                let artist = if i > GROUPING_CHUNK_SIZE + 5 && i % 3 == 0 {
+                    "Late".to_owned()
+                } else {
+                    format!("Artist {}", i % 7)
+                };
+                let record = format!(
+                    r#"{{"ts": "{}", "ms_played": {}, "master_metadata_track_name": "Song {}",
+                    "master_metadata_album_artist_name": "{}", "master_metadata_album_album_name": "Album {}",
+                    "spotify_track_uri": "spotify:track:{}", "skipped": {}, "shuffle": {},
+                    "reason_start": "clickrow", "reason_end": "trackdone"}}"#,
+                    (first_ts + Duration::minutes(i as i64 * 37)).to_rfc3339(),
+                    i % 300_000,
+                    i % 50,
+                    artist,
+                    i % 11,
+                    i % 50,
+                    i % 4 == 0,
+                    i % 5 == 0,
+                );
 
-        if !grouped_data_map.contains_key(&key) {
-            grouped_data_map.insert(key.clone(), group);
+                plays::get_play_item_from_record_bytes(record.as_bytes(), PlayItemKind::Audio)
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    fn get_group_by_values() -> Vec<GroupBy> {
+        vec![
+            GroupBy::Song,
+            GroupBy::Artist,
+            GroupBy::Album,
+            GroupBy::HourOfDay,
+            GroupBy::Days(7),
+        ]
+    }
+
+    fn get_options(utc_offset_minutes: i32) -> GroupOptions {
+        GroupOptions {
+            utc_offset_minutes,
+            ..GroupOptions::default()
         }
+    }
+
+    fn get_ts(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn time_buckets_are_in_local_time() {
+        // A Sunday evening in UTC, which is already the Monday of the next year an hour ahead of UTC
+        let ts = get_ts(2023, 12, 31, 23, 30);
+
+        let get_bucket_names = |utc_offset_minutes: i32| -> Vec<String> {
+            let options = get_options(utc_offset_minutes);
+            [
+                GroupBy::HourOfDay,
+                GroupBy::DayOfWeek,
+                GroupBy::Month,
+                GroupBy::Year,
+            ]
+            .iter()
+            .map(|group_by| {
+                let time_bucket = get_time_bucket(&ts, group_by, &options).unwrap();
+                MetaData::from_time_bucket(group_by, time_bucket)
+                    .unwrap()
+                    .as_string()
+            })
+            .collect()
+        };
 
-        let Some(entry) = grouped_data_map.get_mut(&key) else {continue;};
-        update_hash_map_entry(entry, play_item, ms_played);
+        assert_eq!(get_bucket_names(0), ["23:00", "Sunday", "2023-12", "2023"]);
+        assert_eq!(get_bucket_names(60), ["00:00", "Monday", "2024-01", "2024"]);
+        assert_eq!(
+            get_bucket_names(-90),
+            ["22:00", "Sunday", "2023-12", "2023"]
+        );
+        assert_eq!(
+            get_time_bucket(&ts, &GroupBy::Artist, &get_options(0)),
+            None
+        );
     }
 
-    grouped_data_map.into_values().collect()
+    #[test]
+    fn windows_of_days_start_on_monday() {
+        let options = get_options(0);
+        let group_by = GroupBy::Days(7);
+        let get_bucket = |ts: DateTime<Utc>| get_time_bucket(&ts, &group_by, &options).unwrap();
+
+        let monday = get_bucket(get_ts(2023, 1, 2, 0, 0));
+        assert_eq!(monday, get_bucket(get_ts(2023, 1, 8, 23, 59)));
+        assert_eq!(monday + 1, get_bucket(get_ts(2023, 1, 9, 0, 0)));
+        assert_eq!(
+            MetaData::from_time_bucket(&group_by, monday + 1)
+                .unwrap()
+                .as_string(),
+            "2023-01-09 to 2023-01-15"
+        );
+
+        // Late on Sunday in UTC is already the next window an hour ahead of UTC
+        let ts = get_ts(2023, 1, 8, 23, 30);
+        assert_eq!(
+            get_time_bucket(&ts, &group_by, &get_options(60)),
+            Some(monday + 1)
+        );
+
+        // Windows of 3 days aren't lined up with weeks
+        let group_by = GroupBy::Days(3);
+        let get_bucket = |ts: DateTime<Utc>| get_time_bucket(&ts, &group_by, &options).unwrap();
+        let window = get_bucket(get_ts(2023, 1, 2, 0, 0));
+        let start_date = match MetaData::from_time_bucket(&group_by, window).unwrap() {
+            MetaData::Days { start_date, .. } => start_date,
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            get_bucket(start_date.and_hms_opt(0, 0, 0).unwrap().and_utc()),
+            window
+        );
+        assert_eq!(
+            get_bucket(
+                (start_date + Duration::days(3))
+                    .and_hms_opt(0, 0, 0)
+                    .unwrap()
+                    .and_utc()
+            ),
+            window + 1
+        );
+    }
+
+    #[test]
+    fn windows_of_zero_days_are_not_divided_by() {
+        assert_eq!(get_group_by("every_0_days"), None);
+        assert_eq!(get_group_by("every_7_days"), Some(GroupBy::Days(7)));
+
+        // Windows of 0 days are treated as windows of 1 day
+        let options = get_options(0);
+        let ts = get_ts(2023, 1, 2, 12, 0);
+        let time_bucket = get_time_bucket(&ts, &GroupBy::Days(0), &options);
+        assert_eq!(
+            time_bucket,
+            get_time_bucket(&ts, &GroupBy::Days(1), &options)
+        );
+        assert_eq!(
+            MetaData::from_time_bucket(&GroupBy::Days(0), time_bucket.unwrap())
+                .unwrap()
+                .as_string(),
+            "2023-01-02 to 2023-01-02"
+        );
+    }
+
+    #[test]
+    fn grouping_play_items_in_parallel_matches_grouping_in_order() {
+        let play_items = get_play_items();
+        let options = GroupOptions::default();
+
+        for group_by in get_group_by_values() {
+            let grouped_data = get_grouped_data(&group_by, &options, play_items.clone());
+            let sequential_grouped_data =
+                add_play_items_to_chunk(GroupedChunk::default(), &group_by, &options, &play_items)
+                    .groups;
+
+            assert_eq!(
+                serde_json::to_value(&grouped_data).unwrap(),
+                serde_json::to_value(&sequential_grouped_data).unwrap(),
+                "{:?}",
+                group_by
+            );
+        }
+    }
+
+    #[test]
+    fn grouping_store_in_parallel_matches_grouping_in_order() {
+        let play_store = PlayStore::from_play_items(&get_play_items());
+        let play_indices: Vec<usize> = (0..play_store.len()).collect();
+        let options = GroupOptions::default();
+
+        for group_by in get_group_by_values() {
+            let grouped_data =
+                get_grouped_data_from_store(&group_by, &options, &play_store, &play_indices);
+
+            let mut sequential_grouped_chunk = GroupedChunk::default();
+            let mut symbol_key_group_indices = SymbolKeyGroupIndices::new();
+            for &play_index in &play_indices {
+                add_store_play_to_chunk(
+                    &mut sequential_grouped_chunk,
+                    &mut symbol_key_group_indices,
+                    &group_by,
+                    &options,
+                    &play_store,
+                    play_index,
+                );
+            }
+
+            assert_eq!(
+                serde_json::to_value(&grouped_data).unwrap(),
+                serde_json::to_value(&sequential_grouped_chunk.groups).unwrap(),
+                "{:?}",
+                group_by
+            );
+        }
+    }
 }
@@ -0,0 +1,368 @@
+use crate::{
+    group::{self, Group, GroupBy, GroupKey, GroupOptions},
+    play_store::PlayStore,
+    sort::{self, SortSpotifyDataBy},
+};
+use eyre::{eyre, Result};
+use serde::Serialize;
+
+/// The levels that a `GroupTree` can be drilled down through
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hierarchy {
+    ArtistAlbumSong,
+    PodcastEpisode,
+    AudiobookChapter,
+}
+
+impl Hierarchy {
+    /// How plays are grouped at each level, from the top
+    fn get_levels(&self) -> &'static [GroupBy] {
+        match self {
+            Self::ArtistAlbumSong => &[GroupBy::Artist, GroupBy::Album, GroupBy::Song],
+            Self::PodcastEpisode => &[GroupBy::Podcast, GroupBy::PodcastEpisode],
+            Self::AudiobookChapter => &[GroupBy::Audiobook, GroupBy::AudiobookChapter],
+        }
+    }
+}
+
+/// A group in a `GroupTree`, and the groups that its plays are split into at the level below it. The
+/// aggregated data of a node covers all of the plays in it, so it's the same as that of its children added
+/// together (apart from plays that can't be put into a group at the level below, e.g. songs without an
+/// album).
+#[derive(Clone, Serialize)]
+pub struct GroupNode {
+    /// What the node is addressed by among its siblings, which stays the same however they are sorted
+    key: GroupKey,
+    group: Group,
+    /// None until the node has been expanded
+    children: Option<Vec<GroupNode>>,
+    #[serde(skip)]
+    play_indices: Vec<usize>,
+}
+
+impl AsRef<Group> for GroupNode {
+    fn as_ref(&self) -> &Group {
+        &self.group
+    }
+}
+
+fn find_node<'a>(nodes: &'a mut [GroupNode], key: &GroupKey) -> Option<&'a mut GroupNode> {
+    nodes.iter_mut().find(|node| node.key == *key)
+}
+
+fn get_nodes(
+    group_by: &GroupBy,
+    options: &GroupOptions,
+    play_store: &PlayStore,
+    play_indices: &[usize],
+) -> Vec<GroupNode> {
+    group::get_grouped_play_indices_from_store(group_by, options, play_store, play_indices)
+        .into_iter()
+        .map(|(group, play_indices)| GroupNode {
+            key: group.get_key().clone(),
+            group,
+            children: None,
+            play_indices,
+        })
+        .collect()
+}
+
+/// Plays grouped level by level (e.g. artists, then the albums of each artist, then the songs of each
+/// album), for drilling down from one level into the next. Only the top level is grouped at first, and
+/// each node's children are grouped from its own plays when it's expanded, so drilling into one node never
+/// has to group the whole dataset again.
+pub struct GroupTree {
+    hierarchy: Hierarchy,
+    options: GroupOptions,
+    nodes: Vec<GroupNode>,
+}
+
+impl GroupTree {
+    pub fn new(
+        hierarchy: Hierarchy,
+        options: &GroupOptions,
+        play_store: &PlayStore,
+        play_indices: &[usize],
+    ) -> Self {
+        let top_level = &hierarchy.get_levels()[0];
+
+        GroupTree {
+            hierarchy,
+            options: options.clone(),
+            nodes: get_nodes(top_level, options, play_store, play_indices),
+        }
+    }
+
+    pub fn get_nodes(&self) -> &[GroupNode] {
+        &self.nodes
+    }
+
+    /// Gets the node at `path`, which has the key of a node at each level from the top (e.g. an artist's key
+    /// and then the key of one of their albums), and the level that its children are grouped by
+    fn get_node_mut(
+        &mut self,
+        path: &[GroupKey],
+    ) -> Result<(&mut GroupNode, Option<&'static GroupBy>)> {
+        let levels = self.hierarchy.get_levels();
+        let Some((first_key, rest_of_path)) = path.split_first() else {
+            return Err(eyre!("The path to a node can't be empty."));
+        };
+
+        let mut node = find_node(&mut self.nodes, first_key)
+            .ok_or_else(|| eyre!("There is no node at {:?}.", path))?;
+
+        for key in rest_of_path {
+            node = node
+                .children
+                .as_deref_mut()
+                .and_then(|children| find_node(children, key))
+                .ok_or_else(|| eyre!("There is no node at {:?}.", path))?;
+        }
+
+        Ok((node, levels.get(path.len())))
+    }
+
+    /// Groups the plays of the node at `path` by the level below it, if it hasn't been expanded already,
+    /// and returns its children
+    pub fn expand(&mut self, path: &[GroupKey], play_store: &PlayStore) -> Result<&[GroupNode]> {
+        let options = self.options.clone();
+        let (node, child_level) = self.get_node_mut(path)?;
+        let Some(child_level) = child_level else {
+            return Err(eyre!("The node at {:?} is at the lowest level.", path));
+        };
+
+        let children = node.children.get_or_insert_with(|| {
+            get_nodes(child_level, &options, play_store, &node.play_indices)
+        });
+
+        Ok(children)
+    }
+
+    /// Expands every node at every level, so that the whole tree can be returned at once
+    pub fn expand_all(&mut self, play_store: &PlayStore) {
+        fn expand_nodes(
+            nodes: &mut [GroupNode],
+            levels: &[GroupBy],
+            options: &GroupOptions,
+            play_store: &PlayStore,
+        ) {
+            let Some((child_level, lower_levels)) = levels.split_first() else {
+                return;
+            };
+
+            for node in nodes {
+                let children = node.children.get_or_insert_with(|| {
+                    get_nodes(child_level, options, play_store, &node.play_indices)
+                });
+                expand_nodes(children, lower_levels, options, play_store);
+            }
+        }
+
+        let levels = self.hierarchy.get_levels();
+        expand_nodes(&mut self.nodes, &levels[1..], &self.options, play_store);
+    }
+
+    /// Sorts the children of the node at `path` (or the top level, if `path` is empty) without touching
+    /// the rest of the tree
+    pub fn sort_children(
+        &mut self,
+        path: &[GroupKey],
+        sort_by: SortSpotifyDataBy,
+        descending: bool,
+    ) -> Result<&[GroupNode]> {
+        let nodes = if path.is_empty() {
+            &mut self.nodes
+        } else {
+            let (node, _) = self.get_node_mut(path)?;
+            let Some(children) = node.children.as_mut() else {
+                return Err(eyre!("The node at {:?} hasn't been expanded.", path));
+            };
+            children
+        };
+
+        sort::sort_grouped_data(nodes, sort_by, descending);
+
+        Ok(nodes)
+    }
+
+    /// Sorts every level of the tree that has been expanded, e.g. after `expand_all`
+    pub fn sort_all(&mut self, sort_by: SortSpotifyDataBy, descending: bool) {
+        fn sort_nodes(nodes: &mut [GroupNode], sort_by: &SortSpotifyDataBy, descending: bool) {
+            sort::sort_grouped_data(nodes, sort_by.clone(), descending);
+
+            for node in nodes {
+                if let Some(children) = node.children.as_mut() {
+                    sort_nodes(children, sort_by, descending);
+                }
+            }
+        }
+
+        sort_nodes(&mut self.nodes, &sort_by, descending);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plays::{self, PlayItem, PlayItemKind};
+    use std::slice;
+
+    fn get_play_store() -> PlayStore {
+        let plays = [
+            ("A", "X", "Song 1", 1000),
+            ("A", "X", "Song 1", 2000),
+            ("A", "X", "Song 2", 3000),
+            ("A", "Y", "Song 3", 4000),
+            ("B", "Z", "Song 4", 5000),
+            ("B", "W", "Song 5", 6000),
+            ("B", "W", "Song 5", 7000),
+            ("B", "W", "Song 6", 8000),
+        ];
+
+        let play_items: Vec<PlayItem> = plays
+            .iter()
+            .enumerate()
+            .map(|(i, (artist, album, track, ms_played))| {
+                let record = format!(
+                    r#"{{"ts": "2023-01-01T{:02}:00:00Z", "ms_played": {}, "master_metadata_track_name": "{}",
+                    "master_metadata_album_artist_name": "{}", "master_metadata_album_album_name": "{}"}}"#,
+                    i, ms_played, track, artist, album
+                );
+
+                plays::get_play_item_from_record_bytes(record.as_bytes(), PlayItemKind::Audio)
+                    .unwrap()
+            })
+            .collect();
+
+        PlayStore::from_play_items(&play_items)
+    }
+
+    fn get_key(nodes: &[GroupNode], name: &str) -> GroupKey {
+        nodes
+            .iter()
+            .find(|node| node.group.get_metadata().as_string() == name)
+            .unwrap()
+            .key
+            .clone()
+    }
+
+    fn get_keys(nodes: &[GroupNode]) -> Vec<GroupKey> {
+        nodes.iter().map(|node| node.key.clone()).collect()
+    }
+
+    /// Checks that the aggregated data of a node is the same as that of its children added together
+    fn assert_totals_match_children(node: &GroupNode) {
+        let children = node.children.as_deref().unwrap();
+        let aggregated_data = node.group.get_aggregated_data();
+
+        assert_eq!(
+            children
+                .iter()
+                .map(|child| child.group.get_aggregated_data().play_count)
+                .sum::<u32>(),
+            aggregated_data.play_count
+        );
+        assert_eq!(
+            children
+                .iter()
+                .map(|child| child.group.get_aggregated_data().ms_played)
+                .sum::<u64>(),
+            aggregated_data.ms_played
+        );
+    }
+
+    fn get_tree(play_store: &PlayStore) -> GroupTree {
+        let play_indices: Vec<usize> = (0..play_store.len()).collect();
+        GroupTree::new(
+            Hierarchy::ArtistAlbumSong,
+            &GroupOptions::default(),
+            play_store,
+            &play_indices,
+        )
+    }
+
+    #[test]
+    fn expanded_nodes_add_up_to_their_parent() {
+        let play_store = get_play_store();
+        let mut tree = get_tree(&play_store);
+
+        let artist_key = get_key(tree.get_nodes(), "\"A\"");
+        let albums = tree
+            .expand(slice::from_ref(&artist_key), &play_store)
+            .unwrap();
+        assert_eq!(albums.len(), 2);
+        let album_key = get_key(albums, "\"X\" by \"A\"");
+        let songs = tree
+            .expand(&[artist_key.clone(), album_key.clone()], &play_store)
+            .unwrap();
+        assert_eq!(songs.len(), 2);
+        let song_key = get_key(songs, "\"Song 1\" on \"X\" by \"A\"");
+
+        let artist = &tree.nodes[0];
+        assert_totals_match_children(artist);
+        assert_totals_match_children(&artist.children.as_deref().unwrap()[0]);
+        assert_eq!(artist.group.get_aggregated_data().play_count, 4);
+        assert_eq!(artist.group.get_aggregated_data().ms_played, 10000);
+
+        // Songs are the lowest level
+        assert!(tree
+            .expand(&[artist_key, album_key, song_key], &play_store)
+            .is_err());
+    }
+
+    #[test]
+    fn only_expanded_nodes_can_be_sorted() {
+        let play_store = get_play_store();
+        let mut tree = get_tree(&play_store);
+        let artist_key = get_key(tree.get_nodes(), "\"B\"");
+
+        assert!(tree
+            .sort_children(
+                slice::from_ref(&artist_key),
+                SortSpotifyDataBy::PlayCount,
+                true
+            )
+            .is_err());
+
+        tree.expand(slice::from_ref(&artist_key), &play_store)
+            .unwrap();
+        assert!(tree
+            .sort_children(&[artist_key], SortSpotifyDataBy::PlayCount, true)
+            .is_ok());
+    }
+
+    #[test]
+    fn sorting_children_leaves_other_nodes_alone() {
+        let play_store = get_play_store();
+        let mut tree = get_tree(&play_store);
+        let a_key = get_key(tree.get_nodes(), "\"A\"");
+        let b_key = get_key(tree.get_nodes(), "\"B\"");
+
+        // B's albums are in the opposite order of how they'd be sorted
+        let b_album_keys = get_keys(tree.expand(slice::from_ref(&b_key), &play_store).unwrap());
+        tree.expand(slice::from_ref(&a_key), &play_store).unwrap();
+        let artist_keys = get_keys(tree.get_nodes());
+
+        let a_albums = tree
+            .sort_children(&[a_key], SortSpotifyDataBy::PlayCount, true)
+            .unwrap();
+        assert_eq!(
+            a_albums
+                .iter()
+                .map(|album| album.group.get_aggregated_data().play_count)
+                .collect::<Vec<_>>(),
+            [3, 1]
+        );
+
+        assert_eq!(get_keys(tree.get_nodes()), artist_keys);
+        let b_albums = tree.expand(&[b_key], &play_store).unwrap();
+        assert_eq!(get_keys(b_albums), b_album_keys);
+        assert_eq!(
+            b_albums
+                .iter()
+                .map(|album| album.group.get_aggregated_data().play_count)
+                .collect::<Vec<_>>(),
+            [1, 3]
+        );
+    }
+}
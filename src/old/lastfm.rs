@@ -0,0 +1,466 @@
+use crate::plays::{
+    self, FileIngestionReport, IngestionReport, LoadOptions, ParseMode, PlayItem, PlayItemKind,
+    PlayItemSource,
+};
+use chrono::prelude::*;
+use eyre::{eyre, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{fs, path::PathBuf, sync::Arc};
+
+/// One scrobble from a JSON dump of Last.fm's `user.getRecentTracks` API. Text fields are objects like
+/// `{"#text": "Name", "mbid": "..."}`, or `{"name": "Name", ...}` for the artist when `extended=1` is used.
+#[derive(Clone, Debug, Deserialize)]
+struct LastFmTrack {
+    artist: LastFmText,
+    album: Option<LastFmText>,
+    name: String,
+    /// Missing for the track that is playing right now, which hasn't been scrobbled yet
+    date: Option<LastFmDate>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct LastFmText {
+    #[serde(rename = "#text")]
+    text: Option<String>,
+    name: Option<String>,
+}
+
+impl LastFmText {
+    fn into_string(self) -> Option<String> {
+        self.text.or(self.name).filter(|text| !text.is_empty())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct LastFmDate {
+    /// Seconds since the Unix epoch, as a string
+    uts: String,
+}
+
+/// Which column of a Last.fm CSV export holds each field
+struct CsvColumns {
+    artist: usize,
+    album: Option<usize>,
+    track: usize,
+    timestamp: usize,
+}
+
+impl CsvColumns {
+    /// Exports without a header row are in the order used by most Last.fm export tools
+    fn without_header() -> Self {
+        CsvColumns {
+            artist: 0,
+            album: Some(1),
+            track: 2,
+            timestamp: 3,
+        }
+    }
+
+    /// Finds the columns from a header row, returning None if the row isn't a header
+    fn from_header(header: &csv::StringRecord) -> Option<Self> {
+        let find_column = |names: &[&str]| {
+            header.iter().position(|column_name| {
+                let column_name = column_name.trim().to_lowercase().replace(' ', "_");
+                names.contains(&column_name.as_str())
+            })
+        };
+
+        Some(CsvColumns {
+            artist: find_column(&["artist", "artist_name"])?,
+            album: find_column(&["album", "album_name"]),
+            track: find_column(&["track", "track_name", "name", "title", "song"])?,
+            timestamp: find_column(&["timestamp", "date", "uts", "utc_time", "time"])?,
+        })
+    }
+}
+
+/// Parses when a scrobble happened. Last.fm and the tools that export from it give this as seconds (or
+/// milliseconds) since the Unix epoch, an RFC 3339 date, or a date like "31 Jan 2023 18:05" in UTC.
+fn parse_lastfm_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    let timestamp = timestamp.trim();
+
+    if !timestamp.is_empty() && timestamp.bytes().all(|byte| byte.is_ascii_digit()) {
+        let timestamp: i64 = timestamp.parse().ok()?;
+        // Seconds won't reach 10^11 until the year 5138
+        return match timestamp < 100_000_000_000 {
+            true => Utc.timestamp_opt(timestamp, 0).single(),
+            false => Utc.timestamp_millis_opt(timestamp).single(),
+        };
+    }
+
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(timestamp) {
+        return Some(datetime.with_timezone(&Utc));
+    }
+
+    [
+        "%d %b %Y %H:%M",
+        "%d %b %Y, %H:%M",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M:%S",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(timestamp, format).ok())
+    .map(|datetime| Utc.from_utc_datetime(&datetime))
+}
+
+/// Makes a PlayItem out of a scrobble. The play's `ts` is when the scrobble started, since how long it
+/// lasted isn't known until `estimate::estimate_missing_fields` is used.
+fn get_play_item_from_scrobble(
+    artist_name: String,
+    album_name: Option<String>,
+    track_name: String,
+    started_at: DateTime<Utc>,
+    source_file: &Arc<str>,
+) -> PlayItem {
+    PlayItem {
+        audiobook_chapter_title: None,
+        audiobook_chapter_uri: None,
+        audiobook_title: None,
+        audiobook_uri: None,
+        conn_country: None,
+        episode_name: None,
+        episode_show_name: None,
+        incognito_mode: None,
+        ip_addr_decrypted: None,
+        master_metadata_album_album_name: album_name,
+        master_metadata_album_artist_name: Some(artist_name),
+        master_metadata_track_name: Some(track_name),
+        ms_played: None,
+        offline: None,
+        offline_timestamp: None,
+        platform: None,
+        reason_end: None,
+        reason_start: None,
+        shuffle: None,
+        skipped: None,
+        spotify_episode_uri: None,
+        spotify_track_uri: None,
+        ts: started_at,
+        user_agent_decrypted: None,
+        username: None,
+        kind: PlayItemKind::Audio,
+        source: PlayItemSource::LastFm,
+        source_file: Some(Arc::clone(source_file)),
+    }
+}
+
+fn get_plays_from_lastfm_csv(
+    csv_bytes: &[u8],
+    options: &LoadOptions,
+    file_report: &mut FileIngestionReport,
+) -> std::result::Result<Vec<PlayItem>, String> {
+    let source_file: Arc<str> = Arc::from(file_report.file_name.as_str());
+
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(csv_bytes);
+    let mut records = reader.records().peekable();
+
+    let columns = match records.peek() {
+        Some(Ok(first_record)) => match CsvColumns::from_header(first_record) {
+            Some(columns) => {
+                records.next();
+                columns
+            }
+            None => CsvColumns::without_header(),
+        },
+        _ => CsvColumns::without_header(),
+    };
+
+    let mut play_items = vec![];
+    for (record_index, record) in records.enumerate() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                plays::handle_failed_record(record_index, e.to_string(), options, file_report)?;
+                continue;
+            }
+        };
+
+        let get_field = |column: usize| {
+            record
+                .get(column)
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+                .map(str::to_owned)
+        };
+
+        let (Some(artist_name), Some(track_name), Some(timestamp)) = (
+            get_field(columns.artist),
+            get_field(columns.track),
+            get_field(columns.timestamp),
+        ) else {
+            let error = "The scrobble is missing its artist, track or timestamp.".to_owned();
+            plays::handle_failed_record(record_index, error, options, file_report)?;
+            continue;
+        };
+
+        let Some(started_at) = parse_lastfm_timestamp(&timestamp) else {
+            let error = format!("The timestamp \"{}\" is not valid.", timestamp);
+            plays::handle_failed_record(record_index, error, options, file_report)?;
+            continue;
+        };
+
+        let album_name = columns.album.and_then(get_field);
+
+        play_items.push(get_play_item_from_scrobble(
+            artist_name,
+            album_name,
+            track_name,
+            started_at,
+            &source_file,
+        ));
+    }
+
+    Ok(play_items)
+}
+
+/// Finds the scrobbles in a JSON dump, which can be a single `user.getRecentTracks` response, an array of
+/// them (one per page), or an array of the scrobbles themselves
+fn get_lastfm_tracks_from_json(json: Value) -> std::result::Result<Vec<Value>, String> {
+    let get_page_tracks = |mut page: Value| match page["recenttracks"]["track"].take() {
+        Value::Array(tracks) => Ok(tracks),
+        // A page with a single scrobble has it as an object instead of an array
+        track @ Value::Object(_) => Ok(vec![track]),
+        _ => Err("Expected a Last.fm \"recenttracks\" response.".to_owned()),
+    };
+
+    match json {
+        Value::Array(values) => {
+            let mut tracks = vec![];
+            for value in values {
+                match value.get("recenttracks") {
+                    Some(_) => tracks.append(&mut get_page_tracks(value)?),
+                    None => tracks.push(value),
+                }
+            }
+            Ok(tracks)
+        }
+        page @ Value::Object(_) => get_page_tracks(page),
+        _ => Err("Expected the file to contain Last.fm scrobbles.".to_owned()),
+    }
+}
+
+fn get_plays_from_lastfm_json(
+    json_bytes: &[u8],
+    options: &LoadOptions,
+    file_report: &mut FileIngestionReport,
+) -> std::result::Result<Vec<PlayItem>, String> {
+    let source_file: Arc<str> = Arc::from(file_report.file_name.as_str());
+
+    let json: Value = serde_json::from_slice(json_bytes).map_err(|e| e.to_string())?;
+
+    let mut play_items = vec![];
+    for (record_index, track) in get_lastfm_tracks_from_json(json)?.into_iter().enumerate() {
+        let track: LastFmTrack = match serde_json::from_value(track) {
+            Ok(track) => track,
+            Err(e) => {
+                plays::handle_failed_record(record_index, e.to_string(), options, file_report)?;
+                continue;
+            }
+        };
+
+        // The track that is playing right now shows up at the top of the first page
+        let Some(date) = track.date else {
+            continue;
+        };
+
+        let Some(started_at) = parse_lastfm_timestamp(&date.uts) else {
+            let error = format!("The timestamp \"{}\" is not valid.", date.uts);
+            plays::handle_failed_record(record_index, error, options, file_report)?;
+            continue;
+        };
+
+        let Some(artist_name) = track.artist.into_string() else {
+            let error = "The scrobble has no artist.".to_owned();
+            plays::handle_failed_record(record_index, error, options, file_report)?;
+            continue;
+        };
+
+        let album_name = track.album.and_then(LastFmText::into_string);
+
+        play_items.push(get_play_item_from_scrobble(
+            artist_name,
+            album_name,
+            track.name,
+            started_at,
+            &source_file,
+        ));
+    }
+
+    Ok(play_items)
+}
+
+/// Extracts plays from Last.fm exports, given as the name and contents of each file. CSV files are read as
+/// artist, album, track and timestamp columns (or by their header, if they have one), and JSON files as
+/// dumps of Last.fm's `user.getRecentTracks` API.
+///
+/// The plays are in the order that they were scrobbled in. Their `ts` is when they started, until
+/// `estimate::estimate_missing_fields` is used on them.
+///
+/// This does not touch the filesystem, so it can also be used where there isn't one (e.g. the wasm build).
+pub fn extract_plays_from_lastfm_files(
+    files: &[(String, Vec<u8>)],
+    options: &LoadOptions,
+) -> Result<(Vec<PlayItem>, IngestionReport)> {
+    let mut all_play_items = vec![];
+    let mut report = IngestionReport::new(options);
+
+    for (file_name, file_bytes) in files {
+        let mut file_report = FileIngestionReport {
+            file_name: file_name.to_owned(),
+            ..FileIngestionReport::default()
+        };
+
+        let play_items = if file_name.to_lowercase().ends_with(".json") {
+            get_plays_from_lastfm_json(file_bytes, options, &mut file_report)
+        } else {
+            get_plays_from_lastfm_csv(file_bytes, options, &mut file_report)
+        };
+
+        match play_items {
+            Ok(mut play_items) => {
+                file_report.plays_loaded = play_items.len();
+                all_play_items.append(&mut play_items);
+            }
+            Err(error) => match options.parse_mode {
+                ParseMode::Strict => return Err(eyre!("Unable to load {}. {}", file_name, error)),
+                ParseMode::Lenient => file_report.error = Some(error),
+            },
+        }
+
+        report.files.push(file_report);
+    }
+
+    // The API lists the newest scrobbles first
+    all_play_items.sort_by_key(|play_item| play_item.ts);
+
+    Ok((all_play_items, report))
+}
+
+pub fn extract_plays_from_lastfm_paths(
+    file_paths: &[PathBuf],
+    options: &LoadOptions,
+) -> Result<(Vec<PlayItem>, IngestionReport)> {
+    let mut files = vec![];
+    for path in file_paths {
+        let file_name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        files.push((file_name, fs::read(path)?));
+    }
+
+    extract_plays_from_lastfm_files(&files, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extract_plays(file_name: &str, file: &str) -> (Vec<PlayItem>, IngestionReport) {
+        let files = [(file_name.to_owned(), file.as_bytes().to_vec())];
+        extract_plays_from_lastfm_files(&files, &LoadOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn reads_csv_rows() {
+        let (play_items, report) = extract_plays(
+            "scrobbles.csv",
+            "Artist,Album,Track,31 Jan 2023 18:05\n\
+            Other Artist,,\"Track, With Comma\",1675188000\n\
+            Artist,Album,,31 Jan 2023 18:10\n",
+        );
+
+        assert_eq!(play_items.len(), 2);
+        // Plays are in the order that they were scrobbled in
+        assert_eq!(
+            play_items[0].master_metadata_track_name.as_deref(),
+            Some("Track, With Comma")
+        );
+        assert_eq!(play_items[0].master_metadata_album_album_name, None);
+        assert_eq!(
+            play_items[0].ts,
+            Utc.with_ymd_and_hms(2023, 1, 31, 18, 0, 0).unwrap()
+        );
+        assert_eq!(
+            play_items[1].master_metadata_album_artist_name.as_deref(),
+            Some("Artist")
+        );
+        assert_eq!(
+            play_items[1].master_metadata_album_album_name.as_deref(),
+            Some("Album")
+        );
+        assert_eq!(
+            play_items[1].ts,
+            Utc.with_ymd_and_hms(2023, 1, 31, 18, 5, 0).unwrap()
+        );
+        assert_eq!(play_items[1].ms_played, None);
+        assert_eq!(play_items[1].source, PlayItemSource::LastFm);
+        assert_eq!(play_items[1].source_file.as_deref(), Some("scrobbles.csv"));
+
+        // The row without a track is skipped
+        assert_eq!(report.files[0].plays_loaded, 2);
+        assert_eq!(report.files[0].skipped_records.len(), 1);
+        assert_eq!(report.files[0].skipped_records[0].record_index, 2);
+    }
+
+    #[test]
+    fn finds_csv_columns_by_header() {
+        let (play_items, _) = extract_plays(
+            "scrobbles.csv",
+            "uts,utc_time,artist,album,track\n1675188000,31 Jan 2023 18:00,Artist,Album,Track\n",
+        );
+
+        assert_eq!(play_items.len(), 1);
+        assert_eq!(
+            play_items[0].master_metadata_track_name.as_deref(),
+            Some("Track")
+        );
+        assert_eq!(
+            play_items[0].ts,
+            Utc.with_ymd_and_hms(2023, 1, 31, 18, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn reads_api_dump_page() {
+        let (play_items, report) = extract_plays(
+            "scrobbles.json",
+            r##"{"recenttracks": {"track": [
+                {"artist": {"#text": "Artist"}, "album": {"#text": ""}, "name": "Now Playing",
+                    "@attr": {"nowplaying": "true"}},
+                {"artist": {"#text": "Artist"}, "album": {"#text": "Album"}, "name": "Second",
+                    "date": {"uts": "1675188300", "#text": "31 Jan 2023, 18:05"}},
+                {"artist": {"name": "Artist"}, "album": {"#text": ""}, "name": "First",
+                    "date": {"uts": "1675188000", "#text": "31 Jan 2023, 18:00"}}
+            ]}}"##,
+        );
+
+        // The track that is playing right now hasn't been scrobbled yet
+        assert_eq!(play_items.len(), 2);
+        assert_eq!(report.files[0].plays_loaded, 2);
+
+        assert_eq!(
+            play_items[0].master_metadata_track_name.as_deref(),
+            Some("First")
+        );
+        assert_eq!(
+            play_items[0].master_metadata_album_artist_name.as_deref(),
+            Some("Artist")
+        );
+        assert_eq!(play_items[0].master_metadata_album_album_name, None);
+        assert_eq!(
+            play_items[1].master_metadata_album_album_name.as_deref(),
+            Some("Album")
+        );
+        assert_eq!(
+            play_items[1].ts,
+            Utc.with_ymd_and_hms(2023, 1, 31, 18, 5, 0).unwrap()
+        );
+    }
+}
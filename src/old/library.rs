@@ -0,0 +1,886 @@
+use crate::{
+    play_store::PlayStore,
+    plays::{self, FileIngestionReport, IngestionReport, LoadOptions, ParseMode},
+};
+use chrono::{prelude::*, Duration};
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs,
+    io::{Cursor, Read},
+    path::Path,
+};
+use zip::ZipArchive;
+
+/// A track saved to "Liked Songs", from `YourLibrary.json`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SavedTrack {
+    #[serde(rename(deserialize = "artist"))]
+    pub artist_name: String,
+    #[serde(rename(deserialize = "album"))]
+    pub album_name: Option<String>,
+    #[serde(rename(deserialize = "track"))]
+    pub track_name: String,
+    pub uri: Option<String>,
+}
+
+/// A playlist, from one of the `PlaylistN.json` files
+#[derive(Clone, Debug, Serialize)]
+pub struct Playlist {
+    pub name: String,
+    pub last_modified_date: Option<NaiveDate>,
+    pub items: Vec<PlaylistItem>,
+}
+
+/// A song, podcast episode or local file in a playlist
+#[derive(Clone, Debug, Serialize)]
+pub struct PlaylistItem {
+    pub track_name: Option<String>,
+    pub artist_name: Option<String>,
+    pub album_name: Option<String>,
+    pub episode_name: Option<String>,
+    pub show_name: Option<String>,
+    pub uri: Option<String>,
+    pub added_date: Option<NaiveDate>,
+}
+
+/// Something that was searched for, from `SearchQueries.json`
+#[derive(Clone, Debug, Serialize)]
+pub struct SearchQuery {
+    pub platform: Option<String>,
+    pub search_time: DateTime<Utc>,
+    pub search_query: String,
+    /// The URIs of the results that were clicked on after searching
+    pub search_interaction_uris: Vec<String>,
+}
+
+/// The parts of the "Account data" export that aren't play history
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct LibraryData {
+    pub saved_tracks: Vec<SavedTrack>,
+    pub playlists: Vec<Playlist>,
+    pub search_queries: Vec<SearchQuery>,
+}
+
+impl LibraryData {
+    pub fn is_empty(&self) -> bool {
+        self.saved_tracks.is_empty() && self.playlists.is_empty() && self.search_queries.is_empty()
+    }
+}
+
+/// `YourLibrary.json`, exactly as it's stored. Only the saved tracks are kept.
+#[derive(Clone, Debug, Deserialize)]
+struct RawLibrary {
+    #[serde(default)]
+    tracks: Vec<SavedTrack>,
+}
+
+/// A `PlaylistN.json` file, exactly as it's stored
+#[derive(Clone, Debug, Deserialize)]
+struct RawPlaylists {
+    playlists: Vec<RawPlaylist>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawPlaylist {
+    name: String,
+    last_modified_date: Option<String>,
+    #[serde(default)]
+    items: Vec<RawPlaylistItem>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawPlaylistItem {
+    track: Option<RawPlaylistTrack>,
+    episode: Option<RawPlaylistEpisode>,
+    local_track: Option<RawPlaylistLocalTrack>,
+    added_date: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawPlaylistTrack {
+    track_name: Option<String>,
+    artist_name: Option<String>,
+    album_name: Option<String>,
+    track_uri: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawPlaylistEpisode {
+    episode_name: Option<String>,
+    show_name: Option<String>,
+    episode_uri: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct RawPlaylistLocalTrack {
+    uri: Option<String>,
+}
+
+impl From<RawPlaylist> for Playlist {
+    fn from(raw_playlist: RawPlaylist) -> Self {
+        let items = raw_playlist
+            .items
+            .into_iter()
+            .map(|raw_item| {
+                let track = raw_item.track;
+                let episode = raw_item.episode;
+                let local_track_uri = raw_item.local_track.and_then(|local_track| local_track.uri);
+
+                PlaylistItem {
+                    track_name: track.as_ref().and_then(|track| track.track_name.clone()),
+                    artist_name: track.as_ref().and_then(|track| track.artist_name.clone()),
+                    album_name: track.as_ref().and_then(|track| track.album_name.clone()),
+                    episode_name: episode
+                        .as_ref()
+                        .and_then(|episode| episode.episode_name.clone()),
+                    show_name: episode
+                        .as_ref()
+                        .and_then(|episode| episode.show_name.clone()),
+                    uri: track
+                        .and_then(|track| track.track_uri)
+                        .or(episode.and_then(|episode| episode.episode_uri))
+                        .or(local_track_uri),
+                    added_date: raw_item.added_date.as_deref().and_then(parse_date),
+                }
+            })
+            .collect();
+
+        Playlist {
+            name: raw_playlist.name,
+            last_modified_date: raw_playlist
+                .last_modified_date
+                .as_deref()
+                .and_then(parse_date),
+            items,
+        }
+    }
+}
+
+/// `SearchQueries.json` is an array of these, exactly as they're stored
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawSearchQuery {
+    platform: Option<String>,
+    search_time: String,
+    search_query: String,
+    #[serde(default, rename = "searchInteractionURIs")]
+    search_interaction_uris: Vec<String>,
+}
+
+impl TryFrom<RawSearchQuery> for SearchQuery {
+    type Error = String;
+
+    fn try_from(raw_search_query: RawSearchQuery) -> std::result::Result<Self, Self::Error> {
+        // Search times look like "2023-01-05T14:03:27.123Z[UTC]"
+        let search_time = raw_search_query
+            .search_time
+            .split('[')
+            .next()
+            .unwrap_or_default();
+
+        let Ok(search_time) = search_time.parse::<DateTime<Utc>>() else {
+            return Err(format!(
+                "The search time \"{}\" is not valid.",
+                raw_search_query.search_time
+            ));
+        };
+
+        Ok(SearchQuery {
+            platform: raw_search_query.platform,
+            search_time,
+            search_query: raw_search_query.search_query,
+            search_interaction_uris: raw_search_query.search_interaction_uris,
+        })
+    }
+}
+
+fn parse_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
+}
+
+/// The files of the "Account data" export that are read into LibraryData
+enum LibraryFile {
+    YourLibrary,
+    Playlist,
+    SearchQueries,
+}
+
+fn parse_library_file_name(file_name: &str) -> Option<LibraryFile> {
+    let file_stem = file_name.strip_suffix(".json")?;
+
+    match file_stem {
+        "YourLibrary" => Some(LibraryFile::YourLibrary),
+        "SearchQueries" => Some(LibraryFile::SearchQueries),
+        _ => {
+            let index = file_stem.strip_prefix("Playlist")?;
+            match !index.is_empty() && index.bytes().all(|byte| byte.is_ascii_digit()) {
+                true => Some(LibraryFile::Playlist),
+                false => None,
+            }
+        }
+    }
+}
+
+/// Adds what's in a library file to `library_data`. Search queries are read one by one, so in lenient mode
+/// one that can't be read is skipped and added to the file's report instead of failing the whole file.
+fn add_library_file(
+    library_data: &mut LibraryData,
+    library_file: LibraryFile,
+    file_bytes: &[u8],
+    options: &LoadOptions,
+    file_report: &mut FileIngestionReport,
+) -> std::result::Result<(), String> {
+    match library_file {
+        LibraryFile::YourLibrary => {
+            let raw_library: RawLibrary =
+                serde_json::from_slice(file_bytes).map_err(|e| e.to_string())?;
+            library_data.saved_tracks.extend(raw_library.tracks);
+        }
+        LibraryFile::Playlist => {
+            let raw_playlists: RawPlaylists =
+                serde_json::from_slice(file_bytes).map_err(|e| e.to_string())?;
+            library_data
+                .playlists
+                .extend(raw_playlists.playlists.into_iter().map(Playlist::from));
+        }
+        LibraryFile::SearchQueries => {
+            let records: Vec<serde_json::Value> =
+                serde_json::from_slice(file_bytes).map_err(|e| e.to_string())?;
+
+            for (record_index, record) in records.into_iter().enumerate() {
+                let search_query = serde_json::from_value::<RawSearchQuery>(record)
+                    .map_err(|e| e.to_string())
+                    .and_then(SearchQuery::try_from);
+
+                match search_query {
+                    Ok(search_query) => library_data.search_queries.push(search_query),
+                    Err(error) => {
+                        plays::handle_failed_record(record_index, error, options, file_report)?
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the library files in `files` (name and contents), adding a report for each one to `report`.
+/// Library files aren't needed for the play history, so in lenient mode a file that can't be read is only
+/// reported.
+fn extract_library_from_files(
+    files: impl Iterator<Item = Result<(String, Vec<u8>)>>,
+    options: &LoadOptions,
+    report: &mut IngestionReport,
+) -> Result<LibraryData> {
+    let mut library_data = LibraryData::default();
+
+    for file in files {
+        let (file_name, file_bytes) = file?;
+        let Some(library_file) = parse_library_file_name(&file_name) else {
+            continue;
+        };
+
+        let mut file_report = FileIngestionReport {
+            file_name,
+            ..FileIngestionReport::default()
+        };
+
+        if let Err(error) = add_library_file(
+            &mut library_data,
+            library_file,
+            &file_bytes,
+            options,
+            &mut file_report,
+        ) {
+            match options.parse_mode {
+                ParseMode::Strict => {
+                    return Err(eyre!("Unable to load {}. {}", file_report.file_name, error))
+                }
+                ParseMode::Lenient => file_report.error = Some(error),
+            }
+        }
+
+        report.files.push(file_report);
+    }
+
+    library_data
+        .search_queries
+        .sort_by_key(|search_query| search_query.search_time);
+
+    Ok(library_data)
+}
+
+/// Extracts the saved tracks, playlists and search queries from the "Account data" export files in a
+/// folder. The folder not having any of them isn't an error, since the extended streaming history export
+/// doesn't include them.
+pub fn extract_library_from_path(
+    base_path: &Path,
+    options: &LoadOptions,
+    report: &mut IngestionReport,
+) -> Result<LibraryData> {
+    let files = fs::read_dir(base_path)?.filter_map(|dir_entry| {
+        let file_path = match dir_entry {
+            Ok(dir_entry) => dir_entry.path(),
+            Err(e) => return Some(Err(e.into())),
+        };
+
+        let file_name = file_path.file_name()?.to_str()?.to_owned();
+        parse_library_file_name(&file_name)?;
+
+        Some(
+            fs::read(&file_path)
+                .map(|file_bytes| (file_name, file_bytes))
+                .map_err(|e| e.into()),
+        )
+    });
+
+    extract_library_from_files(files, options, report)
+}
+
+/// Extracts the saved tracks, playlists and search queries from the "Account data" export zip (found by
+/// name anywhere inside of it).
+///
+/// This does not touch the filesystem, so it can also be used where there isn't one (e.g. the wasm build).
+pub fn extract_library_from_zip_bytes(
+    zip_bytes: &[u8],
+    options: &LoadOptions,
+    report: &mut IngestionReport,
+) -> Result<LibraryData> {
+    let mut archive = ZipArchive::new(Cursor::new(zip_bytes))?;
+
+    let files = (0..archive.len()).filter_map(|i| {
+        let mut entry = match archive.by_index(i) {
+            Ok(entry) => entry,
+            Err(e) => return Some(Err(e.into())),
+        };
+
+        let file_name = entry.name().rsplit(['/', '\\']).next()?.to_owned();
+        parse_library_file_name(&file_name)?;
+
+        let mut file_bytes = vec![];
+        Some(
+            entry
+                .read_to_end(&mut file_bytes)
+                .map(|_| (file_name, file_bytes))
+                .map_err(|e| e.into()),
+        )
+    });
+
+    extract_library_from_files(files, options, report)
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////////////////////
+// Questions about the library
+/////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// A set of songs and episodes, by URI and by (artist or show, track or episode) names. Plays from the
+/// "Account data" export have no URIs, so they can only be matched by name.
+#[derive(Default)]
+struct ItemSet {
+    uris: HashSet<String>,
+    names: HashSet<(String, String)>,
+}
+
+impl ItemSet {
+    fn from_plays(play_store: &PlayStore) -> Self {
+        let mut item_set = ItemSet::default();
+        for index in 0..play_store.len() {
+            item_set.insert(
+                get_play_uri(play_store, index),
+                get_play_names(play_store, index),
+            );
+        }
+        item_set
+    }
+
+    fn from_playlist(playlist: &Playlist) -> Self {
+        let mut item_set = ItemSet::default();
+        for playlist_item in &playlist.items {
+            item_set.insert(
+                playlist_item.uri.as_deref(),
+                get_playlist_item_names(playlist_item),
+            );
+        }
+        item_set
+    }
+
+    fn insert(&mut self, uri: Option<&str>, names: Option<(&str, &str)>) {
+        if let Some(uri) = uri {
+            self.uris.insert(uri.to_owned());
+        }
+        if let Some((artist_name, track_name)) = names {
+            self.names
+                .insert((artist_name.to_lowercase(), track_name.to_lowercase()));
+        }
+    }
+
+    fn contains(&self, uri: Option<&str>, names: Option<(&str, &str)>) -> bool {
+        uri.is_some_and(|uri| self.uris.contains(uri))
+            || names.is_some_and(|(artist_name, track_name)| {
+                self.names
+                    .contains(&(artist_name.to_lowercase(), track_name.to_lowercase()))
+            })
+    }
+}
+
+fn get_play_uri(play_store: &PlayStore, index: usize) -> Option<&str> {
+    play_store
+        .get_spotify_track_uri(index)
+        .or(play_store.get_spotify_episode_uri(index))
+}
+
+fn get_play_names(play_store: &PlayStore, index: usize) -> Option<(&str, &str)> {
+    play_store
+        .get_artist_name(index)
+        .zip(play_store.get_track_name(index))
+        .or(play_store
+            .get_episode_show_name(index)
+            .zip(play_store.get_episode_name(index)))
+}
+
+fn get_playlist_item_names(playlist_item: &PlaylistItem) -> Option<(&str, &str)> {
+    playlist_item
+        .artist_name
+        .as_deref()
+        .zip(playlist_item.track_name.as_deref())
+        .or(playlist_item
+            .show_name
+            .as_deref()
+            .zip(playlist_item.episode_name.as_deref()))
+}
+
+/// Gets the saved tracks that don't have a single play
+pub fn get_unplayed_saved_tracks<'a>(
+    library_data: &'a LibraryData,
+    play_store: &PlayStore,
+) -> Vec<&'a SavedTrack> {
+    let played_items = ItemSet::from_plays(play_store);
+
+    library_data
+        .saved_tracks
+        .iter()
+        .filter(|saved_track| {
+            !played_items.contains(
+                saved_track.uri.as_deref(),
+                Some((&saved_track.artist_name, &saved_track.track_name)),
+            )
+        })
+        .collect()
+}
+
+/// How much of a playlist's content has been played. Plays of its songs count whether or not they were
+/// played from the playlist, since the streaming history doesn't say where a play came from.
+#[derive(Clone, Debug, Serialize)]
+pub struct PlaylistPlayStats {
+    pub name: String,
+    pub item_count: usize,
+    /// How many of the playlist's items have been played at least once
+    pub played_item_count: usize,
+    pub play_count: u32,
+    pub ms_played: u64,
+}
+
+pub fn get_playlist_play_stats(
+    library_data: &LibraryData,
+    play_store: &PlayStore,
+) -> Vec<PlaylistPlayStats> {
+    let played_items = ItemSet::from_plays(play_store);
+
+    library_data
+        .playlists
+        .iter()
+        .map(|playlist| {
+            let playlist_items = ItemSet::from_playlist(playlist);
+
+            let playlist_plays = (0..play_store.len()).filter(|&index| {
+                playlist_items.contains(
+                    get_play_uri(play_store, index),
+                    get_play_names(play_store, index),
+                )
+            });
+
+            PlaylistPlayStats {
+                name: playlist.name.clone(),
+                item_count: playlist.items.len(),
+                played_item_count: playlist
+                    .items
+                    .iter()
+                    .filter(|item| {
+                        played_items.contains(item.uri.as_deref(), get_playlist_item_names(item))
+                    })
+                    .count(),
+                play_count: playlist_plays.clone().count() as u32,
+                ms_played: playlist_plays
+                    .filter_map(|index| play_store.get_ms_played(index))
+                    .sum(),
+            }
+        })
+        .collect()
+}
+
+/// A search that was followed by a play of one of the results that were clicked on
+#[derive(Clone, Debug, Serialize)]
+pub struct SearchFollowedByPlay {
+    pub search_query: String,
+    pub search_time: DateTime<Utc>,
+    pub played_uri: String,
+    /// How long after the search the play started
+    pub seconds_until_play: i64,
+}
+
+/// Gets the searches that led to a play of one of their clicked results within `within` of the search.
+/// Searches without any clicked results can't be linked to a play.
+pub fn get_searches_followed_by_plays(
+    library_data: &LibraryData,
+    play_store: &PlayStore,
+    within: Duration,
+) -> Vec<SearchFollowedByPlay> {
+    // Plays by when they started
+    let mut play_starts: Vec<(DateTime<Utc>, &str)> = (0..play_store.len())
+        .filter_map(|index| {
+            let uri = get_play_uri(play_store, index)?;
+            let ms_played = play_store.get_ms_played(index).unwrap_or_default();
+            let started_at = play_store.get_ts(index) - Duration::milliseconds(ms_played as i64);
+            Some((started_at, uri))
+        })
+        .collect();
+    play_starts.sort_unstable();
+
+    library_data
+        .search_queries
+        .iter()
+        .filter_map(|search_query| {
+            let first_play_index = play_starts
+                .partition_point(|(started_at, _)| *started_at < search_query.search_time);
+
+            let (started_at, played_uri) = play_starts[first_play_index..]
+                .iter()
+                .take_while(|(started_at, _)| *started_at <= search_query.search_time + within)
+                .find(|(_, uri)| {
+                    search_query
+                        .search_interaction_uris
+                        .iter()
+                        .any(|search_interaction_uri| search_interaction_uri == uri)
+                })?;
+
+            Some(SearchFollowedByPlay {
+                search_query: search_query.search_query.clone(),
+                search_time: search_query.search_time,
+                played_uri: (*played_uri).to_owned(),
+                seconds_until_play: (*started_at - search_query.search_time).num_seconds(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plays::{PlayItem, PlayItemKind};
+
+    /// A play of the song "Song <id>" by "Artist", whose URI is "spotify:track:<id>"
+    fn get_play(ts: &str, ms_played: u64, id: &str) -> PlayItem {
+        let record = serde_json::json!({
+            "ts": ts,
+            "ms_played": ms_played,
+            "spotify_track_uri": format!("spotify:track:{}", id),
+            "master_metadata_album_artist_name": "Artist",
+            "master_metadata_track_name": format!("Song {}", id),
+        })
+        .to_string();
+
+        plays::get_play_item_from_record_bytes(record.as_bytes(), PlayItemKind::Audio).unwrap()
+    }
+
+    fn get_saved_track(uri: Option<&str>, artist_name: &str, track_name: &str) -> SavedTrack {
+        SavedTrack {
+            artist_name: artist_name.to_owned(),
+            album_name: None,
+            track_name: track_name.to_owned(),
+            uri: uri.map(str::to_owned),
+        }
+    }
+
+    fn get_search_query(
+        search_time: DateTime<Utc>,
+        search_interaction_uris: &[&str],
+    ) -> SearchQuery {
+        SearchQuery {
+            platform: None,
+            search_time,
+            search_query: "query".to_owned(),
+            search_interaction_uris: search_interaction_uris
+                .iter()
+                .map(|uri| uri.to_string())
+                .collect(),
+        }
+    }
+
+    fn extract_library(files: &[(&str, &str)]) -> (LibraryData, IngestionReport) {
+        let options = LoadOptions::default();
+        let mut report = IngestionReport::new(&options);
+        let files = files
+            .iter()
+            .map(|(file_name, file)| Ok((file_name.to_string(), file.as_bytes().to_vec())));
+
+        let library_data = extract_library_from_files(files, &options, &mut report).unwrap();
+        (library_data, report)
+    }
+
+    #[test]
+    fn matches_library_file_names() {
+        assert!(matches!(
+            parse_library_file_name("Playlist1.json"),
+            Some(LibraryFile::Playlist)
+        ));
+        assert!(matches!(
+            parse_library_file_name("Playlist12.json"),
+            Some(LibraryFile::Playlist)
+        ));
+        assert!(matches!(
+            parse_library_file_name("YourLibrary.json"),
+            Some(LibraryFile::YourLibrary)
+        ));
+        assert!(matches!(
+            parse_library_file_name("SearchQueries.json"),
+            Some(LibraryFile::SearchQueries)
+        ));
+
+        assert!(parse_library_file_name("Playlist.json").is_none());
+        assert!(parse_library_file_name("PlaylistA.json").is_none());
+        assert!(parse_library_file_name("Playlist1.json.bak").is_none());
+        assert!(parse_library_file_name("StreamingHistory0.json").is_none());
+    }
+
+    #[test]
+    fn reads_search_queries_with_time_zone_suffix() {
+        let (library_data, report) = extract_library(&[(
+            "SearchQueries.json",
+            r#"[
+                {"platform": "ANDROID", "searchTime": "2023-01-05T14:03:27.123Z[UTC]",
+                    "searchQuery": "second", "searchInteractionURIs": ["spotify:track:abc"]},
+                {"platform": "ANDROID", "searchTime": "2023-01-04T09:00:00Z[UTC]",
+                    "searchQuery": "first"}
+            ]"#,
+        )]);
+
+        assert_eq!(report.files.len(), 1);
+        assert_eq!(report.files[0].error, None);
+
+        // Searches are put in order of when they were made
+        let search_queries = &library_data.search_queries;
+        assert_eq!(search_queries[0].search_query, "first");
+        assert!(search_queries[0].search_interaction_uris.is_empty());
+        assert_eq!(search_queries[1].search_query, "second");
+        assert_eq!(
+            search_queries[1].search_time,
+            Utc.with_ymd_and_hms(2023, 1, 5, 14, 3, 27).unwrap() + Duration::milliseconds(123)
+        );
+        assert_eq!(
+            search_queries[1].search_interaction_uris,
+            ["spotify:track:abc"]
+        );
+    }
+
+    #[test]
+    fn reads_playlists_from_every_playlist_file() {
+        let (library_data, report) = extract_library(&[
+            (
+                "Playlist1.json",
+                r#"{"playlists": [{"name": "Songs", "lastModifiedDate": "2023-01-02", "items": [
+                    {"track": {"trackName": "Song", "artistName": "Artist", "albumName": "Album",
+                        "trackUri": "spotify:track:abc"}, "episode": null, "localTrack": null,
+                        "addedDate": "2023-01-01"},
+                    {"track": null, "episode": {"episodeName": "Episode", "showName": "Show",
+                        "episodeUri": "spotify:episode:def"}, "localTrack": null, "addedDate": "2023-01-02"}
+                ]}]}"#,
+            ),
+            (
+                "Playlist2.json",
+                r#"{"playlists": [{"name": "Empty", "lastModifiedDate": "2023-01-03"}]}"#,
+            ),
+            ("Playlist.json", "not a playlist file"),
+        ]);
+
+        assert_eq!(report.files.len(), 2);
+        assert_eq!(library_data.playlists.len(), 2);
+
+        let playlist = &library_data.playlists[0];
+        assert_eq!(playlist.name, "Songs");
+        assert_eq!(
+            playlist.last_modified_date,
+            NaiveDate::from_ymd_opt(2023, 1, 2)
+        );
+        assert_eq!(playlist.items[0].track_name.as_deref(), Some("Song"));
+        assert_eq!(playlist.items[0].uri.as_deref(), Some("spotify:track:abc"));
+        assert_eq!(playlist.items[1].show_name.as_deref(), Some("Show"));
+        assert_eq!(
+            playlist.items[1].uri.as_deref(),
+            Some("spotify:episode:def")
+        );
+        assert!(library_data.playlists[1].items.is_empty());
+    }
+
+    #[test]
+    fn skips_search_queries_that_cant_be_read() {
+        let (library_data, report) = extract_library(&[(
+            "SearchQueries.json",
+            r#"[
+                {"searchTime": "2023-01-04T09:00:00Z[UTC]", "searchQuery": "first"},
+                {"searchTime": "yesterday", "searchQuery": "bad time"},
+                {"searchTime": "2023-01-05T09:00:00Z[UTC]"},
+                {"searchTime": "2023-01-06T09:00:00Z[UTC]", "searchQuery": "second"}
+            ]"#,
+        )]);
+
+        assert_eq!(report.files[0].error, None);
+        let skipped_record_indexes: Vec<_> = report.files[0]
+            .skipped_records
+            .iter()
+            .map(|skipped_record| skipped_record.record_index)
+            .collect();
+        assert_eq!(skipped_record_indexes, [1, 2]);
+
+        let search_queries: Vec<_> = library_data
+            .search_queries
+            .iter()
+            .map(|search_query| search_query.search_query.as_str())
+            .collect();
+        assert_eq!(search_queries, ["first", "second"]);
+    }
+
+    #[test]
+    fn fails_on_search_query_that_cant_be_read_in_strict_mode() {
+        let options = LoadOptions {
+            parse_mode: ParseMode::Strict,
+            ..LoadOptions::default()
+        };
+        let mut report = IngestionReport::new(&options);
+        let files = std::iter::once(Ok((
+            "SearchQueries.json".to_owned(),
+            br#"[{"searchTime": "yesterday", "searchQuery": "bad time"}]"#.to_vec(),
+        )));
+
+        assert!(extract_library_from_files(files, &options, &mut report).is_err());
+    }
+
+    #[test]
+    fn gets_unplayed_saved_tracks_by_uri_or_name() {
+        let play_store = PlayStore::from_play_items(&[
+            get_play("2023-01-01T10:00:00Z", 1000, "a"),
+            get_play("2023-01-01T11:00:00Z", 1000, "b"),
+        ]);
+        let library_data = LibraryData {
+            saved_tracks: vec![
+                get_saved_track(Some("spotify:track:a"), "Other Artist", "Renamed"),
+                // Names are matched without regard to case when there's no URI to match
+                get_saved_track(None, "ARTIST", "song b"),
+                get_saved_track(Some("spotify:track:c"), "Artist", "Song c"),
+                get_saved_track(None, "Artist", "Song d"),
+            ],
+            ..LibraryData::default()
+        };
+
+        let unplayed_track_names: Vec<_> = get_unplayed_saved_tracks(&library_data, &play_store)
+            .iter()
+            .map(|saved_track| saved_track.track_name.as_str())
+            .collect();
+        assert_eq!(unplayed_track_names, ["Song c", "Song d"]);
+    }
+
+    #[test]
+    fn gets_playlist_play_stats() {
+        let play_store = PlayStore::from_play_items(&[
+            get_play("2023-01-01T10:00:00Z", 1000, "a"),
+            get_play("2023-01-02T10:00:00Z", 2000, "a"),
+            get_play("2023-01-03T10:00:00Z", 4000, "b"),
+            get_play("2023-01-04T10:00:00Z", 8000, "x"),
+        ]);
+        let get_playlist_item = |uri: &str, track_name: &str| PlaylistItem {
+            track_name: Some(track_name.to_owned()),
+            artist_name: Some("Artist".to_owned()),
+            album_name: None,
+            episode_name: None,
+            show_name: None,
+            uri: Some(uri.to_owned()),
+            added_date: None,
+        };
+        let library_data = LibraryData {
+            playlists: vec![Playlist {
+                name: "Songs".to_owned(),
+                last_modified_date: None,
+                items: vec![
+                    get_playlist_item("spotify:track:a", "Song a"),
+                    get_playlist_item("spotify:track:b", "Song b"),
+                    get_playlist_item("spotify:track:c", "Song c"),
+                ],
+            }],
+            ..LibraryData::default()
+        };
+
+        let playlist_play_stats = get_playlist_play_stats(&library_data, &play_store);
+
+        assert_eq!(playlist_play_stats.len(), 1);
+        assert_eq!(playlist_play_stats[0].name, "Songs");
+        assert_eq!(playlist_play_stats[0].item_count, 3);
+        assert_eq!(playlist_play_stats[0].played_item_count, 2);
+        assert_eq!(playlist_play_stats[0].play_count, 3);
+        assert_eq!(playlist_play_stats[0].ms_played, 7000);
+    }
+
+    #[test]
+    fn links_searches_to_first_clicked_play_within_window() {
+        let search_time = Utc.with_ymd_and_hms(2023, 1, 1, 10, 0, 0).unwrap();
+
+        // Plays are given by when they ended, so each one started `ms_played` before its `ts`
+        let play_store = PlayStore::from_play_items(&[
+            // Started before the search
+            get_play("2023-01-01T10:00:30Z", 60_000, "a"),
+            // Not one of the clicked results
+            get_play("2023-01-01T10:01:00Z", 30_000, "x"),
+            get_play("2023-01-01T10:03:00Z", 60_000, "a"),
+            get_play("2023-01-01T10:04:00Z", 60_000, "b"),
+            // Started after the window
+            get_play("2023-01-01T10:11:00Z", 30_000, "c"),
+        ]);
+        let library_data = LibraryData {
+            search_queries: vec![
+                get_search_query(search_time, &["spotify:track:b", "spotify:track:a"]),
+                get_search_query(search_time, &["spotify:track:c"]),
+                get_search_query(search_time, &[]),
+            ],
+            ..LibraryData::default()
+        };
+
+        let searches_followed_by_plays =
+            get_searches_followed_by_plays(&library_data, &play_store, Duration::minutes(10));
+
+        assert_eq!(searches_followed_by_plays.len(), 1);
+        assert_eq!(searches_followed_by_plays[0].played_uri, "spotify:track:a");
+        assert_eq!(searches_followed_by_plays[0].seconds_until_play, 120);
+    }
+
+    #[test]
+    fn links_search_to_play_at_end_of_window() {
+        let search_time = Utc.with_ymd_and_hms(2023, 1, 1, 10, 0, 0).unwrap();
+        let play_store =
+            PlayStore::from_play_items(&[get_play("2023-01-01T10:11:00Z", 60_000, "a")]);
+        let library_data = LibraryData {
+            search_queries: vec![get_search_query(search_time, &["spotify:track:a"])],
+            ..LibraryData::default()
+        };
+
+        let searches_followed_by_plays =
+            get_searches_followed_by_plays(&library_data, &play_store, Duration::minutes(10));
+
+        assert_eq!(searches_followed_by_plays.len(), 1);
+        assert_eq!(searches_followed_by_plays[0].seconds_until_play, 600);
+    }
+}
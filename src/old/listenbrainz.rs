@@ -0,0 +1,464 @@
+use crate::plays::{
+    self, FileIngestionReport, IngestionReport, LoadOptions, ParseMode, PlayItem, PlayItemKind,
+    PlayItemSource,
+};
+use chrono::{prelude::*, Duration};
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use zip::ZipArchive;
+
+/// The most listens that ListenBrainz accepts in a single submission
+const MAX_LISTENS_PER_IMPORT: usize = 1000;
+
+/// Plays shorter than this aren't exported, since ListenBrainz (like Last.fm) only counts a listen once
+/// enough of the song has been played
+const MIN_MS_PLAYED_FOR_LISTEN: u64 = 30_000;
+
+const SPOTIFY_TRACK_URI_PREFIX: &str = "spotify:track:";
+const SPOTIFY_TRACK_URL_PREFIX: &str = "https://open.spotify.com/track/";
+
+/// One listen, as it's stored in a ListenBrainz export and sent in a ListenBrainz import
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Listen {
+    /// When the listen started, in seconds since the Unix epoch
+    listened_at: i64,
+    track_metadata: TrackMetadata,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct TrackMetadata {
+    artist_name: String,
+    track_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    release_name: Option<String>,
+    #[serde(default)]
+    additional_info: AdditionalInfo,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct AdditionalInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_ms: Option<u64>,
+    /// The length of the listen in seconds, which some clients send instead of `duration_ms`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration: Option<u64>,
+    /// A link to the track on Spotify, like "https://open.spotify.com/track/..."
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spotify_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    music_service: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    submission_client: Option<String>,
+}
+
+/// The body of a ListenBrainz `submit-listens` request for importing past listens
+#[derive(Clone, Debug, Serialize)]
+struct ListenBrainzImport<'a> {
+    listen_type: &'static str,
+    payload: &'a [Listen],
+}
+
+fn get_play_item_from_listen(
+    listen: Listen,
+    source_file: &Arc<str>,
+) -> std::result::Result<PlayItem, String> {
+    let Some(started_at) = Utc.timestamp_opt(listen.listened_at, 0).single() else {
+        return Err(format!(
+            "The timestamp {} is not valid.",
+            listen.listened_at
+        ));
+    };
+
+    let track_metadata = listen.track_metadata;
+    let additional_info = track_metadata.additional_info;
+
+    let ms_played = additional_info
+        .duration_ms
+        .or(additional_info.duration.map(|duration| duration * 1000));
+
+    // Without a length, `ts` is left as when the listen started until the length is estimated. Spotify's
+    // timestamps are to the second, so the end is too.
+    let ts = match ms_played {
+        Some(ms_played) => {
+            let ended_at = started_at + Duration::milliseconds(ms_played as i64);
+            ended_at.with_nanosecond(0).unwrap_or(ended_at)
+        }
+        None => started_at,
+    };
+
+    let spotify_track_uri = additional_info.spotify_id.and_then(|spotify_id| {
+        spotify_id
+            .strip_prefix(SPOTIFY_TRACK_URL_PREFIX)
+            .or(spotify_id.strip_prefix(SPOTIFY_TRACK_URI_PREFIX))
+            .map(|track_id| format!("{}{}", SPOTIFY_TRACK_URI_PREFIX, track_id))
+    });
+
+    Ok(PlayItem {
+        audiobook_chapter_title: None,
+        audiobook_chapter_uri: None,
+        audiobook_title: None,
+        audiobook_uri: None,
+        conn_country: None,
+        episode_name: None,
+        episode_show_name: None,
+        incognito_mode: None,
+        ip_addr_decrypted: None,
+        master_metadata_album_album_name: track_metadata.release_name,
+        master_metadata_album_artist_name: Some(track_metadata.artist_name),
+        master_metadata_track_name: Some(track_metadata.track_name),
+        ms_played,
+        offline: None,
+        offline_timestamp: None,
+        platform: None,
+        reason_end: None,
+        reason_start: None,
+        shuffle: None,
+        skipped: None,
+        spotify_episode_uri: None,
+        spotify_track_uri,
+        ts,
+        user_agent_decrypted: None,
+        username: None,
+        kind: PlayItemKind::Audio,
+        source: PlayItemSource::ListenBrainz,
+        source_file: Some(Arc::clone(source_file)),
+    })
+}
+
+/// Reads the listens in a file of a ListenBrainz export. Newer exports have one listen per line (JSONL),
+/// and older ones have a single JSON array of listens.
+fn get_plays_from_listenbrainz_file(
+    file_bytes: &[u8],
+    options: &LoadOptions,
+    file_report: &mut FileIngestionReport,
+) -> std::result::Result<Vec<PlayItem>, String> {
+    let source_file: Arc<str> = Arc::from(file_report.file_name.as_str());
+
+    let records: Vec<serde_json::Result<Listen>> = match file_bytes.trim_ascii_start().first() {
+        Some(b'[') => {
+            let listens: Vec<serde_json::Value> =
+                serde_json::from_slice(file_bytes).map_err(|e| e.to_string())?;
+            listens.into_iter().map(serde_json::from_value).collect()
+        }
+        _ => file_bytes
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.trim_ascii().is_empty())
+            .map(serde_json::from_slice)
+            .collect(),
+    };
+
+    let mut play_items = vec![];
+    for (record_index, listen) in records.into_iter().enumerate() {
+        let play_item = listen
+            .map_err(|e| e.to_string())
+            .and_then(|listen| get_play_item_from_listen(listen, &source_file));
+
+        match play_item {
+            Ok(play_item) => play_items.push(play_item),
+            Err(error) => plays::handle_failed_record(record_index, error, options, file_report)?,
+        }
+    }
+
+    Ok(play_items)
+}
+
+/// Gets the files with listens out of a ListenBrainz export zip, which keeps them in a `listens` folder
+/// next to files that aren't listens (like the user's feedback)
+fn get_listen_files_from_zip(zip_bytes: &[u8]) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut archive = ZipArchive::new(Cursor::new(zip_bytes))?;
+
+    let mut listen_files = vec![];
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        let entry_name = entry.name().to_owned();
+
+        let is_listen_file = entry_name
+            .split(['/', '\\'])
+            .any(|folder| folder == "listens")
+            && (entry_name.ends_with(".jsonl") || entry_name.ends_with(".json"));
+        if !entry.is_file() || !is_listen_file {
+            continue;
+        }
+
+        let mut entry_bytes = vec![];
+        entry.read_to_end(&mut entry_bytes)?;
+        listen_files.push((entry_name, entry_bytes));
+    }
+
+    if listen_files.is_empty() {
+        return Err(eyre!(
+            "Invalid zip file. Could not find any ListenBrainz listens."
+        ));
+    }
+
+    listen_files.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    Ok(listen_files)
+}
+
+/// Extracts plays from a ListenBrainz export, given as the name and contents of each file. Zip files are
+/// searched for the files in their `listens` folder.
+///
+/// The plays are in the order that they were listened to. Listens without a length have their `ts` as
+/// when they started, until `estimate::estimate_missing_fields` is used on them.
+///
+/// This does not touch the filesystem, so it can also be used where there isn't one (e.g. the wasm build).
+pub fn extract_plays_from_listenbrainz_files(
+    files: Vec<(String, Vec<u8>)>,
+    options: &LoadOptions,
+) -> Result<(Vec<PlayItem>, IngestionReport)> {
+    let mut listen_files = vec![];
+    for (file_name, file_bytes) in files {
+        if file_name.to_lowercase().ends_with(".zip") {
+            listen_files.append(&mut get_listen_files_from_zip(&file_bytes)?);
+        } else {
+            listen_files.push((file_name, file_bytes));
+        }
+    }
+
+    let mut all_play_items = vec![];
+    let mut report = IngestionReport::new(options);
+
+    for (file_name, file_bytes) in listen_files {
+        let mut file_report = FileIngestionReport {
+            file_name,
+            ..FileIngestionReport::default()
+        };
+
+        match get_plays_from_listenbrainz_file(&file_bytes, options, &mut file_report) {
+            Ok(mut play_items) => {
+                file_report.plays_loaded = play_items.len();
+                all_play_items.append(&mut play_items);
+            }
+            Err(error) => match options.parse_mode {
+                ParseMode::Strict => {
+                    return Err(eyre!("Unable to load {}. {}", file_report.file_name, error))
+                }
+                ParseMode::Lenient => file_report.error = Some(error),
+            },
+        }
+
+        report.files.push(file_report);
+    }
+
+    // Exports list the newest listens first
+    all_play_items.sort_by_key(|play_item| play_item.ts);
+
+    Ok((all_play_items, report))
+}
+
+pub fn extract_plays_from_listenbrainz_paths(
+    file_paths: &[PathBuf],
+    options: &LoadOptions,
+) -> Result<(Vec<PlayItem>, IngestionReport)> {
+    let mut files = vec![];
+    for path in file_paths {
+        let file_name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        files.push((file_name, fs::read(path)?));
+    }
+
+    extract_plays_from_listenbrainz_files(files, options)
+}
+
+/// Turns a Spotify play into a listen, if it's a song that was played for long enough to count as one.
+/// Plays in incognito mode aren't exported, since they were meant to stay out of the listening history.
+fn get_listen_from_play_item(play_item: &PlayItem) -> Option<Listen> {
+    if play_item.source != PlayItemSource::Spotify
+        || play_item.kind != PlayItemKind::Audio
+        || play_item.incognito_mode == Some(true)
+    {
+        return None;
+    }
+
+    let ms_played = play_item.ms_played?;
+    if ms_played < MIN_MS_PLAYED_FOR_LISTEN {
+        return None;
+    }
+
+    // Rounded up, so that the end of the play comes out as the same second when the listen is imported
+    let started_at_ms = play_item.ts.timestamp_millis() - ms_played as i64;
+    let listened_at = started_at_ms.div_euclid(1000) + (started_at_ms.rem_euclid(1000) > 0) as i64;
+
+    let spotify_id = play_item
+        .spotify_track_uri
+        .as_ref()
+        .and_then(|uri| uri.strip_prefix(SPOTIFY_TRACK_URI_PREFIX))
+        .map(|track_id| format!("{}{}", SPOTIFY_TRACK_URL_PREFIX, track_id));
+
+    Some(Listen {
+        listened_at,
+        track_metadata: TrackMetadata {
+            artist_name: play_item.master_metadata_album_artist_name.clone()?,
+            track_name: play_item.master_metadata_track_name.clone()?,
+            release_name: play_item.master_metadata_album_album_name.clone(),
+            additional_info: AdditionalInfo {
+                duration_ms: Some(ms_played),
+                duration: None,
+                spotify_id,
+                music_service: Some("spotify.com".to_owned()),
+                submission_client: Some("dio".to_owned()),
+            },
+        },
+    })
+}
+
+/// Turns listens into the JSON that ListenBrainz takes for importing them, which is the body of one
+/// `submit-listens` request of type "import"
+fn get_listenbrainz_import(listens: &[Listen]) -> serde_json::Result<String> {
+    serde_json::to_string(&ListenBrainzImport {
+        listen_type: "import",
+        payload: listens,
+    })
+}
+
+/// Writes Spotify plays to a folder as `listenbrainz_import_N.json` files, which are ready to be sent to
+/// ListenBrainz. Each file is written as soon as it is full, so the plays don't all have to be in memory
+/// at once. Returns the number of files written.
+pub fn export_plays_to_listenbrainz_folder(
+    play_items: impl IntoIterator<Item = PlayItem>,
+    folder_path: &Path,
+) -> Result<usize> {
+    let mut files_written = 0;
+    let mut write_listens = |listens: &[Listen]| -> Result<()> {
+        let file_path = folder_path.join(format!("listenbrainz_import_{}.json", files_written));
+        fs::write(file_path, get_listenbrainz_import(listens)?)?;
+        files_written += 1;
+        Ok(())
+    };
+
+    let mut listens = Vec::with_capacity(MAX_LISTENS_PER_IMPORT);
+    for play_item in play_items {
+        let Some(listen) = get_listen_from_play_item(&play_item) else {
+            continue;
+        };
+        listens.push(listen);
+        if listens.len() == MAX_LISTENS_PER_IMPORT {
+            write_listens(&listens)?;
+            listens.clear();
+        }
+    }
+    if !listens.is_empty() {
+        write_listens(&listens)?;
+    }
+
+    Ok(files_written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extract_plays(file_name: &str, file: &str) -> (Vec<PlayItem>, IngestionReport) {
+        let files = vec![(file_name.to_owned(), file.as_bytes().to_vec())];
+        extract_plays_from_listenbrainz_files(files, &LoadOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn reads_jsonl_listens() {
+        let (play_items, report) = extract_plays(
+            "listens/2023/1.jsonl",
+            r#"{"listened_at": 1672567200, "track_metadata": {"artist_name": "Artist", "track_name": "Second", "additional_info": {"duration": 200}}}
+
+{"listened_at": 1672567000, "track_metadata": {"artist_name": "Artist", "track_name": "First", "release_name": "Album", "additional_info": {"duration_ms": 100500, "spotify_id": "https://open.spotify.com/track/abc"}}}
+{"listened_at": 1672567400, "track_metadata": {"artist_name": "Artist"}}
+"#,
+        );
+
+        // The listen without a track name is skipped
+        assert_eq!(report.files[0].plays_loaded, 2);
+        assert_eq!(report.files[0].skipped_records.len(), 1);
+        assert_eq!(report.files[0].skipped_records[0].record_index, 2);
+
+        assert_eq!(
+            play_items[0].master_metadata_track_name.as_deref(),
+            Some("First")
+        );
+        assert_eq!(
+            play_items[0].master_metadata_album_album_name.as_deref(),
+            Some("Album")
+        );
+        assert_eq!(play_items[0].ms_played, Some(100_500));
+        // The play ends when the listen did, to the second
+        assert_eq!(
+            play_items[0].ts,
+            Utc.with_ymd_and_hms(2023, 1, 1, 9, 58, 20).unwrap()
+        );
+        assert_eq!(
+            play_items[0].spotify_track_uri.as_deref(),
+            Some("spotify:track:abc")
+        );
+        assert_eq!(play_items[0].source, PlayItemSource::ListenBrainz);
+
+        assert_eq!(play_items[1].ms_played, Some(200_000));
+        assert_eq!(play_items[1].spotify_track_uri, None);
+    }
+
+    #[test]
+    fn reads_array_of_listens() {
+        let (play_items, report) = extract_plays(
+            "listens.json",
+            r#"[
+                {"listened_at": 1672567200, "track_metadata": {"artist_name": "Artist", "track_name": "Song"}}
+            ]"#,
+        );
+
+        assert_eq!(report.files[0].plays_loaded, 1);
+        assert_eq!(play_items[0].ms_played, None);
+        // Without a length, the play is left as when the listen started
+        assert_eq!(
+            play_items[0].ts,
+            Utc.with_ymd_and_hms(2023, 1, 1, 10, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn exported_listen_is_imported_as_same_play() {
+        let record = r#"{"ts": "2023-01-01T10:03:20Z", "ms_played": 200500,
+            "master_metadata_track_name": "Song", "master_metadata_album_artist_name": "Artist",
+            "master_metadata_album_album_name": "Album", "spotify_track_uri": "spotify:track:abc"}"#;
+        let play_item =
+            plays::get_play_item_from_record_bytes(record.as_bytes(), PlayItemKind::Audio).unwrap();
+
+        let listen = get_listen_from_play_item(&play_item).unwrap();
+        // The listen started half a second into 09:59:59, which is rounded up
+        assert_eq!(
+            listen.listened_at,
+            Utc.with_ymd_and_hms(2023, 1, 1, 10, 0, 0)
+                .unwrap()
+                .timestamp()
+        );
+
+        let (imported_play_items, _) =
+            extract_plays("listens.jsonl", &serde_json::to_string(&listen).unwrap());
+        assert_eq!(imported_play_items[0].ts, play_item.ts);
+        assert_eq!(imported_play_items[0].ms_played, play_item.ms_played);
+        assert_eq!(
+            imported_play_items[0].spotify_track_uri,
+            play_item.spotify_track_uri
+        );
+        assert_eq!(
+            imported_play_items[0].master_metadata_album_album_name,
+            play_item.master_metadata_album_album_name
+        );
+    }
+
+    #[test]
+    fn short_plays_are_not_exported() {
+        let record = r#"{"ts": "2023-01-01T10:00:20Z", "ms_played": 20000,
+            "master_metadata_track_name": "Song", "master_metadata_album_artist_name": "Artist"}"#;
+        let play_item =
+            plays::get_play_item_from_record_bytes(record.as_bytes(), PlayItemKind::Audio).unwrap();
+
+        assert!(get_listen_from_play_item(&play_item).is_none());
+    }
+}
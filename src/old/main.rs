@@ -3,27 +3,53 @@
     windows_subsystem = "windows"
 )]
 
+mod apple_music;
+mod arrow_export;
+mod cache;
 mod commands;
+mod country;
 mod dates;
+mod db;
+mod dedup;
+mod device;
+mod estimate;
 mod filter;
 mod group;
+mod group_tree;
+mod lastfm;
+mod library;
+mod listenbrainz;
+mod pivot;
+mod play_reader;
+mod play_store;
 mod plays;
+mod privacy;
 mod sort;
 mod util;
 
 use filter::Filter;
-use group::{Group, GroupBy};
+use group::{Group, GroupBy, GroupOptions};
 use sort::SortSpotifyDataBy;
 use std::{path::PathBuf, sync::Mutex};
 
 pub struct Dio(Mutex<DioState>);
 
 pub struct DioState {
-    spotify_data_folder_path: Option<PathBuf>,
-    spotify_plays_data: Vec<plays::PlayItem>,
+    spotify_data_path: Option<PathBuf>,
+    database_path: Option<PathBuf>,
+    spotify_plays_data: play_store::PlayStore,
+    load_options: plays::LoadOptions,
+    ingestion_report: plays::IngestionReport,
+    library_data: library::LibraryData,
+    dedup_report: dedup::DedupReport,
     filter: filter::Filter,
     processed_data: Vec<Group>,
+    /// What `processed_data` was grouped by, so that plays that are added later are only added to it if
+    /// they'd be grouped the same way
+    processed_data_grouping: Option<(GroupBy, GroupOptions)>,
+    group_tree: Option<group_tree::GroupTree>,
     group_by: group::GroupBy,
+    group_options: group::GroupOptions,
     sort_by: sort::SortSpotifyDataBy,
     sort_order_descending: bool,
 }
@@ -31,11 +57,19 @@ pub struct DioState {
 impl Default for DioState {
     fn default() -> Self {
         DioState {
-            spotify_data_folder_path: None,
-            spotify_plays_data: Vec::new(),
+            spotify_data_path: None,
+            database_path: None,
+            spotify_plays_data: play_store::PlayStore::default(),
+            load_options: plays::LoadOptions::default(),
+            ingestion_report: plays::IngestionReport::default(),
+            library_data: library::LibraryData::default(),
+            dedup_report: dedup::DedupReport::default(),
             filter: Filter::default(),
             processed_data: Vec::new(),
+            processed_data_grouping: None,
+            group_tree: None,
             group_by: GroupBy::Song,
+            group_options: GroupOptions::default(),
             sort_by: SortSpotifyDataBy::TotalListenTime,
             sort_order_descending: true,
         }
@@ -47,9 +81,38 @@ fn main() {
         .manage(Dio(Mutex::new(DioState::default())))
         .invoke_handler(tauri::generate_handler![
             commands::load_spotify_data,
+            commands::load_spotify_data_from_zip,
+            commands::add_spotify_data,
+            commands::import_lastfm_data,
+            commands::import_listenbrainz_data,
+            commands::import_apple_music_data,
+            commands::export_listenbrainz_data,
+            commands::export_columnar_data,
+            commands::open_database,
+            commands::import_spotify_data_into_database,
+            commands::run_sql_query,
+            commands::get_ingestion_report,
+            commands::get_dedup_report,
+            commands::set_parse_mode,
+            commands::set_privacy_policy,
+            commands::get_library_data,
+            commands::get_unplayed_saved_tracks,
+            commands::get_playlist_play_stats,
+            commands::get_searches_followed_by_plays,
             commands::get_processed_data,
+            commands::get_unavailable_metrics,
+            commands::get_travel_timeline,
             commands::set_group_by,
+            commands::set_merge_same_name_releases,
+            commands::set_utc_offset,
+            commands::apply_filters_and_build_group_tree,
+            commands::get_group_tree,
+            commands::expand_group_node,
+            commands::sort_group_node_children,
+            commands::get_pivot_table,
+            commands::export_pivot_table_to_csv,
             commands::apply_filters_and_group,
+            commands::apply_filters_and_group_in_database,
             commands::reset_filter,
             commands::set_sort,
             commands::apply_sort
@@ -0,0 +1,311 @@
+use crate::{
+    group::{self, AggregatedData, Group, GroupBy, GroupOptions},
+    play_store::PlayStore,
+    sort::SortSpotifyDataBy,
+};
+use eyre::Result;
+use serde::Serialize;
+use std::{cmp::Ordering, io::Write};
+
+/// What a pivot table breaks plays out by, and which metric it shows
+pub struct PivotSpec {
+    pub rows: GroupBy,
+    pub columns: GroupBy,
+    pub metric: SortSpotifyDataBy,
+    /// Only the rows with the highest totals are kept, unless the rows are time buckets, in which case the
+    /// earliest ones are
+    pub max_rows: Option<usize>,
+    /// The same as `max_rows`, for columns
+    pub max_columns: Option<usize>,
+}
+
+/// A metric of plays broken out by two groupings at once, e.g. the listening time of each artist in each
+/// year. Every row has a value for every column. Values are None where the metric can't be computed (e.g.
+/// a skip percentage for a cell without any plays).
+///
+/// The totals are computed from all of the plays in a row or column, including the ones in rows and
+/// columns that were left out by `max_rows` and `max_columns`, so they can be used to see what share of
+/// the whole each cell is. Percentages are computed over the total's plays, not added up.
+#[derive(Clone, Debug, Serialize)]
+pub struct PivotTable {
+    pub row_names: Vec<String>,
+    pub column_names: Vec<String>,
+    /// `values[row][column]`
+    pub values: Vec<Vec<Option<f64>>>,
+    pub row_totals: Vec<Option<f64>>,
+    pub column_totals: Vec<Option<f64>>,
+    pub total: Option<f64>,
+}
+
+/// A row or column of a pivot table while it's being made
+struct PivotLine {
+    name: String,
+    total: AggregatedData,
+    /// The line's time bucket, when grouping by time, so that the lines can be put in order of time
+    time_bucket: Option<i64>,
+}
+
+impl PivotLine {
+    fn new(
+        group: Group,
+        first_play_index: usize,
+        group_by: &GroupBy,
+        options: &GroupOptions,
+        play_store: &PlayStore,
+    ) -> Self {
+        PivotLine {
+            name: group.get_metadata().as_string(),
+            time_bucket: group::get_time_bucket(
+                &play_store.get_ts(first_play_index),
+                group_by,
+                options,
+            ),
+            total: group.get_aggregated_data().clone(),
+        }
+    }
+}
+
+/// Gets the order that lines are shown in, and leaves out the ones past `max_lines`. Time buckets are put
+/// in order of time, and anything else from the highest total down (with totals that can't be computed
+/// last).
+fn get_line_order(
+    lines: &[PivotLine],
+    metric: &SortSpotifyDataBy,
+    max_lines: Option<usize>,
+) -> Vec<usize> {
+    // Lines are empty when none of their plays could be put into a line of the other grouping
+    let mut line_order: Vec<usize> = (0..lines.len())
+        .filter(|&line_index| lines[line_index].total.play_count > 0)
+        .collect();
+    line_order.sort_by(
+        |&a, &b| match (lines[a].time_bucket, lines[b].time_bucket) {
+            (Some(a_time_bucket), Some(b_time_bucket)) => a_time_bucket.cmp(&b_time_bucket),
+            _ => {
+                let a_total = lines[a].total.get_metric(metric);
+                let b_total = lines[b].total.get_metric(metric);
+                b_total.partial_cmp(&a_total).unwrap_or(Ordering::Equal)
+            }
+        },
+    );
+    line_order.truncate(max_lines.unwrap_or(usize::MAX));
+
+    line_order
+}
+
+/// Makes a pivot table from the plays at `play_indices` in a PlayStore. Plays that can't be put into both a
+/// row and a column (e.g. podcast plays when the rows are artists) are left out.
+pub fn get_pivot_table(
+    spec: &PivotSpec,
+    options: &GroupOptions,
+    play_store: &PlayStore,
+    play_indices: &[usize],
+) -> PivotTable {
+    let cross_grouped_data = group::get_cross_grouped_data_from_store(
+        &spec.rows,
+        &spec.columns,
+        options,
+        play_store,
+        play_indices,
+    );
+
+    let get_lines = |groups: Vec<(Group, usize)>, group_by: &GroupBy| -> Vec<PivotLine> {
+        groups
+            .into_iter()
+            .map(|(group, first_play_index)| {
+                PivotLine::new(group, first_play_index, group_by, options, play_store)
+            })
+            .collect()
+    };
+    let rows = get_lines(cross_grouped_data.row_groups, &spec.rows);
+    let columns = get_lines(cross_grouped_data.column_groups, &spec.columns);
+    let cells = cross_grouped_data.cells;
+
+    let total = columns
+        .iter()
+        .fold(AggregatedData::default(), |mut total, column| {
+            total.merge(column.total.clone());
+            total
+        });
+
+    let row_order = get_line_order(&rows, &spec.metric, spec.max_rows);
+    let column_order = get_line_order(&columns, &spec.metric, spec.max_columns);
+
+    let get_names = |lines: &[PivotLine], line_order: &[usize]| {
+        line_order
+            .iter()
+            .map(|&line_index| lines[line_index].name.clone())
+            .collect()
+    };
+    let get_totals = |lines: &[PivotLine], line_order: &[usize]| {
+        line_order
+            .iter()
+            .map(|&line_index| lines[line_index].total.get_metric(&spec.metric))
+            .collect()
+    };
+
+    let empty_cell = AggregatedData::default();
+
+    PivotTable {
+        row_names: get_names(&rows, &row_order),
+        column_names: get_names(&columns, &column_order),
+        values: row_order
+            .iter()
+            .map(|&row_index| {
+                column_order
+                    .iter()
+                    .map(|&column_index| {
+                        cells
+                            .get(&(row_index, column_index))
+                            .unwrap_or(&empty_cell)
+                            .get_metric(&spec.metric)
+                    })
+                    .collect()
+            })
+            .collect(),
+        row_totals: get_totals(&rows, &row_order),
+        column_totals: get_totals(&columns, &column_order),
+        total: total.get_metric(&spec.metric),
+    }
+}
+
+impl PivotTable {
+    /// Writes the table as CSV, with the row names in the first column, the column names in the first row,
+    /// and the totals in the last column and row. Values that can't be computed are left empty.
+    pub fn write_csv(&self, writer: impl Write) -> Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+        let get_value_string = |value: &Option<f64>| match value {
+            Some(value) => value.to_string(),
+            None => String::new(),
+        };
+
+        let mut header = vec![String::new()];
+        header.extend(self.column_names.iter().cloned());
+        header.push("Total".to_owned());
+        writer.write_record(&header)?;
+
+        for ((row_name, row_values), row_total) in self
+            .row_names
+            .iter()
+            .zip(&self.values)
+            .zip(&self.row_totals)
+        {
+            let mut record = vec![row_name.clone()];
+            record.extend(row_values.iter().map(get_value_string));
+            record.push(get_value_string(row_total));
+            writer.write_record(&record)?;
+        }
+
+        let mut totals = vec!["Total".to_owned()];
+        totals.extend(self.column_totals.iter().map(get_value_string));
+        totals.push(get_value_string(&self.total));
+        writer.write_record(&totals)?;
+
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plays::{self, PlayItem, PlayItemKind};
+
+    /// Artist "A" is played the most and "C" the least. The years are played out of order, so that the
+    /// columns aren't already in order of time.
+    fn get_play_store() -> PlayStore {
+        let plays = [
+            ("B", "2023-03-01T10:00:00Z", false),
+            ("A", "2022-03-01T10:00:00Z", true),
+            ("A", "2022-03-02T10:00:00Z", false),
+            ("A", "2022-03-03T10:00:00Z", false),
+            ("A", "2021-03-01T10:00:00Z", false),
+            ("B", "2023-03-02T10:00:00Z", false),
+            ("B", "2021-03-02T10:00:00Z", false),
+            ("C", "2022-03-04T10:00:00Z", false),
+        ];
+
+        let play_items: Vec<PlayItem> = plays
+            .iter()
+            .map(|(artist, ts, skipped)| {
+                let record = format!(
+                    r#"{{"ts": "{}", "ms_played": 1000, "master_metadata_track_name": "Song",
+                    "master_metadata_album_artist_name": "{}", "skipped": {}}}"#,
+                    ts, artist, skipped
+                );
+
+                plays::get_play_item_from_record_bytes(record.as_bytes(), PlayItemKind::Audio)
+                    .unwrap()
+            })
+            .collect();
+
+        PlayStore::from_play_items(&play_items)
+    }
+
+    fn get_artist_by_year_pivot_table(
+        metric: SortSpotifyDataBy,
+        max_rows: Option<usize>,
+        max_columns: Option<usize>,
+    ) -> PivotTable {
+        let play_store = get_play_store();
+        let play_indices: Vec<usize> = (0..play_store.len()).collect();
+        let spec = PivotSpec {
+            rows: GroupBy::Artist,
+            columns: GroupBy::Year,
+            metric,
+            max_rows,
+            max_columns,
+        };
+
+        get_pivot_table(&spec, &GroupOptions::default(), &play_store, &play_indices)
+    }
+
+    #[test]
+    fn truncated_pivot_table_keeps_totals_of_every_play() {
+        let pivot_table =
+            get_artist_by_year_pivot_table(SortSpotifyDataBy::PlayCount, Some(2), Some(2));
+
+        assert_eq!(pivot_table.row_names, ["\"A\"", "\"B\""]);
+        assert_eq!(pivot_table.column_names, ["2021", "2022"]);
+        assert_eq!(
+            pivot_table.values,
+            [[Some(1.), Some(3.)], [Some(1.), Some(0.)]]
+        );
+        // B's total has its plays in 2023, and 2022's total has C's play
+        assert_eq!(pivot_table.row_totals, [Some(4.), Some(3.)]);
+        assert_eq!(pivot_table.column_totals, [Some(2.), Some(4.)]);
+        assert_eq!(pivot_table.total, Some(8.));
+    }
+
+    #[test]
+    fn percentage_of_empty_cell_is_none() {
+        let pivot_table = get_artist_by_year_pivot_table(SortSpotifyDataBy::SkipPct, None, None);
+
+        assert_eq!(pivot_table.column_names, ["2021", "2022", "2023"]);
+        let b_row = pivot_table
+            .row_names
+            .iter()
+            .position(|row_name| row_name == "\"B\"")
+            .unwrap();
+        assert_eq!(pivot_table.values[b_row], [Some(0.), None, Some(0.)]);
+
+        assert_eq!(pivot_table.row_names[0], "\"A\"");
+        assert_eq!(pivot_table.values[0], [Some(0.), Some(100. / 3.), None]);
+        assert_eq!(pivot_table.row_totals[0], Some(25.));
+        assert_eq!(pivot_table.total, Some(12.5));
+    }
+
+    #[test]
+    fn writes_totals_as_last_row_and_column_of_csv() {
+        let pivot_table =
+            get_artist_by_year_pivot_table(SortSpotifyDataBy::PlayCount, Some(2), Some(2));
+
+        let mut csv = vec![];
+        pivot_table.write_csv(&mut csv).unwrap();
+
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            ",2021,2022,Total\n\"\"\"A\"\"\",1,3,4\n\"\"\"B\"\"\",1,0,3\nTotal,2,4,8\n"
+        );
+    }
+}
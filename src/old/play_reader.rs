@@ -0,0 +1,326 @@
+use crate::plays::{self, PlayItem, PlayItemKind};
+use std::{error::Error, fmt::Display, io, io::BufRead};
+
+/// The ways that reading a play from a streaming history file can fail
+#[derive(Debug)]
+pub enum PlayReadError {
+    /// The file couldn't be read. Trying again might work.
+    Io(io::Error),
+    /// The file isn't laid out as an array of records, so nothing after this point can be read
+    Syntax(String),
+    /// A single record couldn't be turned into a PlayItem. Records after it can still be read.
+    Record {
+        record_index: u64,
+        error: serde_json::Error,
+    },
+}
+
+impl Display for PlayReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Unable to read the file: {}", e),
+            Self::Syntax(message) => write!(f, "{}", message),
+            Self::Record {
+                record_index,
+                error,
+            } => write!(f, "Record {} could not be read: {}", record_index, error),
+        }
+    }
+}
+
+impl Error for PlayReadError {}
+
+impl From<io::Error> for PlayReadError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+type Result<T> = std::result::Result<T, PlayReadError>;
+
+/// Where a PlayItemReader is in the JSON array that it is reading
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ReaderPosition {
+    BeforeArray,
+    BeforeFirstRecord,
+    AfterRecord,
+    Done,
+}
+
+/// Reads the plays in a streaming history file one record at a time, so the whole file never has to be
+/// held in memory as a string. Each record is found by scanning for the end of its JSON object, and only
+/// that record's bytes are handed to serde_json.
+pub struct PlayItemReader<R: BufRead> {
+    reader: R,
+    kind: PlayItemKind,
+    position: ReaderPosition,
+    record_bytes: Vec<u8>,
+    bytes_read: u64,
+    records_parsed: u64,
+    records_seen: u64,
+}
+
+impl<R: BufRead> PlayItemReader<R> {
+    pub fn new(reader: R, kind: PlayItemKind) -> Self {
+        PlayItemReader {
+            reader,
+            kind,
+            position: ReaderPosition::BeforeArray,
+            record_bytes: Vec::new(),
+            bytes_read: 0,
+            records_parsed: 0,
+            records_seen: 0,
+        }
+    }
+
+    /// The number of bytes of the file that have been read so far
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// The number of records that have been parsed into PlayItem instances so far
+    pub fn records_parsed(&self) -> u64 {
+        self.records_parsed
+    }
+
+    fn peek_byte(&mut self) -> Result<Option<u8>> {
+        Ok(self.reader.fill_buf()?.first().copied())
+    }
+
+    fn consume_byte(&mut self) {
+        self.reader.consume(1);
+        self.bytes_read += 1;
+    }
+
+    fn skip_whitespace(&mut self) -> Result<()> {
+        loop {
+            let buf = self.reader.fill_buf()?;
+            if buf.is_empty() {
+                return Ok(());
+            }
+
+            let whitespace_len = buf
+                .iter()
+                .take_while(|byte| byte.is_ascii_whitespace())
+                .count();
+            let reached_non_whitespace = whitespace_len < buf.len();
+
+            self.reader.consume(whitespace_len);
+            self.bytes_read += whitespace_len as u64;
+
+            if reached_non_whitespace {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Copies the bytes of the JSON object that the reader is at into `record_bytes`
+    fn read_record_bytes(&mut self) -> Result<()> {
+        self.record_bytes.clear();
+
+        let mut depth = 0_usize;
+        let mut in_string = false;
+        let mut escaped = false;
+
+        loop {
+            let buf = self.reader.fill_buf()?;
+            if buf.is_empty() {
+                return Err(PlayReadError::Syntax(
+                    "Unexpected end of file in the middle of a record.".to_owned(),
+                ));
+            }
+
+            let mut record_end = None;
+            for (i, byte) in buf.iter().enumerate() {
+                if in_string {
+                    if escaped {
+                        escaped = false;
+                    } else if *byte == b'\\' {
+                        escaped = true;
+                    } else if *byte == b'"' {
+                        in_string = false;
+                    }
+                    continue;
+                }
+
+                match byte {
+                    b'"' => in_string = true,
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            record_end = Some(i + 1);
+                            break;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            let used_len = record_end.unwrap_or(buf.len());
+            self.record_bytes.extend_from_slice(&buf[..used_len]);
+            self.reader.consume(used_len);
+            self.bytes_read += used_len as u64;
+
+            if record_end.is_some() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Moves the reader to the start of the next record, returning false if the end of the array was
+    /// reached instead
+    fn advance_to_next_record(&mut self) -> Result<bool> {
+        self.skip_whitespace()?;
+
+        match (self.position, self.peek_byte()?) {
+            (ReaderPosition::BeforeArray, Some(b'[')) => {
+                self.consume_byte();
+                self.position = ReaderPosition::BeforeFirstRecord;
+                self.advance_to_next_record()
+            }
+            (ReaderPosition::BeforeArray, _) => Err(PlayReadError::Syntax(
+                "Expected the file to contain an array of plays.".to_owned(),
+            )),
+            (ReaderPosition::BeforeFirstRecord | ReaderPosition::AfterRecord, Some(b']')) => {
+                self.consume_byte();
+                Ok(false)
+            }
+            (ReaderPosition::BeforeFirstRecord, Some(b'{')) => Ok(true),
+            (ReaderPosition::AfterRecord, Some(b',')) => {
+                self.consume_byte();
+                self.skip_whitespace()?;
+                match self.peek_byte()? {
+                    Some(b'{') => Ok(true),
+                    _ => Err(PlayReadError::Syntax(
+                        "Expected a record after a comma.".to_owned(),
+                    )),
+                }
+            }
+            (ReaderPosition::Done, _) => Ok(false),
+            (_, None) => Err(PlayReadError::Syntax(
+                "Unexpected end of file before the end of the array.".to_owned(),
+            )),
+            (_, Some(byte)) => Err(PlayReadError::Syntax(format!(
+                "Unexpected character '{}' between records.",
+                byte as char
+            ))),
+        }
+    }
+
+    fn read_next_play_item(&mut self) -> Result<Option<PlayItem>> {
+        if !self.advance_to_next_record()? {
+            self.position = ReaderPosition::Done;
+            return Ok(None);
+        }
+
+        self.read_record_bytes()?;
+        self.position = ReaderPosition::AfterRecord;
+
+        let record_index = self.records_seen;
+        self.records_seen += 1;
+
+        let play_item = plays::get_play_item_from_record_bytes(&self.record_bytes, self.kind)
+            .map_err(|error| PlayReadError::Record {
+                record_index,
+                error,
+            })?;
+        self.records_parsed += 1;
+
+        Ok(Some(play_item))
+    }
+}
+
+impl<R: BufRead> Iterator for PlayItemReader<R> {
+    type Item = Result<PlayItem>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position == ReaderPosition::Done {
+            return None;
+        }
+
+        let next_play_item = self.read_next_play_item();
+
+        // The reader can't find its place in the file again after an error, unless the error was only
+        // with the contents of a single record
+        if let Err(PlayReadError::Io(_) | PlayReadError::Syntax(_)) = next_play_item {
+            self.position = ReaderPosition::Done;
+        }
+
+        next_play_item.transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+
+    /// Reads a file a few bytes at a time, so that records are split up between reads
+    fn read_play_items(file: &str) -> Vec<Result<PlayItem>> {
+        let reader = BufReader::with_capacity(3, file.as_bytes());
+        PlayItemReader::new(reader, PlayItemKind::Audio).collect()
+    }
+
+    #[test]
+    fn reads_strings_with_escaped_quotes_and_brackets() {
+        let file = r#"[
+            {"ts": "2023-01-01T10:00:00Z", "ms_played": 1000, "master_metadata_track_name": "A \"}]{[\" B \\"},
+            {"ts": "2023-01-01T11:00:00Z", "ms_played": 2000, "master_metadata_track_name": "[{C}]"}
+        ]"#;
+
+        let play_items: Vec<PlayItem> = read_play_items(file)
+            .into_iter()
+            .map(|play_item| play_item.unwrap())
+            .collect();
+
+        assert_eq!(play_items.len(), 2);
+        assert_eq!(
+            play_items[0].master_metadata_track_name.as_deref(),
+            Some(r#"A "}]{[" B \"#)
+        );
+        assert_eq!(
+            play_items[1].master_metadata_track_name.as_deref(),
+            Some("[{C}]")
+        );
+    }
+
+    #[test]
+    fn stops_at_truncated_record() {
+        let file = r#"[{"ts": "2023-01-01T10:00:00Z", "ms_played": 1000}, {"ts": "2023-01-"#;
+
+        let play_items = read_play_items(file);
+
+        assert_eq!(play_items.len(), 2);
+        assert!(play_items[0].is_ok());
+        assert!(matches!(play_items[1], Err(PlayReadError::Syntax(_))));
+    }
+
+    #[test]
+    fn stops_at_truncated_array() {
+        let file = r#"[{"ts": "2023-01-01T10:00:00Z", "ms_played": 1000},"#;
+
+        let play_items = read_play_items(file);
+
+        assert_eq!(play_items.len(), 2);
+        assert!(play_items[0].is_ok());
+        assert!(matches!(play_items[1], Err(PlayReadError::Syntax(_))));
+    }
+
+    #[test]
+    fn keeps_reading_after_bad_record() {
+        let file = r#"[{"ts": "not a time"}, {"ts": "2023-01-01T10:00:00Z", "ms_played": 1000}]"#;
+
+        let play_items = read_play_items(file);
+
+        assert_eq!(play_items.len(), 2);
+        assert!(matches!(
+            play_items[0],
+            Err(PlayReadError::Record {
+                record_index: 0,
+                ..
+            })
+        ));
+        assert!(play_items[1].is_ok());
+    }
+}
@@ -0,0 +1,452 @@
+use crate::{
+    group::{self, GroupBy, GroupOptions, PlayStats},
+    plays::{Platform, PlayItem, PlayItemKind, PlayItemSource, ReasonEnd, ReasonStart},
+};
+use chrono::prelude::*;
+use std::{
+    borrow::Borrow,
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hash},
+    num::NonZeroU32,
+    sync::Arc,
+};
+
+/// The ID of a value kept by an `Interner`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Symbol(NonZeroU32);
+
+impl Symbol {
+    fn get_index(&self) -> usize {
+        self.0.get() as usize - 1
+    }
+}
+
+/// Keeps a single copy of each distinct value, so that a column can hold a small ID for each play instead
+/// of its own copy of the value. Values are only kept in `values`, and are found again by their hash, so
+/// that the map from values to their IDs doesn't need copies of them.
+#[derive(Clone, Debug)]
+pub struct Interner<T> {
+    values: Vec<T>,
+    /// The first value with each hash
+    first_symbols: HashMap<u64, Symbol>,
+    /// The next value with the same hash as each value, if there is one
+    next_symbols: Vec<Option<Symbol>>,
+    hash_builder: RandomState,
+}
+
+impl<T> Default for Interner<T> {
+    fn default() -> Self {
+        Interner {
+            values: Vec::new(),
+            first_symbols: HashMap::new(),
+            next_symbols: Vec::new(),
+            hash_builder: RandomState::new(),
+        }
+    }
+}
+
+impl<T> Interner<T> {
+    /// Gets the ID of a value, keeping a copy of it if it hasn't been seen before
+    pub fn get_or_intern<Q>(&mut self, value: &Q) -> Symbol
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = T> + ?Sized,
+    {
+        let hash = self.hash_builder.hash_one(value);
+
+        let mut last_symbol = None;
+        let mut next_symbol = self.first_symbols.get(&hash).copied();
+        while let Some(symbol) = next_symbol {
+            if self.resolve(symbol).borrow() == value {
+                return symbol;
+            }
+            last_symbol = Some(symbol);
+            next_symbol = self.next_symbols[symbol.get_index()];
+        }
+
+        self.values.push(value.to_owned());
+        self.next_symbols.push(None);
+        let symbol = Symbol(NonZeroU32::new(self.values.len() as u32).unwrap());
+
+        match last_symbol {
+            Some(last_symbol) => self.next_symbols[last_symbol.get_index()] = Some(symbol),
+            None => {
+                self.first_symbols.insert(hash, symbol);
+            }
+        }
+
+        symbol
+    }
+
+    pub fn resolve(&self, symbol: Symbol) -> &T {
+        &self.values[symbol.get_index()]
+    }
+}
+
+/// The `Option<bool>` fields of a play, packed into a byte. Each field takes two bits: whether it is
+/// known, and then its value.
+#[derive(Clone, Copy, Debug, Default)]
+struct PlayFlags(u8);
+
+impl PlayFlags {
+    const INCOGNITO_MODE: u8 = 0;
+    const OFFLINE: u8 = 2;
+    const SHUFFLE: u8 = 4;
+    const SKIPPED: u8 = 6;
+
+    fn new(play_item: &PlayItem) -> Self {
+        let mut flags = PlayFlags::default();
+        flags.set(Self::INCOGNITO_MODE, play_item.incognito_mode);
+        flags.set(Self::OFFLINE, play_item.offline);
+        flags.set(Self::SHUFFLE, play_item.shuffle);
+        flags.set(Self::SKIPPED, play_item.skipped);
+
+        flags
+    }
+
+    fn set(&mut self, offset: u8, value: Option<bool>) {
+        if let Some(value) = value {
+            self.0 |= (0b01 | (value as u8) << 1) << offset;
+        }
+    }
+
+    fn get(&self, offset: u8) -> Option<bool> {
+        match (self.0 >> offset) & 0b11 {
+            0b01 => Some(false),
+            0b11 => Some(true),
+            _ => None,
+        }
+    }
+}
+
+/// What the plays of a group have in common, as the IDs of their values (and the time bucket they're in,
+/// when grouping by time). Plays with the same key always end up in the same group, so grouping only has
+/// to look at the values themselves once for each key.
+pub type GroupSymbolKey = (PlayItemKind, [Option<Symbol>; 4], Option<i64>);
+
+/// Holds plays column by column instead of as a `Vec<PlayItem>`. Text (and the reasons plays started and
+/// ended) is interned, so each distinct name is kept once no matter how many plays have it, and the
+/// `Option<bool>` fields are packed into a byte. Plays are referred to by their index, so filtering and
+/// grouping can work on indices without copying any plays.
+#[derive(Clone, Debug, Default)]
+pub struct PlayStore {
+    strings: Interner<String>,
+    platforms: Interner<Platform>,
+    reasons_end: Interner<ReasonEnd>,
+    reasons_start: Interner<ReasonStart>,
+    source_files: Interner<Arc<str>>,
+
+    audiobook_chapter_title: Vec<Option<Symbol>>,
+    audiobook_chapter_uri: Vec<Option<Symbol>>,
+    audiobook_title: Vec<Option<Symbol>>,
+    audiobook_uri: Vec<Option<Symbol>>,
+    conn_country: Vec<Option<Symbol>>,
+    episode_name: Vec<Option<Symbol>>,
+    episode_show_name: Vec<Option<Symbol>>,
+    ip_addr_decrypted: Vec<Option<Symbol>>,
+    master_metadata_album_album_name: Vec<Option<Symbol>>,
+    master_metadata_album_artist_name: Vec<Option<Symbol>>,
+    master_metadata_track_name: Vec<Option<Symbol>>,
+    ms_played: Vec<Option<u64>>,
+    offline_timestamp: Vec<Option<u64>>,
+    platform: Vec<Option<Symbol>>,
+    reason_end: Vec<Option<Symbol>>,
+    reason_start: Vec<Option<Symbol>>,
+    spotify_episode_uri: Vec<Option<Symbol>>,
+    spotify_track_uri: Vec<Option<Symbol>>,
+    /// When each play ended, in milliseconds since the Unix epoch
+    ts: Vec<i64>,
+    user_agent_decrypted: Vec<Option<Symbol>>,
+    username: Vec<Option<Symbol>>,
+    kind: Vec<PlayItemKind>,
+    source: Vec<PlayItemSource>,
+    source_file: Vec<Option<Symbol>>,
+    flags: Vec<PlayFlags>,
+}
+
+impl PlayStore {
+    pub fn from_play_items(play_items: &[PlayItem]) -> Self {
+        let mut play_store = PlayStore::default();
+        play_store.extend(play_items);
+
+        play_store
+    }
+
+    pub fn len(&self) -> usize {
+        self.ts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ts.is_empty()
+    }
+
+    /// Adds plays to the end of the store
+    pub fn extend(&mut self, play_items: &[PlayItem]) {
+        for play_item in play_items {
+            self.push(play_item);
+        }
+    }
+
+    pub fn push(&mut self, play_item: &PlayItem) {
+        let strings = &mut self.strings;
+        let mut intern =
+            |value: &Option<String>| value.as_deref().map(|value| strings.get_or_intern(value));
+
+        self.audiobook_chapter_title
+            .push(intern(&play_item.audiobook_chapter_title));
+        self.audiobook_chapter_uri
+            .push(intern(&play_item.audiobook_chapter_uri));
+        self.audiobook_title
+            .push(intern(&play_item.audiobook_title));
+        self.audiobook_uri.push(intern(&play_item.audiobook_uri));
+        self.conn_country.push(intern(&play_item.conn_country));
+        self.episode_name.push(intern(&play_item.episode_name));
+        self.episode_show_name
+            .push(intern(&play_item.episode_show_name));
+        self.ip_addr_decrypted
+            .push(intern(&play_item.ip_addr_decrypted));
+        self.master_metadata_album_album_name
+            .push(intern(&play_item.master_metadata_album_album_name));
+        self.master_metadata_album_artist_name
+            .push(intern(&play_item.master_metadata_album_artist_name));
+        self.master_metadata_track_name
+            .push(intern(&play_item.master_metadata_track_name));
+        self.spotify_episode_uri
+            .push(intern(&play_item.spotify_episode_uri));
+        self.spotify_track_uri
+            .push(intern(&play_item.spotify_track_uri));
+        self.user_agent_decrypted
+            .push(intern(&play_item.user_agent_decrypted));
+        self.username.push(intern(&play_item.username));
+
+        self.ms_played.push(play_item.ms_played);
+        self.offline_timestamp.push(play_item.offline_timestamp);
+        self.platform.push(
+            play_item
+                .platform
+                .as_ref()
+                .map(|platform| self.platforms.get_or_intern(platform)),
+        );
+        self.reason_end.push(
+            play_item
+                .reason_end
+                .as_ref()
+                .map(|reason_end| self.reasons_end.get_or_intern(reason_end)),
+        );
+        self.reason_start.push(
+            play_item
+                .reason_start
+                .as_ref()
+                .map(|reason_start| self.reasons_start.get_or_intern(reason_start)),
+        );
+        self.ts.push(play_item.ts.timestamp_millis());
+        self.kind.push(play_item.kind);
+        self.source.push(play_item.source);
+        self.source_file.push(
+            play_item
+                .source_file
+                .as_ref()
+                .map(|source_file| self.source_files.get_or_intern(source_file)),
+        );
+        self.flags.push(PlayFlags::new(play_item));
+    }
+
+    fn resolve_string(&self, symbol: Option<Symbol>) -> Option<&str> {
+        symbol.map(|symbol| self.strings.resolve(symbol).as_str())
+    }
+
+    /// When the play at `index` ended, in milliseconds since the Unix epoch
+    pub fn get_ts_millis(&self, index: usize) -> i64 {
+        self.ts[index]
+    }
+
+    pub fn get_conn_country(&self, index: usize) -> Option<&str> {
+        self.resolve_string(self.conn_country[index])
+    }
+
+    pub fn get_track_name(&self, index: usize) -> Option<&str> {
+        self.resolve_string(self.master_metadata_track_name[index])
+    }
+
+    pub fn get_album_name(&self, index: usize) -> Option<&str> {
+        self.resolve_string(self.master_metadata_album_album_name[index])
+    }
+
+    pub fn get_artist_name(&self, index: usize) -> Option<&str> {
+        self.resolve_string(self.master_metadata_album_artist_name[index])
+    }
+
+    pub fn get_episode_name(&self, index: usize) -> Option<&str> {
+        self.resolve_string(self.episode_name[index])
+    }
+
+    pub fn get_episode_show_name(&self, index: usize) -> Option<&str> {
+        self.resolve_string(self.episode_show_name[index])
+    }
+
+    pub fn get_spotify_track_uri(&self, index: usize) -> Option<&str> {
+        self.resolve_string(self.spotify_track_uri[index])
+    }
+
+    pub fn get_spotify_episode_uri(&self, index: usize) -> Option<&str> {
+        self.resolve_string(self.spotify_episode_uri[index])
+    }
+
+    pub fn get_audiobook_chapter_uri(&self, index: usize) -> Option<&str> {
+        self.resolve_string(self.audiobook_chapter_uri[index])
+    }
+
+    pub fn get_ms_played(&self, index: usize) -> Option<u64> {
+        self.ms_played[index]
+    }
+
+    pub fn get_platform(&self, index: usize) -> Option<&Platform> {
+        self.platform[index].map(|symbol| self.platforms.resolve(symbol))
+    }
+
+    pub fn get_skipped(&self, index: usize) -> Option<bool> {
+        self.flags[index].get(PlayFlags::SKIPPED)
+    }
+
+    pub fn get_source(&self, index: usize) -> PlayItemSource {
+        self.source[index]
+    }
+
+    pub fn get_source_file(&self, index: usize) -> Option<&Arc<str>> {
+        self.source_file[index].map(|symbol| self.source_files.resolve(symbol))
+    }
+
+    pub fn get_ts(&self, index: usize) -> DateTime<Utc> {
+        Utc.timestamp_millis_opt(self.ts[index]).unwrap()
+    }
+
+    /// What is added to the aggregated data of a group for the play at `index`, or None if the play can't
+    /// be added to one (because it's unknown how long it lasted)
+    pub fn get_play_stats(&self, index: usize) -> Option<PlayStats<'_>> {
+        let flags = self.flags[index];
+
+        Some(PlayStats {
+            ms_played: group::get_known_ms_played(self.ms_played[index], self.source[index])?,
+            reason_start: self.reason_start[index].map(|symbol| self.reasons_start.resolve(symbol)),
+            reason_end: self.reason_end[index].map(|symbol| self.reasons_end.resolve(symbol)),
+            skipped: flags.get(PlayFlags::SKIPPED),
+            shuffle: flags.get(PlayFlags::SHUFFLE),
+            audiobook_chapter: self
+                .resolve_string(self.audiobook_chapter_uri[index])
+                .or(self.resolve_string(self.audiobook_chapter_title[index])),
+        })
+    }
+
+    /// Gets the values that decide which group the play at `index` is put into when grouping by
+    /// `group_by`, as a key that is the same for every play that ends up in the same group
+    pub fn get_group_symbol_key(
+        &self,
+        index: usize,
+        group_by: &GroupBy,
+        options: &GroupOptions,
+    ) -> GroupSymbolKey {
+        let symbols = match group_by {
+            GroupBy::Album => [
+                self.master_metadata_album_album_name[index],
+                self.master_metadata_album_artist_name[index],
+                None,
+                None,
+            ],
+            GroupBy::Artist => [
+                self.master_metadata_album_artist_name[index],
+                None,
+                None,
+                None,
+            ],
+            GroupBy::Song => [
+                self.master_metadata_track_name[index],
+                self.master_metadata_album_album_name[index],
+                self.master_metadata_album_artist_name[index],
+                self.spotify_track_uri[index],
+            ],
+            GroupBy::Podcast => [self.episode_show_name[index], None, None, None],
+            GroupBy::PodcastEpisode => [
+                self.episode_name[index],
+                self.episode_show_name[index],
+                self.spotify_episode_uri[index],
+                None,
+            ],
+            GroupBy::Audiobook => [
+                self.audiobook_title[index],
+                self.audiobook_uri[index],
+                None,
+                None,
+            ],
+            GroupBy::AudiobookChapter => [
+                self.audiobook_chapter_title[index],
+                self.audiobook_title[index],
+                self.audiobook_chapter_uri[index],
+                None,
+            ],
+            // The device is made from the platform string alone, so it only has to be normalized once for
+            // each distinct one
+            GroupBy::Platform => [self.platform[index], None, None, None],
+            GroupBy::Country => [self.conn_country[index], None, None, None],
+            GroupBy::HourOfDay
+            | GroupBy::DayOfWeek
+            | GroupBy::Month
+            | GroupBy::Year
+            | GroupBy::Days(_) => [None; 4],
+        };
+        // Only plays that are grouped by time need their local time worked out
+        let time_bucket = if group_by.is_by_time() {
+            group::get_time_bucket(&self.get_ts(index), group_by, options)
+        } else {
+            None
+        };
+
+        (self.kind[index], symbols, time_bucket)
+    }
+
+    /// Makes a PlayItem out of the play at `index`
+    pub fn get_play_item(&self, index: usize) -> PlayItem {
+        let get_string =
+            |column: &[Option<Symbol>]| self.resolve_string(column[index]).map(str::to_owned);
+        let flags = self.flags[index];
+
+        PlayItem {
+            audiobook_chapter_title: get_string(&self.audiobook_chapter_title),
+            audiobook_chapter_uri: get_string(&self.audiobook_chapter_uri),
+            audiobook_title: get_string(&self.audiobook_title),
+            audiobook_uri: get_string(&self.audiobook_uri),
+            conn_country: get_string(&self.conn_country),
+            episode_name: get_string(&self.episode_name),
+            episode_show_name: get_string(&self.episode_show_name),
+            incognito_mode: flags.get(PlayFlags::INCOGNITO_MODE),
+            ip_addr_decrypted: get_string(&self.ip_addr_decrypted),
+            master_metadata_album_album_name: get_string(&self.master_metadata_album_album_name),
+            master_metadata_album_artist_name: get_string(&self.master_metadata_album_artist_name),
+            master_metadata_track_name: get_string(&self.master_metadata_track_name),
+            ms_played: self.ms_played[index],
+            offline: flags.get(PlayFlags::OFFLINE),
+            offline_timestamp: self.offline_timestamp[index],
+            platform: self.platform[index].map(|symbol| self.platforms.resolve(symbol).clone()),
+            reason_end: self.reason_end[index]
+                .map(|symbol| self.reasons_end.resolve(symbol).clone()),
+            reason_start: self.reason_start[index]
+                .map(|symbol| self.reasons_start.resolve(symbol).clone()),
+            shuffle: flags.get(PlayFlags::SHUFFLE),
+            skipped: flags.get(PlayFlags::SKIPPED),
+            spotify_episode_uri: get_string(&self.spotify_episode_uri),
+            spotify_track_uri: get_string(&self.spotify_track_uri),
+            ts: self.get_ts(index),
+            user_agent_decrypted: get_string(&self.user_agent_decrypted),
+            username: get_string(&self.username),
+            kind: self.kind[index],
+            source: self.source[index],
+            source_file: self.source_file[index]
+                .map(|symbol| Arc::clone(self.source_files.resolve(symbol))),
+        }
+    }
+
+    /// Makes PlayItems out of the plays in the store one at a time, for the things that still need
+    /// PlayItems but don't need all of them at once
+    pub fn iter_play_items(&self) -> impl Iterator<Item = PlayItem> + '_ {
+        (0..self.len()).map(|index| self.get_play_item(index))
+    }
+}
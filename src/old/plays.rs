@@ -1,12 +1,234 @@
+use crate::{
+    play_reader::{PlayItemReader, PlayReadError},
+    privacy::PrivacyPolicy,
+};
+use chrono::prelude::*;
 use eyre::{eyre, Result};
-use serde::{Deserialize, Serialize};
-use std::path;
-use tokio::fs;
+use serde::{
+    de::{Error as _, IgnoredAny},
+    Deserialize, Serialize,
+};
+use std::{
+    fs,
+    io::{BufRead, BufReader, Cursor},
+    path,
+    str::FromStr,
+    sync::Arc,
+};
+use zip::ZipArchive;
 
-/// A struct that represents one entry of an end_song.json file. This struct represents a single "play" of
-/// a single song/podcast.
+/// The kind of media a play was of. Spotify keeps audio plays (songs, podcasts) and video plays in
+/// separate streaming history files, so this is decided by the file a play was read from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum PlayItemKind {
+    #[default]
+    Audio,
+    Video,
+}
+
+impl PlayItemKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Audio => "audio",
+            Self::Video => "video",
+        }
+    }
+}
+
+impl FromStr for PlayItemKind {
+    type Err = String;
+
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        match kind {
+            "audio" => Ok(Self::Audio),
+            "video" => Ok(Self::Video),
+            _ => Err(format!("\"{}\" is not a kind of play.", kind)),
+        }
+    }
+}
+
+/// The service that a play was exported from
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlayItemSource {
+    #[default]
+    Spotify,
+    /// A scrobble from Last.fm. Last.fm only knows when a play started and what was played, so the other
+    /// fields are either estimated from Spotify plays of the same song or left as None.
+    LastFm,
+    /// A listen from a ListenBrainz export. Listens usually know how long they lasted, but nothing about
+    /// how they started or ended.
+    ListenBrainz,
+    /// A play from the `Apple Music Play Activity.csv` file of Apple's data and privacy export
+    AppleMusic,
+}
+
+impl PlayItemSource {
+    /// The name of the source, the same as it is serialized as
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Spotify => "spotify",
+            Self::LastFm => "last_fm",
+            Self::ListenBrainz => "listen_brainz",
+            Self::AppleMusic => "apple_music",
+        }
+    }
+}
+
+impl FromStr for PlayItemSource {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        match source {
+            "spotify" => Ok(Self::Spotify),
+            "last_fm" => Ok(Self::LastFm),
+            "listen_brainz" => Ok(Self::ListenBrainz),
+            "apple_music" => Ok(Self::AppleMusic),
+            _ => Err(format!("\"{}\" is not a source of plays.", source)),
+        }
+    }
+}
+
+/// Why a play started, from the `reason_start` field of the streaming history
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum ReasonStart {
+    AppLoad,
+    Autoplay,
+    BackBtn,
+    ClickRow,
+    ClickSide,
+    FwdBtn,
+    PlayBtn,
+    Popup,
+    Remote,
+    TrackDone,
+    TrackError,
+    Unknown,
+    Other(String),
+}
+
+impl From<String> for ReasonStart {
+    fn from(reason_start: String) -> Self {
+        match reason_start.to_ascii_lowercase().as_str() {
+            "appload" => Self::AppLoad,
+            "autoplay" => Self::Autoplay,
+            "backbtn" => Self::BackBtn,
+            "clickrow" => Self::ClickRow,
+            "clickside" => Self::ClickSide,
+            "fwdbtn" => Self::FwdBtn,
+            "playbtn" => Self::PlayBtn,
+            "popup" => Self::Popup,
+            "remote" => Self::Remote,
+            "trackdone" => Self::TrackDone,
+            "trackerror" => Self::TrackError,
+            "unknown" => Self::Unknown,
+            _ => Self::Other(reason_start),
+        }
+    }
+}
+
+impl ReasonStart {
+    /// The reason as it is written in the streaming history
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::AppLoad => "appload",
+            Self::Autoplay => "autoplay",
+            Self::BackBtn => "backbtn",
+            Self::ClickRow => "clickrow",
+            Self::ClickSide => "clickside",
+            Self::FwdBtn => "fwdbtn",
+            Self::PlayBtn => "playbtn",
+            Self::Popup => "popup",
+            Self::Remote => "remote",
+            Self::TrackDone => "trackdone",
+            Self::TrackError => "trackerror",
+            Self::Unknown => "unknown",
+            Self::Other(reason_start) => reason_start,
+        }
+    }
+}
+
+/// Why a play ended, from the `reason_end` field of the streaming history
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum ReasonEnd {
+    BackBtn,
+    ClickRow,
+    EndPlay,
+    FwdBtn,
+    Logout,
+    Remote,
+    TrackDone,
+    TrackError,
+    UnexpectedExit,
+    UnexpectedExitWhilePaused,
+    Unknown,
+    Other(String),
+}
+
+impl From<String> for ReasonEnd {
+    fn from(reason_end: String) -> Self {
+        match reason_end.to_ascii_lowercase().as_str() {
+            "backbtn" => Self::BackBtn,
+            "clickrow" => Self::ClickRow,
+            "endplay" => Self::EndPlay,
+            "fwdbtn" => Self::FwdBtn,
+            "logout" => Self::Logout,
+            "remote" => Self::Remote,
+            "trackdone" => Self::TrackDone,
+            "trackerror" => Self::TrackError,
+            "unexpected-exit" => Self::UnexpectedExit,
+            "unexpected-exit-while-paused" => Self::UnexpectedExitWhilePaused,
+            "unknown" => Self::Unknown,
+            _ => Self::Other(reason_end),
+        }
+    }
+}
+
+impl ReasonEnd {
+    /// The reason as it is written in the streaming history
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::BackBtn => "backbtn",
+            Self::ClickRow => "clickrow",
+            Self::EndPlay => "endplay",
+            Self::FwdBtn => "fwdbtn",
+            Self::Logout => "logout",
+            Self::Remote => "remote",
+            Self::TrackDone => "trackdone",
+            Self::TrackError => "trackerror",
+            Self::UnexpectedExit => "unexpected-exit",
+            Self::UnexpectedExitWhilePaused => "unexpected-exit-while-paused",
+            Self::Unknown => "unknown",
+            Self::Other(reason_end) => reason_end,
+        }
+    }
+}
+
+/// The platform that a play happened on, as Spotify describes it (e.g. "Android OS 9 API 28 (samsung,
+/// SM-G960U)" or "OS X 10.15.7 [x86 8]")
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct Platform(String);
+
+impl From<String> for Platform {
+    fn from(platform: String) -> Self {
+        Self(platform)
+    }
+}
+
+impl Platform {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// A single "play" of a single song/podcast/video. The fields that come from the streaming history files
+/// are parsed into their proper types once, when the files are loaded.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PlayItem {
+    pub audiobook_chapter_title: Option<String>,
+    pub audiobook_chapter_uri: Option<String>,
+    pub audiobook_title: Option<String>,
+    pub audiobook_uri: Option<String>,
     pub conn_country: Option<String>,
     pub episode_name: Option<String>,
     pub episode_show_name: Option<String>,
@@ -18,76 +240,648 @@ pub struct PlayItem {
     pub ms_played: Option<u64>,
     pub offline: Option<bool>,
     pub offline_timestamp: Option<u64>,
-    pub platform: Option<String>,
-    pub reason_end: Option<String>,
-    pub reason_start: Option<String>,
+    pub platform: Option<Platform>,
+    pub reason_end: Option<ReasonEnd>,
+    pub reason_start: Option<ReasonStart>,
     pub shuffle: Option<bool>,
     pub skipped: Option<bool>,
     pub spotify_episode_uri: Option<String>,
     pub spotify_track_uri: Option<String>,
-    pub ts: Option<String>,
+    /// When the play ended
+    pub ts: DateTime<Utc>,
     pub user_agent_decrypted: Option<String>,
     pub username: Option<String>,
+    pub kind: PlayItemKind,
+    pub source: PlayItemSource,
+    /// The file that the play was read from. For Spotify data this is the file's whole path (or the zip
+    /// file's name followed by the file's path inside of it), since overlapping exports have files with the
+    /// same names.
+    pub source_file: Option<Arc<str>>,
+}
+
+/// A struct that represents one entry of an extended streaming history file, exactly as it's stored.
+#[derive(Clone, Debug, Deserialize)]
+struct RawPlayItem {
+    audiobook_chapter_title: Option<String>,
+    audiobook_chapter_uri: Option<String>,
+    audiobook_title: Option<String>,
+    audiobook_uri: Option<String>,
+    conn_country: Option<String>,
+    episode_name: Option<String>,
+    episode_show_name: Option<String>,
+    incognito_mode: Option<bool>,
+    ip_addr_decrypted: Option<String>,
+    master_metadata_album_album_name: Option<String>,
+    master_metadata_album_artist_name: Option<String>,
+    master_metadata_track_name: Option<String>,
+    ms_played: Option<u64>,
+    offline: Option<bool>,
+    offline_timestamp: Option<u64>,
+    platform: Option<String>,
+    reason_end: Option<String>,
+    reason_start: Option<String>,
+    shuffle: Option<bool>,
+    skipped: Option<bool>,
+    spotify_episode_uri: Option<String>,
+    spotify_track_uri: Option<String>,
+    ts: Option<String>,
+    user_agent_decrypted: Option<String>,
+    username: Option<String>,
+}
+
+impl TryFrom<RawPlayItem> for PlayItem {
+    type Error = String;
+
+    fn try_from(raw_play_item: RawPlayItem) -> Result<Self, Self::Error> {
+        let Some(ts) = raw_play_item.ts else {
+            return Err("The play has no timestamp.".to_owned());
+        };
+
+        let Ok(ts) = ts.parse::<DateTime<Utc>>() else {
+            return Err(format!("The timestamp \"{}\" is not valid.", ts));
+        };
+
+        Ok(PlayItem {
+            audiobook_chapter_title: raw_play_item.audiobook_chapter_title,
+            audiobook_chapter_uri: raw_play_item.audiobook_chapter_uri,
+            audiobook_title: raw_play_item.audiobook_title,
+            audiobook_uri: raw_play_item.audiobook_uri,
+            conn_country: raw_play_item.conn_country,
+            episode_name: raw_play_item.episode_name,
+            episode_show_name: raw_play_item.episode_show_name,
+            incognito_mode: raw_play_item.incognito_mode,
+            ip_addr_decrypted: raw_play_item.ip_addr_decrypted,
+            master_metadata_album_album_name: raw_play_item.master_metadata_album_album_name,
+            master_metadata_album_artist_name: raw_play_item.master_metadata_album_artist_name,
+            master_metadata_track_name: raw_play_item.master_metadata_track_name,
+            ms_played: raw_play_item.ms_played,
+            offline: raw_play_item.offline,
+            offline_timestamp: raw_play_item.offline_timestamp,
+            platform: raw_play_item.platform.map(Platform::from),
+            reason_end: raw_play_item.reason_end.map(ReasonEnd::from),
+            reason_start: raw_play_item.reason_start.map(ReasonStart::from),
+            shuffle: raw_play_item.shuffle,
+            skipped: raw_play_item.skipped,
+            spotify_episode_uri: raw_play_item.spotify_episode_uri,
+            spotify_track_uri: raw_play_item.spotify_track_uri,
+            ts,
+            user_agent_decrypted: raw_play_item.user_agent_decrypted,
+            username: raw_play_item.username,
+            kind: PlayItemKind::Audio,
+            source: PlayItemSource::Spotify,
+            source_file: None,
+        })
+    }
+}
+
+/// A struct that represents one entry of a StreamingHistory file from the "Account data" export. These
+/// files only hold a small part of what the extended streaming history has, so they are converted into
+/// PlayItem instances with the missing fields left as None.
+///
+/// Older exports put songs and podcast episodes in the same files using the artist and track fields,
+/// while newer exports split them into `StreamingHistory_music_N.json` and
+/// `StreamingHistory_podcast_N.json`, where podcast episodes use the podcast and episode fields.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AccountDataPlayItem {
+    end_time: String,
+    ms_played: u64,
+    artist_name: Option<String>,
+    track_name: Option<String>,
+    podcast_name: Option<String>,
+    episode_name: Option<String>,
+}
+
+impl TryFrom<AccountDataPlayItem> for PlayItem {
+    type Error = String;
+
+    fn try_from(account_data_play_item: AccountDataPlayItem) -> Result<Self, Self::Error> {
+        // End times are given in UTC to the minute, like "2023-01-05 14:03"
+        let Ok(end_time) =
+            NaiveDateTime::parse_from_str(&account_data_play_item.end_time, "%Y-%m-%d %H:%M") else {
+            return Err(format!(
+                "The end time \"{}\" is not valid.",
+                account_data_play_item.end_time
+            ));
+        };
+
+        Ok(PlayItem {
+            audiobook_chapter_title: None,
+            audiobook_chapter_uri: None,
+            audiobook_title: None,
+            audiobook_uri: None,
+            conn_country: None,
+            episode_name: account_data_play_item.episode_name,
+            episode_show_name: account_data_play_item.podcast_name,
+            incognito_mode: None,
+            ip_addr_decrypted: None,
+            master_metadata_album_album_name: None,
+            master_metadata_album_artist_name: account_data_play_item.artist_name,
+            master_metadata_track_name: account_data_play_item.track_name,
+            ms_played: Some(account_data_play_item.ms_played),
+            offline: None,
+            offline_timestamp: None,
+            platform: None,
+            reason_end: None,
+            reason_start: None,
+            shuffle: None,
+            skipped: None,
+            spotify_episode_uri: None,
+            spotify_track_uri: None,
+            ts: Utc.from_utc_datetime(&end_time),
+            user_agent_decrypted: None,
+            username: None,
+            kind: PlayItemKind::Audio,
+            source: PlayItemSource::Spotify,
+            source_file: None,
+        })
+    }
+}
+
+/// Used to tell which export a record is from without fully parsing it. Account data entries always have
+/// an `endTime`, which extended streaming history entries never have.
+#[derive(Deserialize)]
+struct RecordFormat {
+    #[serde(rename = "endTime")]
+    end_time: Option<IgnoredAny>,
+}
+
+/// How to handle records in the streaming history files that can't be read
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub enum ParseMode {
+    /// Loading fails if anything in any of the files can't be read
+    Strict,
+    /// Records that can't be read are skipped and listed in the IngestionReport, and the rest are kept
+    #[default]
+    Lenient,
+}
+
+/// Options for how plays are loaded from the streaming history files
+#[derive(Clone, Debug, Default)]
+pub struct LoadOptions {
+    pub parse_mode: ParseMode,
+    pub privacy_policy: PrivacyPolicy,
+    /// Set when plays are added to ones that are already loaded, which they have to be hashed the same way
+    /// as. A cache of plays that were hashed with another salt can't be used then.
+    pub keep_hash_salt: bool,
+}
+
+/// Progress of loading plays, sent out while the streaming history files are being read so that it can
+/// be shown to the user.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct LoadProgress {
+    pub files_done: usize,
+    pub files_total: usize,
+    pub bytes_read: u64,
+    pub bytes_total: u64,
+    pub records_parsed: u64,
+}
+
+/// How many records are parsed between each progress update
+const RECORDS_PER_PROGRESS_UPDATE: u64 = 1000;
+
+/// A record that was skipped while loading because it couldn't be read. The record index is the
+/// position of the record in its file's array, starting from 0.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SkippedRecord {
+    pub record_index: u64,
+    pub error: String,
+}
+
+/// What happened while loading a single streaming history file
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct FileIngestionReport {
+    pub file_name: String,
+    pub plays_loaded: usize,
+    pub skipped_records: Vec<SkippedRecord>,
+    /// Set if the file couldn't be read past some point. Plays read before that point are still kept,
+    /// unless the file couldn't be read at all.
+    pub error: Option<String>,
+}
+
+/// What happened while loading plays, so that problems with the data can be shown to the user instead of
+/// plays silently going missing
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct IngestionReport {
+    pub parse_mode: ParseMode,
+    /// How the sensitive fields of the plays were scrubbed, so it's known which of them are available
+    pub privacy_policy: PrivacyPolicy,
+    pub files: Vec<FileIngestionReport>,
+}
+
+impl IngestionReport {
+    pub fn new(options: &LoadOptions) -> Self {
+        IngestionReport {
+            parse_mode: options.parse_mode,
+            privacy_policy: options.privacy_policy.clone(),
+            files: Vec::new(),
+        }
+    }
 }
 
-async fn get_song_plays_from_file(file_path: &path::PathBuf) -> Result<Vec<PlayItem>> {
-    let contents = fs::read_to_string(file_path).await?;
-    let song_play_data: Vec<PlayItem> = serde_json::from_str(&contents)?;
+/// Parses the bytes of a single record (one JSON object) from any of the streaming history files
+pub fn get_play_item_from_record_bytes(
+    record_bytes: &[u8],
+    kind: PlayItemKind,
+) -> serde_json::Result<PlayItem> {
+    let format: RecordFormat = serde_json::from_slice(record_bytes)?;
+
+    let play_item = if format.end_time.is_some() {
+        PlayItem::try_from(serde_json::from_slice::<AccountDataPlayItem>(record_bytes)?)
+    } else {
+        PlayItem::try_from(serde_json::from_slice::<RawPlayItem>(record_bytes)?)
+    }
+    .map_err(serde_json::Error::custom)?;
+
+    Ok(PlayItem { kind, ..play_item })
+}
+
+fn get_song_plays_from_reader<R: BufRead>(
+    reader: R,
+    kind: PlayItemKind,
+    source_file: &str,
+    options: &LoadOptions,
+    file_report: &mut FileIngestionReport,
+    progress: &mut LoadProgress,
+    on_progress: &mut impl FnMut(&LoadProgress),
+) -> std::result::Result<Vec<PlayItem>, PlayReadError> {
+    let bytes_read_before_file = progress.bytes_read;
+    let records_parsed_before_file = progress.records_parsed;
+    // Records that are skipped don't change the count, so it's only sent when it has passed another
+    // multiple of `RECORDS_PER_PROGRESS_UPDATE` since it was last sent
+    let mut records_parsed_at_last_update = progress.records_parsed;
+
+    let lenient = options.parse_mode == ParseMode::Lenient;
+    let source_file: Arc<str> = Arc::from(source_file);
+
+    let mut play_item_reader = PlayItemReader::new(reader, kind);
+    let mut song_play_data = vec![];
+
+    while let Some(play_item) = play_item_reader.next() {
+        match play_item {
+            Ok(mut play_item) => {
+                options.privacy_policy.apply(&mut play_item);
+                play_item.source_file = Some(Arc::clone(&source_file));
+                song_play_data.push(play_item);
+            }
+            Err(PlayReadError::Record {
+                record_index,
+                error,
+            }) if lenient => {
+                file_report.skipped_records.push(SkippedRecord {
+                    record_index,
+                    error: error.to_string(),
+                });
+            }
+            // The reader stops after this, but the plays read before it are kept
+            Err(PlayReadError::Syntax(message)) if lenient => {
+                file_report.error = Some(message);
+            }
+            Err(e) => {
+                // Undo the progress from this file, since none of its plays will be kept
+                progress.bytes_read = bytes_read_before_file;
+                progress.records_parsed = records_parsed_before_file;
+                return Err(e);
+            }
+        }
+
+        progress.bytes_read = bytes_read_before_file + play_item_reader.bytes_read();
+        progress.records_parsed = records_parsed_before_file + play_item_reader.records_parsed();
+
+        if progress.records_parsed / RECORDS_PER_PROGRESS_UPDATE
+            > records_parsed_at_last_update / RECORDS_PER_PROGRESS_UPDATE
+        {
+            records_parsed_at_last_update = progress.records_parsed;
+            on_progress(progress);
+        }
+    }
+
+    // Include whatever came after the last record, like the end of the array
+    progress.bytes_read = bytes_read_before_file + play_item_reader.bytes_read();
 
     Ok(song_play_data)
 }
 
-fn get_song_history_file_paths(base_path: &path::PathBuf) -> Result<Vec<path::PathBuf>> {
-    let mut file_paths = vec![];
+/// Handles a file that none of the plays could be kept from. In strict mode this stops the loading, and in
+/// lenient mode the file is skipped and the error is added to its report.
+fn handle_failed_file(
+    error: PlayReadError,
+    options: &LoadOptions,
+    file_report: &mut FileIngestionReport,
+) -> Result<()> {
+    match options.parse_mode {
+        ParseMode::Strict => Err(eyre!("Unable to load {}. {}", file_report.file_name, error)),
+        ParseMode::Lenient => {
+            file_report.error = Some(error.to_string());
+            Ok(())
+        }
+    }
+}
 
-    let mut i = 0;
+/// Handles a record that couldn't be read from a file that isn't read with a PlayItemReader. In strict
+/// mode this stops the file from being loaded, and in lenient mode the record is skipped and added to the
+/// file's report.
+pub fn handle_failed_record(
+    record_index: usize,
+    error: String,
+    options: &LoadOptions,
+    file_report: &mut FileIngestionReport,
+) -> std::result::Result<(), String> {
+    match options.parse_mode {
+        ParseMode::Strict => Err(format!(
+            "Record {} could not be read: {}",
+            record_index, error
+        )),
+        ParseMode::Lenient => {
+            file_report.skipped_records.push(SkippedRecord {
+                record_index: record_index as u64,
+                error,
+            });
+            Ok(())
+        }
+    }
+}
 
-    loop {
-        let path_str = format!("endsong_{}.json", i);
-        let next_file_name = path::Path::new(&path_str);
-        let mut next_data_file_path = base_path.clone();
-        next_data_file_path.push(next_file_name);
+/// Parses the name of a streaming history file, returning the kind of plays it holds along with the
+/// numbers in its name (years and file index), which are used to order the files. Returns None if the
+/// file is not a streaming history file.
+///
+/// Recognized names are `endsong_N.json` from older exports, and `Streaming_History_Audio_YYYY_N.json`,
+/// `Streaming_History_Audio_YYYY-YYYY_N.json` and their `Streaming_History_Video_` counterparts from
+/// newer exports. The `_N` index is optional for the newer names. The "Account data" export's
+/// `StreamingHistoryN.json`, `StreamingHistory_music_N.json` and `StreamingHistory_podcast_N.json` are
+/// recognized as well.
+fn parse_streaming_history_file_name(file_name: &str) -> Option<(PlayItemKind, Vec<u32>)> {
+    let file_stem = file_name.strip_suffix(".json")?;
+
+    let (kind, numbers_str) = if let Some(index) = file_stem.strip_prefix("endsong_") {
+        (PlayItemKind::Audio, index)
+    } else if let Some(rest) = file_stem.strip_prefix("Streaming_History_Audio_") {
+        (PlayItemKind::Audio, rest)
+    } else if let Some(rest) = file_stem.strip_prefix("Streaming_History_Video_") {
+        (PlayItemKind::Video, rest)
+    } else if let Some(index) = file_stem.strip_prefix("StreamingHistory_music_") {
+        (PlayItemKind::Audio, index)
+    } else if let Some(index) = file_stem.strip_prefix("StreamingHistory_podcast_") {
+        (PlayItemKind::Audio, index)
+    } else if let Some(index) = file_stem.strip_prefix("StreamingHistory") {
+        (PlayItemKind::Audio, index)
+    } else {
+        return None;
+    };
 
-        if next_data_file_path.exists() {
-            file_paths.push(next_data_file_path);
-        } else {
-            break;
+    let mut numbers = vec![];
+    for number_str in numbers_str.split(['-', '_']) {
+        if number_str.is_empty() || !number_str.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
         }
+        numbers.push(number_str.parse().ok()?);
+    }
+
+    Some((kind, numbers))
+}
+
+/// Audio files come first, then each kind of file is ordered by the numbers in its name.
+fn sort_streaming_history_files<T>(files: &mut [(PlayItemKind, Vec<u32>, T)]) {
+    files.sort_by(|(a_kind, a_numbers, _), (b_kind, b_numbers, _)| {
+        (*a_kind == PlayItemKind::Video, a_numbers)
+            .cmp(&(*b_kind == PlayItemKind::Video, b_numbers))
+    });
+}
 
-        i += 1;
+pub fn get_song_history_file_paths(
+    base_path: &path::PathBuf,
+) -> Result<Vec<(path::PathBuf, PlayItemKind)>> {
+    let mut file_paths = vec![];
+
+    // Every file in the folder is checked rather than counting up from 0, since exports can have gaps
+    // in their numbering
+    for dir_entry in fs::read_dir(base_path)? {
+        let file_path = dir_entry?.path();
+
+        let Some(file_name) = file_path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+
+        if let Some((kind, numbers)) = parse_streaming_history_file_name(file_name) {
+            file_paths.push((kind, numbers, file_path));
+        }
     }
 
-    // If no files are read, return an error. Else, return the file paths
-    if i == 0 {
+    sort_streaming_history_files(&mut file_paths);
+
+    // If no files are found, return an error. Else, return the file paths
+    if file_paths.is_empty() {
         Err(eyre!(
             "Invalid folder. Could not find any streaming data files."
         ))
     } else {
-        Ok(file_paths)
+        Ok(file_paths
+            .into_iter()
+            .map(|(kind, _, file_path)| (file_path, kind))
+            .collect())
     }
 }
 
-pub async fn extract_plays_from_path(base_path: &path::PathBuf) -> Result<Vec<PlayItem>> {
+/// Extracts all of the plays from the streaming history files in a folder. Files are read one record at a
+/// time, and `on_progress` is called as they are read.
+pub fn extract_plays_from_path(
+    base_path: &path::PathBuf,
+    options: &LoadOptions,
+    mut on_progress: impl FnMut(&LoadProgress),
+) -> Result<(Vec<PlayItem>, IngestionReport)> {
     // Get all of the song history file paths
-    match get_song_history_file_paths(base_path) {
-        Err(e) => Err(e),
-        Ok(file_paths) => {
-            // Vec to hold all of the song play instances from all JSON files combined
-            let mut all_song_plays: Vec<PlayItem> = vec![];
-
-            // Extract a Vec of SongPlay instances from all of the JSON files
-            for path in file_paths.iter() {
-                let max_retries = 5;
-                for _ in 1..max_retries {
-                    if let Ok(mut single_file_song_plays) = get_song_plays_from_file(path).await {
-                        all_song_plays.append(&mut single_file_song_plays);
-                        break;
-                    }
+    let file_paths = get_song_history_file_paths(base_path)?;
+
+    let mut progress = LoadProgress {
+        files_total: file_paths.len(),
+        ..LoadProgress::default()
+    };
+    for (path, _) in file_paths.iter() {
+        progress.bytes_total += fs::metadata(path)?.len();
+    }
+    on_progress(&progress);
+
+    // Vec to hold all of the song play instances from all JSON files combined
+    let mut all_song_plays: Vec<PlayItem> = vec![];
+    let mut report = IngestionReport::new(options);
+
+    // Extract a Vec of SongPlay instances from all of the JSON files
+    for (path, kind) in file_paths.iter() {
+        let mut file_report = FileIngestionReport {
+            file_name: path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            ..FileIngestionReport::default()
+        };
+
+        let max_retries = 5;
+        for attempt in 1..max_retries {
+            file_report.skipped_records.clear();
+            file_report.error = None;
+
+            let single_file_song_plays = fs::File::open(path)
+                .map_err(PlayReadError::from)
+                .and_then(|file| {
+                    get_song_plays_from_reader(
+                        BufReader::new(file),
+                        *kind,
+                        &path.to_string_lossy(),
+                        options,
+                        &mut file_report,
+                        &mut progress,
+                        &mut on_progress,
+                    )
+                });
+
+            match single_file_song_plays {
+                Ok(mut single_file_song_plays) => {
+                    file_report.plays_loaded = single_file_song_plays.len();
+                    all_song_plays.append(&mut single_file_song_plays);
+                    break;
                 }
+                // Reading the file again can help if there was a problem with reading it, but it won't
+                // change a problem with what's in it
+                Err(PlayReadError::Io(_)) if attempt + 1 < max_retries => continue,
+                Err(e) => {
+                    handle_failed_file(e, options, &mut file_report)?;
+                    break;
+                }
+            }
+        }
+
+        report.files.push(file_report);
+
+        progress.files_done += 1;
+        on_progress(&progress);
+    }
+
+    // Return the song plays
+    Ok((all_song_plays, report))
+}
+
+/// Extracts plays straight from the zip archive that Spotify sends (either `my_spotify_data.zip` or the
+/// extended streaming history zip), without needing it to be unzipped first. The streaming history files
+/// are found by name anywhere inside the archive and read in memory. `zip_name` (e.g. the path of the zip
+/// file) is put before the path of each file inside the archive to make the plays' source files, so that
+/// plays from different zip files can be told apart.
+///
+/// This does not touch the filesystem, so it can also be used where there isn't one (e.g. the wasm build).
+pub fn extract_plays_from_zip_bytes(
+    zip_bytes: &[u8],
+    zip_name: &str,
+    options: &LoadOptions,
+    mut on_progress: impl FnMut(&LoadProgress),
+) -> Result<(Vec<PlayItem>, IngestionReport)> {
+    let mut archive = ZipArchive::new(Cursor::new(zip_bytes))?;
+
+    // Find the indices of all of the streaming history files in the archive
+    let mut entry_indices = vec![];
+    let mut progress = LoadProgress::default();
+    for i in 0..archive.len() {
+        let entry = archive.by_index(i)?;
+        if !entry.is_file() {
+            continue;
+        }
+
+        // Entries are usually inside of a folder, like "MyData/endsong_0.json"
+        let Some(file_name) = entry.name().rsplit(['/', '\\']).next() else {
+            continue;
+        };
+
+        if let Some((kind, numbers)) = parse_streaming_history_file_name(file_name) {
+            progress.bytes_total += entry.size();
+            let entry_name = entry.name().to_owned();
+            entry_indices.push((kind, numbers, (i, file_name.to_owned(), entry_name)));
+        }
+    }
+
+    sort_streaming_history_files(&mut entry_indices);
+
+    if entry_indices.is_empty() {
+        return Err(eyre!(
+            "Invalid zip file. Could not find any streaming data files."
+        ));
+    }
+
+    progress.files_total = entry_indices.len();
+    on_progress(&progress);
+
+    // Vec to hold all of the song play instances from all JSON files combined
+    let mut all_song_plays: Vec<PlayItem> = vec![];
+    let mut report = IngestionReport::new(options);
+
+    for (kind, _, (i, file_name, entry_name)) in entry_indices {
+        let mut file_report = FileIngestionReport {
+            file_name,
+            ..FileIngestionReport::default()
+        };
+
+        // The archive is already in memory, so there's nothing to gain from retrying a file
+        let single_file_song_plays = archive
+            .by_index(i)
+            .map_err(|e| PlayReadError::Io(e.into()))
+            .and_then(|entry| {
+                get_song_plays_from_reader(
+                    BufReader::new(entry),
+                    kind,
+                    &format!("{}/{}", zip_name, entry_name),
+                    options,
+                    &mut file_report,
+                    &mut progress,
+                    &mut on_progress,
+                )
+            });
+
+        match single_file_song_plays {
+            Ok(mut single_file_song_plays) => {
+                file_report.plays_loaded = single_file_song_plays.len();
+                all_song_plays.append(&mut single_file_song_plays);
             }
+            Err(e) => handle_failed_file(e, options, &mut file_report)?,
+        }
+
+        report.files.push(file_report);
+
+        progress.files_done += 1;
+        on_progress(&progress);
+    }
 
-            // Return the song plays
-            Ok(all_song_plays)
+    Ok((all_song_plays, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reason_end_round_trips_through_its_string() {
+        let reason_end_strings = [
+            "backbtn",
+            "clickrow",
+            "endplay",
+            "fwdbtn",
+            "logout",
+            "remote",
+            "trackdone",
+            "trackerror",
+            "unexpected-exit",
+            "unexpected-exit-while-paused",
+            "unknown",
+            "some-new-reason",
+        ];
+
+        for reason_end_string in reason_end_strings {
+            let reason_end = ReasonEnd::from(reason_end_string.to_owned());
+            assert_eq!(reason_end.as_str(), reason_end_string);
+            assert_eq!(ReasonEnd::from(reason_end.as_str().to_owned()), reason_end);
         }
+
+        assert_eq!(
+            ReasonEnd::from("some-new-reason".to_owned()),
+            ReasonEnd::Other("some-new-reason".to_owned())
+        );
     }
 }
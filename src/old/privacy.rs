@@ -0,0 +1,210 @@
+use crate::plays::PlayItem;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+/// What to do with a sensitive field of each play when it is loaded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldPolicy {
+    Keep,
+    Drop,
+    /// Replaced with a hash, so plays with the same value can still be told apart from others without
+    /// knowing what the value was
+    Hash,
+}
+
+/// What to do with the IP address of each play when it is loaded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IpAddrPolicy {
+    Keep,
+    Drop,
+    Hash,
+    /// Cut down to the network it belongs to, a /24 prefix for IPv4 or a /48 prefix for IPv6. Addresses
+    /// that can't be parsed are dropped.
+    Prefix,
+}
+
+/// How the sensitive fields of plays (IP address, user agent and username) are scrubbed while they are
+/// loaded, so that they never end up in memory, exports or responses to the frontend. The country that
+/// each play was streamed from is always kept.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PrivacyPolicy {
+    pub ip_addr: IpAddrPolicy,
+    pub user_agent: FieldPolicy,
+    pub username: FieldPolicy,
+    /// Mixed into every hash so that hashed values can't be found by hashing guesses, e.g. every IPv4
+    /// address. It is random for each policy, so hashes are only comparable within data loaded with the
+    /// same policy.
+    #[serde(skip)]
+    hash_salt: u64,
+}
+
+impl Default for PrivacyPolicy {
+    fn default() -> Self {
+        PrivacyPolicy::new(IpAddrPolicy::Drop, FieldPolicy::Drop, FieldPolicy::Hash)
+    }
+}
+
+impl PrivacyPolicy {
+    pub fn new(ip_addr: IpAddrPolicy, user_agent: FieldPolicy, username: FieldPolicy) -> Self {
+        PrivacyPolicy {
+            ip_addr,
+            user_agent,
+            username,
+            hash_salt: RandomState::new().build_hasher().finish(),
+        }
+    }
+
+    /// The salt that values are hashed with, so that it can be stored alongside plays that were hashed
+    /// with it
+    pub fn get_hash_salt(&self) -> u64 {
+        self.hash_salt
+    }
+
+    /// The same policy, but hashing with a stored salt so that its hashes match those of plays that were
+    /// hashed with it before
+    pub fn with_hash_salt(self, hash_salt: u64) -> Self {
+        PrivacyPolicy { hash_salt, ..self }
+    }
+
+    /// Whether any field is hashed, i.e. whether the salt makes a difference to the scrubbed plays
+    pub fn hashes_any_field(&self) -> bool {
+        self.ip_addr == IpAddrPolicy::Hash
+            || self.user_agent == FieldPolicy::Hash
+            || self.username == FieldPolicy::Hash
+    }
+
+    /// Scrubs the sensitive fields of a play according to this policy
+    pub fn apply(&self, play_item: &mut PlayItem) {
+        play_item.ip_addr_decrypted = match self.ip_addr {
+            IpAddrPolicy::Keep => play_item.ip_addr_decrypted.take(),
+            IpAddrPolicy::Drop => None,
+            IpAddrPolicy::Hash => play_item
+                .ip_addr_decrypted
+                .as_deref()
+                .map(|ip_addr| self.hash(ip_addr)),
+            IpAddrPolicy::Prefix => play_item
+                .ip_addr_decrypted
+                .as_deref()
+                .and_then(get_ip_addr_prefix),
+        };
+
+        play_item.user_agent_decrypted =
+            self.apply_field_policy(self.user_agent, play_item.user_agent_decrypted.take());
+        play_item.username = self.apply_field_policy(self.username, play_item.username.take());
+    }
+
+    fn apply_field_policy(
+        &self,
+        field_policy: FieldPolicy,
+        value: Option<String>,
+    ) -> Option<String> {
+        match field_policy {
+            FieldPolicy::Keep => value,
+            FieldPolicy::Drop => None,
+            FieldPolicy::Hash => value.map(|value| self.hash(&value)),
+        }
+    }
+
+    /// A short hex string that is the same for equal values
+    fn hash(&self, value: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.hash_salt.to_le_bytes());
+        hasher.update(value.as_bytes());
+
+        hasher.finalize()[..8]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+/// Gets the network that an IP address belongs to, e.g. "203.0.113.0/24" for "203.0.113.42"
+fn get_ip_addr_prefix(ip_addr: &str) -> Option<String> {
+    match ip_addr.trim().parse::<IpAddr>().ok()? {
+        IpAddr::V4(ip_addr) => {
+            let [a, b, c, _] = ip_addr.octets();
+            Some(format!("{}/24", Ipv4Addr::new(a, b, c, 0)))
+        }
+        IpAddr::V6(ip_addr) => {
+            let [a, b, c, ..] = ip_addr.segments();
+            Some(format!("{}/48", Ipv6Addr::new(a, b, c, 0, 0, 0, 0, 0)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plays::{self, PlayItemKind};
+
+    fn get_scrubbed_play_item(policy: &PrivacyPolicy, ip_addr: &str) -> PlayItem {
+        let record = format!(
+            r#"{{"ts": "2023-01-01T10:00:00Z", "ms_played": 1000, "ip_addr_decrypted": "{}",
+            "user_agent_decrypted": "unknown", "username": "user"}}"#,
+            ip_addr
+        );
+
+        let mut play_item =
+            plays::get_play_item_from_record_bytes(record.as_bytes(), PlayItemKind::Audio).unwrap();
+        policy.apply(&mut play_item);
+        play_item
+    }
+
+    #[test]
+    fn cuts_ip_addr_down_to_prefix() {
+        let policy = PrivacyPolicy::new(IpAddrPolicy::Prefix, FieldPolicy::Keep, FieldPolicy::Keep);
+
+        assert_eq!(
+            get_scrubbed_play_item(&policy, "203.0.113.42").ip_addr_decrypted,
+            Some("203.0.113.0/24".to_owned())
+        );
+        assert_eq!(
+            get_scrubbed_play_item(&policy, "2001:db8:85a3:8d3:1319:8a2e:370:7348")
+                .ip_addr_decrypted,
+            Some("2001:db8:85a3::/48".to_owned())
+        );
+        assert_eq!(
+            get_scrubbed_play_item(&policy, "not an address").ip_addr_decrypted,
+            None
+        );
+    }
+
+    #[test]
+    fn drops_ip_addr_and_user_agent() {
+        let policy = PrivacyPolicy::new(IpAddrPolicy::Drop, FieldPolicy::Drop, FieldPolicy::Keep);
+
+        let play_item = get_scrubbed_play_item(&policy, "203.0.113.42");
+
+        assert_eq!(play_item.ip_addr_decrypted, None);
+        assert_eq!(play_item.user_agent_decrypted, None);
+        assert_eq!(play_item.username.as_deref(), Some("user"));
+    }
+
+    #[test]
+    fn hashes_username_the_same_way_with_the_same_salt() {
+        let policy = PrivacyPolicy::new(IpAddrPolicy::Drop, FieldPolicy::Drop, FieldPolicy::Hash);
+        let same_salt_policy =
+            PrivacyPolicy::new(IpAddrPolicy::Drop, FieldPolicy::Drop, FieldPolicy::Hash)
+                .with_hash_salt(policy.get_hash_salt());
+        let other_salt_policy = policy.clone().with_hash_salt(policy.get_hash_salt() ^ 1);
+
+        let username = get_scrubbed_play_item(&policy, "").username.unwrap();
+
+        assert_ne!(username, "user");
+        assert_eq!(
+            get_scrubbed_play_item(&same_salt_policy, "").username,
+            Some(username.clone())
+        );
+        assert_ne!(
+            get_scrubbed_play_item(&other_salt_policy, "").username,
+            Some(username)
+        );
+    }
+}
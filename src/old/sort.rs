@@ -1,60 +1,87 @@
 use crate::group::Group;
+#[cfg(not(target_arch = "wasm32"))]
 use rayon::prelude::*;
+use serde::Serialize;
+use std::cmp::Ordering;
 
 /// Enum to represent the different ways that PlayGroup instances can be sorted
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum SortSpotifyDataBy {
     AutoPlayPct,
     ClickPct,
+    CompletionPct,
     PlayCount,
     ShufflePct,
     SkipPct,
+    #[serde(rename = "total_listening_time")]
     TotalListenTime,
 }
 
-pub fn sort_grouped_data(
-    grouped_data: &mut Vec<Group>,
+/// Sorts grouped data (or anything that holds a group, like the nodes of a `GroupTree`) by one of its
+/// metrics. Groups that a percentage can't be computed for (see
+/// `AggregatedData::get_unavailable_metrics`) are treated as lower than every group that it can be.
+pub fn sort_grouped_data<T: AsRef<Group> + Send>(
+    grouped_data: &mut [T],
     sort_by: SortSpotifyDataBy,
     descending: bool,
 ) {
     match sort_by {
         SortSpotifyDataBy::AutoPlayPct => {
-            grouped_data.par_sort_by(|a, b| {
-                let a_autoplay_pct = a.get_aggregated_data().get_autoplay_pct();
-                let b_autoplay_pct = b.get_aggregated_data().get_autoplay_pct();
+            sort_groups_by(grouped_data, |a, b| {
+                let a_autoplay_pct = a.as_ref().get_aggregated_data().get_autoplay_pct();
+                let b_autoplay_pct = b.as_ref().get_aggregated_data().get_autoplay_pct();
 
                 a_autoplay_pct.partial_cmp(&b_autoplay_pct).unwrap()
             });
         }
         SortSpotifyDataBy::ClickPct => {
-            grouped_data.par_sort_by(|a, b| {
-                let a_click_pct = a.get_aggregated_data().get_click_pct();
-                let b_click_pct = b.get_aggregated_data().get_click_pct();
+            sort_groups_by(grouped_data, |a, b| {
+                let a_click_pct = a.as_ref().get_aggregated_data().get_click_pct();
+                let b_click_pct = b.as_ref().get_aggregated_data().get_click_pct();
 
                 a_click_pct.partial_cmp(&b_click_pct).unwrap()
             });
         }
+        SortSpotifyDataBy::CompletionPct => {
+            sort_groups_by(grouped_data, |a, b| {
+                let a_completion_pct = a.as_ref().get_aggregated_data().get_completion_pct();
+                let b_completion_pct = b.as_ref().get_aggregated_data().get_completion_pct();
+
+                a_completion_pct.partial_cmp(&b_completion_pct).unwrap()
+            });
+        }
         SortSpotifyDataBy::PlayCount => {
-            grouped_data.par_sort_by_key(|e| e.get_aggregated_data().get_play_count());
+            sort_groups_by(grouped_data, |a, b| {
+                let a_play_count = a.as_ref().get_aggregated_data().get_play_count();
+                let b_play_count = b.as_ref().get_aggregated_data().get_play_count();
+
+                a_play_count.cmp(&b_play_count)
+            });
         }
         SortSpotifyDataBy::ShufflePct => {
-            grouped_data.par_sort_by(|a, b| {
-                let a_shuffle_pct = a.get_aggregated_data().get_shuffle_pct();
-                let b_shuffle_pct = b.get_aggregated_data().get_shuffle_pct();
+            sort_groups_by(grouped_data, |a, b| {
+                let a_shuffle_pct = a.as_ref().get_aggregated_data().get_shuffle_pct();
+                let b_shuffle_pct = b.as_ref().get_aggregated_data().get_shuffle_pct();
 
                 a_shuffle_pct.partial_cmp(&b_shuffle_pct).unwrap()
             });
         }
         SortSpotifyDataBy::SkipPct => {
-            grouped_data.par_sort_by(|a, b| {
-                let a_skip_pct = a.get_aggregated_data().get_skip_pct();
-                let b_skip_pct = b.get_aggregated_data().get_skip_pct();
+            sort_groups_by(grouped_data, |a, b| {
+                let a_skip_pct = a.as_ref().get_aggregated_data().get_skip_pct();
+                let b_skip_pct = b.as_ref().get_aggregated_data().get_skip_pct();
 
                 a_skip_pct.partial_cmp(&b_skip_pct).unwrap()
             });
         }
         SortSpotifyDataBy::TotalListenTime => {
-            grouped_data.par_sort_by_key(|e| e.get_aggregated_data().get_ms_played());
+            sort_groups_by(grouped_data, |a, b| {
+                let a_ms_played = a.as_ref().get_aggregated_data().get_ms_played();
+                let b_ms_played = b.as_ref().get_aggregated_data().get_ms_played();
+
+                a_ms_played.cmp(&b_ms_played)
+            });
         }
     };
 
@@ -62,3 +89,13 @@ pub fn sort_grouped_data(
         grouped_data.reverse();
     }
 }
+
+/// Sorts with as many threads as there are available. The wasm build has no threads, so there it sorts on
+/// the one it has. Both sorts are stable, so groups come out in the same order either way.
+fn sort_groups_by<T: Send>(items: &mut [T], compare: impl Fn(&T, &T) -> Ordering + Sync) {
+    #[cfg(not(target_arch = "wasm32"))]
+    items.par_sort_by(compare);
+
+    #[cfg(target_arch = "wasm32")]
+    items.sort_by(compare);
+}
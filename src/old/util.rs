@@ -37,3 +37,11 @@ pub fn get_total_listen_time_from_ms(listen_time_ms: u64) -> String {
         format!("{}ms", remaining_ms)
     }
 }
+
+/// Formats a percentage for display, or "N/A" if it couldn't be computed.
+pub fn get_pct_string(pct: Option<f32>) -> String {
+    match pct {
+        Some(pct) => format!("{:.2}%", pct),
+        None => "N/A".to_owned(),
+    }
+}